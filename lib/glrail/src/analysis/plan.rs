@@ -155,6 +155,7 @@ pub fn convert_usage(entity_routes :&HashMap<EntityId, Vec<usize>>,
 
         let train = planner::input::Train {
             length: vehicle.length,
+            max_velocity: vehicle.max_velocity,
             visits: visits,
         };
 
@@ -171,7 +172,7 @@ pub fn convert_usage(entity_routes :&HashMap<EntityId, Vec<usize>>,
 
         // train ids and train visits are the same
         // we simply need to drop the timing req
-        train_ord.push(planner::input::TrainOrd { a: timing.visit_a, b: timing.visit_b });
+        train_ord.push(planner::input::TrainOrd { a: timing.visit_a, b: timing.visit_b, max_time: None });
     }
 
     // TODO timing spec
@@ -25,6 +25,8 @@ pub struct TopoTrack {
     pub objects :Objects,
     pub length: f64,
     pub offset :f64,
+    /// Mileage at the A and B ends, if given in the railML file.
+    pub mileages :(Option<f64>, Option<f64>),
 }
 
 #[derive(Copy,Clone,PartialEq,Eq,Hash)]
@@ -129,6 +131,7 @@ pub struct TopoSwitchInfo {
     switch_geometry :Side,
     dir :AB,
     pos :f64,
+    mileage :Option<f64>,
 }
 
 pub fn switch_info(sw :Switch) -> Result<TopoSwitchInfo,TopoConvErr> {
@@ -153,6 +156,7 @@ pub fn switch_info(sw :Switch) -> Result<TopoSwitchInfo,TopoConvErr> {
                             deviating_side: deviating_side,
                             switch_geometry: switch_geometry,
                             pos: pos.offset,
+                            mileage: pos.mileage,
                             dir: match connection.orientation { 
                                 ConnectionOrientation::Outgoing => AB::A,
                                 ConnectionOrientation::Incoming => AB::B,
@@ -185,6 +189,7 @@ pub fn convert_railml_topo(doc :RailML) -> Result<Topological,TopoConvErr> {
                 objects: Objects::empty(),
                 offset: 0.0,
                 length: 0.0,
+                mileages: (track.begin.pos.mileage, None),
             });
 
             let mut current_offset = 0.0;
@@ -198,6 +203,7 @@ pub fn convert_railml_topo(doc :RailML) -> Result<Topological,TopoConvErr> {
                 let sw_info = switch_info(sw)?;
                 debug!("Switch info b. {:?}", sw_info);
                 topo.tracks[track_idx].length = sw_info.pos - current_offset;
+                topo.tracks[track_idx].mileages.1 = sw_info.mileage;
 
                 let nd = new_node(&mut topo, TopoNode::Switch(sw_info.switch_geometry));
                 named_node_ports.insert(sw_info.connref, (nd, sw_info.deviating_side.to_port()));
@@ -209,7 +215,8 @@ pub fn convert_railml_topo(doc :RailML) -> Result<Topological,TopoConvErr> {
                 track_idx = new_track(&mut topo, TopoTrack {
                     objects: Objects::empty(),
                     offset: sw_info.pos,
-                    length: 0.0
+                    length: 0.0,
+                    mileages: (sw_info.mileage, None),
                 });
                 topo.connections.push(((track_idx,AB::A), (nd, b_port)));
                 current_offset = sw_info.pos;
@@ -217,6 +224,7 @@ pub fn convert_railml_topo(doc :RailML) -> Result<Topological,TopoConvErr> {
 
             track_end(track.end.connection, (track_idx, AB::B), &mut topo, &mut named_track_ports);
             topo.tracks[track_idx].length = track.end.pos.offset - current_offset;
+            topo.tracks[track_idx].mileages.1 = track.end.pos.mileage;
         }
     }
 
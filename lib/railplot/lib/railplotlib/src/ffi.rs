@@ -186,6 +186,7 @@ pub extern "system" fn railplot_new() -> *mut FFISolver {
             edges: Vec::new(),
 
             main_tracks_edges :Vec::new(),
+            fixed_nodes :Vec::new(),
         }),
         solution: None,
         error_message: None,
@@ -44,7 +44,7 @@ pub fn idx_between<T>(slice :&[T], r :Result<usize,usize>) -> (usize,usize) {
     (r,r+1)
 }
 
-pub fn solve(nodes :&[Node], edges :&[Edge], symbols:&[(EdgeRef,&Symbol)], edges_lt :&[EdgePair], main_tracks :&[EdgeRef], goals :&[Goal], nodes_distinct: bool) -> Result<Output, String> {
+pub fn solve(nodes :&[Node], edges :&[Edge], symbols:&[(EdgeRef,&Symbol)], edges_lt :&[EdgePair], main_tracks :&[EdgeRef], fixed :&[(NodeRef, FixedPos)], goals :&[Goal], nodes_distinct: bool) -> Result<Output, String> {
     info!("Solving node/edge/symbols model using Levels/SAT method.");
 
     if nodes.len() == 0 { return Err(format!("No nodes specified.")); }
@@ -179,6 +179,27 @@ pub fn solve(nodes :&[Node], edges :&[Edge], symbols:&[(EdgeRef,&Symbol)], edges
         .map(|(i,_)| s.diff.named_var(Some(format!("s_x_{}",i)))).collect::<Vec<_>>();
 
     let symbol_factor = 10000isize;
+
+    // constraint: fixed node positions, relative to the first node fixed in each direction.
+    let fixed_xs = fixed.iter().filter_map(|(n,p)| p.x.map(|x| (*n,x))).collect::<Vec<_>>();
+    for (n,x) in fixed_xs.iter().skip(1) {
+        let (n0,x0) = fixed_xs[0];
+        let d = ((x - x0)*(symbol_factor as f64)).round() as isize;
+        let c1 = s.cond_constraint(symbol_node_xs[*n], symbol_node_xs[n0], d);
+        let c2 = s.cond_constraint(symbol_node_xs[n0], symbol_node_xs[*n], -d);
+        s.sat.add_clause(vec![c1]);
+        s.sat.add_clause(vec![c2]);
+    }
+    let fixed_ys = fixed.iter().filter_map(|(n,p)| p.y.map(|y| (*n,y))).collect::<Vec<_>>();
+    for (n,y) in fixed_ys.iter().skip(1) {
+        let (n0,y0) = fixed_ys[0];
+        let d = (y - y0).round() as isize;
+        let c1 = s.cond_constraint(node_ys[*n], node_ys[n0], d);
+        let c2 = s.cond_constraint(node_ys[n0], node_ys[*n], -d);
+        s.sat.add_clause(vec![c1]);
+        s.sat.add_clause(vec![c2]);
+    }
+
     //
     // symbol node x deltas
     for (i,dx) in node_delta_xs.iter().enumerate() {
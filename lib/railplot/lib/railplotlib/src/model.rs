@@ -121,6 +121,16 @@ pub struct SchematicGraph<Obj> {
     /// the optimization criterion `Goal::MainTrackHeight` will minimize 
     /// the total y coordinate range for this set of edges.
     pub main_tracks_edges: Vec<usize>,
+    /// Optional: nodes (by name) with a fixed x and/or y coordinate.
+    pub fixed_nodes: Vec<(String, FixedPos)>,
+}
+
+/// Fixed coordinates of a node (in drawing units). The drawing may still be
+/// translated, so only the distances between fixed nodes are preserved.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FixedPos {
+    pub x :Option<f64>,
+    pub y :Option<f64>,
 }
 
 pub type Pt = (f64,f64);
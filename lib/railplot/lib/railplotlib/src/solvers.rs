@@ -13,6 +13,7 @@ use log::{info,debug,trace};
 
 #[allow(unused)]
 /// Optimization goals.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Goal {
     /// Minimize the total width of the drawing (in drawing units).
     Width = 0,
@@ -97,11 +98,14 @@ impl SchematicSolver for LevelsSatSolver {
         let edges_lt : Vec<(usize,usize)> = edges_lt.into_iter().collect();
 
         let main_tracks = model.main_tracks_edges.iter().map(|x| edges_sort.apply_idx(*x)).collect::<Vec<_>>();
+        let fixed = model.fixed_nodes.iter().map(|(n,p)| {
+            node_names.get(n).map(|i| (*i,*p)).ok_or(format!("Fixed node {:?} does not exist.", n))
+        }).collect::<Result<Vec<_>,_>>()?;
 
         let nodes = model.nodes.iter().map(|n| levelssat::Node { shape: n.shape.clone(),
          pos: n.pos }).collect::<Vec<_>>();
         let levelssat::Output { node_coords, edge_levels, symbol_xs } = 
-            levelssat::solve(&nodes, &edges, &symbols, &edges_lt, &main_tracks, &self.criteria, self.nodes_distinct)?;
+            levelssat::solve(&nodes, &edges, &symbols, &edges_lt, &main_tracks, &fixed, &self.criteria, self.nodes_distinct)?;
 
         debug!("result node coodinates  {:?}", node_coords);
         debug!("result edge levels {:?}", edge_levels);
@@ -269,6 +273,7 @@ mod tests {
             nodes: nodes,
             edges: edges.into_iter().map(|(a,b)| Edge { a,b,objects:Vec::new() }).collect(),
             main_tracks_edges: vec![],
+            fixed_nodes: vec![],
         };
 
         let solver = LevelsSatSolver {
@@ -51,6 +51,8 @@ type ElementaryRoute = HashSet<PartialRouteId>;
 #[derive(Debug)]
 pub struct Train {
     pub length: f32,
+    /// Used for bounding the travel time in time-constrained orderings.
+    pub max_velocity: f32,
     pub visits: Vec<HashSet<RouteId>>,
 }
 
@@ -58,6 +60,8 @@ pub struct Train {
 pub struct TrainOrd {
     pub a :(TrainId, VisitId),
     pub b :(TrainId, VisitId),
+    /// Maximum time (in seconds) from visit a to visit b.
+    pub max_time :Option<f64>,
 }
 
 #[derive(Debug)]
@@ -11,6 +11,7 @@ pub struct SignalOptimizer {
     states :Vec<Vec<State>>,
     infrastructure :Infrastructure,
     usages :Box<[Usage]>,
+    distances :Vec<VisitDistances>,
     // current_signals :Option<HashSet<SignalId>>, // 
     last_signal_set_clause :Option<Vec<Bool>>,
     failed_states :usize,
@@ -33,12 +34,14 @@ impl SignalOptimizer {
             = all_signals.into_iter().map(|x| (x, solver.new_lit())).collect();
 
 
+        let distances = usages.iter().map(|u| visit_distances(&inf, u)).collect();
         let mut s = SignalOptimizer {
             solver,
             active_signals,
             states: (0..usages.len()).map(|_| vec![]).collect(),
             infrastructure: inf,
             usages,
+            distances,
             failed_states: 0,
             last_signal_set_clause: None,
         };
@@ -48,11 +51,25 @@ impl SignalOptimizer {
         s
     }
 
+    /// Require the signal to be present in every signal set.
+    pub fn fix_signal(&mut self, signal :SignalId) {
+        if let Some(v) = self.active_signals.get(&signal) {
+            self.solver.add_clause(vec![*v]);
+        }
+    }
+
+    /// Exclude a signal set, e.g. one which was explored in an earlier run.
+    pub fn exclude_signal_set(&mut self, signals :&HashSet<SignalId>) {
+        let clause = self.active_signals.iter()
+            .map(|(sig,v)| if signals.contains(sig) { !*v } else { *v }).collect::<Vec<_>>();
+        self.solver.add_clause(clause);
+    }
+
     pub fn add_state(&mut self) {
         for (usage_idx,usage) in self.usages.iter().enumerate() {
             let prev_state = self.states[usage_idx].last();
             let new_state = mk_state(&mut self.solver, prev_state, 
-                       &self.infrastructure, usage,
+                       &self.infrastructure, usage, &self.distances[usage_idx],
                        Some(&self.active_signals));
             self.states.get_mut(usage_idx).unwrap().push(new_state);
         }
@@ -2,6 +2,8 @@ use minisat::{*, symbolic::*};
 use std::collections::{HashMap, HashSet};
 use crate::input::*;
 use log::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
 // State management types (private)
 
@@ -27,16 +29,45 @@ pub(crate) struct TrainsState {
 pub fn plan<F : FnMut(&RoutePlan) -> bool>(config :&Config, 
                                         infrastructure :&Infrastructure,
                                         usage :&Usage,
+                                        test :F) -> Option<RoutePlan> {
+    plan_cancellable(config, infrastructure, usage, None, test)
+}
+
+/// Like `plan`, but gives up (returning `None`) when the `cancel` flag is set.
+/// The flag is checked between solver calls.
+pub fn plan_cancellable<F : FnMut(&RoutePlan) -> bool>(config :&Config, 
+                                        infrastructure :&Infrastructure,
+                                        usage :&Usage,
+                                        cancel :Option<&AtomicBool>,
+                                        test :F) -> Option<RoutePlan> {
+    plan_until(config, infrastructure, usage, cancel, None, test)
+}
+
+/// Like `plan_cancellable`, but also gives up when the `deadline` has passed.
+pub fn plan_until<F : FnMut(&RoutePlan) -> bool>(config :&Config, 
+                                        infrastructure :&Infrastructure,
+                                        usage :&Usage,
+                                        cancel :Option<&AtomicBool>,
+                                        deadline :Option<Instant>,
                                         mut test :F) -> Option<RoutePlan> {
     let mut s = Solver::new();
     let mut failed_steps = None;
 
 
     info!("Adding initial state");
+    let distances = visit_distances(infrastructure, usage);
     let mut states = Vec::new();
-    states.push(mk_state(&mut s, None, infrastructure, usage, None));
+    states.push(mk_state(&mut s, None, infrastructure, usage, &distances, None));
 
     loop {
+        if cancel.map(|c| c.load(Ordering::Relaxed)).unwrap_or(false) {
+            info!("Planning cancelled.");
+            break None;
+        }
+        if deadline.map(|d| Instant::now() >= d).unwrap_or(false) {
+            info!("Planning deadline passed.");
+            break None;
+        }
         info!("Solving with n={}.", states.len());
         if let Ok(model) = s.solve_under_assumptions(
             end_state_condition(states.last().unwrap().trains.iter().map(|(t,s)| s))) {
@@ -84,7 +115,7 @@ pub fn plan<F : FnMut(&RoutePlan) -> bool>(config :&Config,
             failed_steps = failed_steps.map(|x| x+1);
             if increase {
                 info!("Adding new state.");
-                states.push(mk_state(&mut s, states.last(), infrastructure, usage, None));
+                states.push(mk_state(&mut s, states.last(), infrastructure, usage, &distances, None));
                 continue;
             } else {
                 break None;
@@ -98,7 +129,21 @@ pub(crate) fn mk_state(s :&mut Solver,
             prev_state :Option<&State>, 
             infrastructure :&Infrastructure,
             usage :&Usage,
+            distances :&VisitDistances,
+            active_signals: Option<&HashMap<SignalId, Bool>>,
+            ) -> State {
+    mk_state_guarded(s, prev_state, infrastructure, usage, distances, active_signals, None)
+}
+
+/// Create a state where each ordering constraint only applies when
+/// its literal in `ord_enabled` is true.
+pub(crate) fn mk_state_guarded(s :&mut Solver, 
+            prev_state :Option<&State>, 
+            infrastructure :&Infrastructure,
+            usage :&Usage,
+            distances :&VisitDistances,
             active_signals: Option<&HashMap<SignalId, Bool>>,
+            ord_enabled: Option<&[Bool]>,
             ) -> State {
     use std::iter::{once};
 
@@ -455,7 +500,8 @@ pub(crate) fn mk_state(s :&mut Solver,
 
     }
     // Visits need to happen in order
-    for ord in usage.train_ord.iter() {
+    for (ord_idx,ord) in usage.train_ord.iter().enumerate() {
+        let disabled = ord_enabled.map(|e| !e[ord_idx]).unwrap_or(false.into());
         let (t1,v1) = &ord.a;
         let (t2,v2) = &ord.b;
 
@@ -469,13 +515,157 @@ pub(crate) fn mk_state(s :&mut Solver,
         let v1_future = !trains_state[t1].visit_before[*v1];
         let v2_future = !trains_state[t2].visit_before[*v2];
 
-        s.add_clause(vec![v2_future, !v1_future]);
+        s.add_clause(vec![disabled, v2_future, !v1_future]);
+
+        // With a maximum time difference, the train must be close enough
+        // to visit b when visit a has happened:
+        // !v1_future => !v2_future || near_1 || near_2 || ...
+        if let Some(max_time) = ord.max_time {
+            let train = &usage.trains[t2];
+            let max_dist = (max_time as f32) * train.max_velocity;
+            let dist = &distances[&(*t2,*v2)];
+
+            let mut clause = vec![disabled, v1_future, !v2_future];
+            for (rn,d) in dist.iter() {
+                if *d <= max_dist {
+                    clause.push(inf_state[rn].occupation.has_value(&Some(*t2)));
+                }
+            }
+            // Not born yet, but can enter and reach the visit in time.
+            let birth_dist = dist.iter()
+                .filter(|(rn,_)| infrastructure.partial_routes[rn].entry.is_boundary())
+                .map(|(rn,d)| d + infrastructure.partial_routes[rn].length)
+                .fold(std::f32::INFINITY, f32::min);
+            if birth_dist <= max_dist || train.visits[*v2].iter().any(|r| 
+                    infrastructure.partial_routes[&(*r,0)].entry.is_boundary()) {
+                clause.push(!trains_state[t2].born_before);
+            }
+            s.add_clause(clause);
+        }
     }
 
     //info!("mk_state: Trains state {:?}", trains_state);
     State { infrastructure: inf_state, trains: trains_state }
 }
 
+/// Parts of a usage which together cannot be planned.
+#[derive(Debug, Clone, Default)]
+pub struct Conflict {
+    pub trains :Vec<TrainId>,
+    pub visits :Vec<(TrainId, VisitId)>,
+    /// Indices into `Usage::train_ord`.
+    pub train_ord :Vec<usize>,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Requirement {
+    Train(TrainId),
+    Visit(TrainId, VisitId),
+    Ord(usize),
+}
+
+/// Find a minimal set of trains, visits and ordering constraints which 
+/// cannot be satisfied using `config.n_before` states. Returns `None` if
+/// the usage is satisfiable, i.e. when failures come from the simulation 
+/// or the user-supplied test, not from the planning problem itself.
+pub fn explain(config :&Config, infrastructure :&Infrastructure, usage :&Usage) -> Option<Conflict> {
+    let mut s = Solver::new();
+    let ord_enabled = usage.train_ord.iter().map(|_| s.new_lit()).collect::<Vec<_>>();
+    let distances = visit_distances(infrastructure, usage);
+    let mut states :Vec<State> = Vec::new();
+    for _ in 0..(config.n_before.max(1)) {
+        let state = mk_state_guarded(&mut s, states.last(), infrastructure, usage, &distances,
+                                     None, Some(&ord_enabled));
+        states.push(state);
+    }
+
+    // Each requirement is a group of assumption literals.
+    let last = states.last().unwrap();
+    let mut groups :Vec<(Requirement, Vec<Bool>)> = Vec::new();
+    for (train_id, ts) in last.trains.iter() {
+        let mut lits = vec![ts.born_before];
+        lits.extend(ts.progress_before.iter().map(|(_,v)| *v));
+        groups.push((Requirement::Train(*train_id), lits));
+        for (visit_id, v) in ts.visit_before.iter().enumerate() {
+            groups.push((Requirement::Visit(*train_id, visit_id), vec![*v]));
+        }
+    }
+    for (i,lit) in ord_enabled.iter().enumerate() {
+        groups.push((Requirement::Ord(i), vec![*lit]));
+    }
+
+    let solve = |s :&mut Solver, keep :&[bool]| {
+        let assumptions = groups.iter().zip(keep.iter()).filter(|(_,k)| **k)
+            .flat_map(|((_,lits),_)| lits.iter().cloned()).collect::<Vec<_>>();
+        s.solve_under_assumptions(assumptions).is_ok()
+    };
+
+    let mut keep = groups.iter().map(|_| true).collect::<Vec<_>>();
+    if solve(&mut s, &keep) { return None; }
+
+    // Deletion-based minimization: drop each requirement if the 
+    // rest is still unsatisfiable.
+    for i in 0..keep.len() {
+        keep[i] = false;
+        if solve(&mut s, &keep) { keep[i] = true; }
+    }
+
+    let mut conflict = Conflict::default();
+    for ((req,_),k) in groups.iter().zip(keep.iter()) {
+        if !k { continue; }
+        match req {
+            Requirement::Train(t) => conflict.trains.push(*t),
+            Requirement::Visit(t,v) => conflict.visits.push((*t,*v)),
+            Requirement::Ord(i) => conflict.train_ord.push(*i),
+        }
+    }
+    info!("Plan conflict {:?}", conflict);
+    Some(conflict)
+}
+
+/// Distances to each visit which has a maximum time ordering constraint,
+/// computed once per planning problem and shared by all states.
+pub(crate) type VisitDistances = HashMap<(TrainId, VisitId), HashMap<PartialRouteId, f32>>;
+
+pub(crate) fn visit_distances(infrastructure :&Infrastructure, usage :&Usage) -> VisitDistances {
+    let mut distances = HashMap::new();
+    for ord in usage.train_ord.iter().filter(|o| o.max_time.is_some()) {
+        let (t,v) = ord.b;
+        distances.entry((t,v)).or_insert_with(||
+            distance_to_visit(infrastructure, &usage.trains[&t].visits[v]));
+    }
+    distances
+}
+
+/// Shortest distance from the end of each partial route to the 
+/// entry of one of the visit's routes.
+pub(crate) fn distance_to_visit(infrastructure :&Infrastructure, 
+                                visit :&HashSet<RouteId>) -> HashMap<PartialRouteId, f32> {
+    let targets = visit.iter().filter_map(|r| infrastructure.partial_routes.get(&(*r,0)))
+        .map(|r| r.entry).collect::<HashSet<_>>();
+    let mut dist :HashMap<PartialRouteId, f32> = infrastructure.partial_routes.iter()
+        .filter(|(_,r)| targets.contains(&r.exit) && !r.exit.is_boundary())
+        .map(|(rn,_)| (*rn, 0.0)).collect();
+
+    // Bellman-Ford style relaxation over the partial route graph.
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (rn,r) in infrastructure.partial_routes.iter() {
+            if r.exit.is_boundary() { continue; }
+            let best = infrastructure.partial_routes.iter()
+                .filter(|(_,next)| next.entry == r.exit)
+                .filter_map(|(next_n,next)| dist.get(next_n).map(|d| d + next.length))
+                .fold(std::f32::INFINITY, f32::min);
+            if best < *dist.get(rn).unwrap_or(&std::f32::INFINITY) {
+                dist.insert(*rn, best);
+                changed = true;
+            }
+        }
+    }
+    dist
+}
+
 pub(crate) fn exactly_one(s :&mut Solver, v :Vec<Bool>) {
     s.assert_at_most_one(v.iter().cloned());
     s.add_clause(v);
@@ -17,7 +17,7 @@ fn trivial_model() -> (Infrastructure, Usage) {
     };
 
     let trains = Usage {
-        trains: hashmap!{ 0 => Train { length: 100.0, visits: vec![] } },
+        trains: hashmap!{ 0 => Train { length: 100.0, max_velocity: 20.0, visits: vec![] } },
         train_ord: vec![]
     };
 
@@ -41,13 +41,14 @@ fn test_basic() {
 fn too_many_states() {
     // it should be allowed to have empty states at the end of the state list
 
-    use crate::solver::{ mk_state, mk_schedule, end_state_condition, disallow_schedule };
+    use crate::solver::{ mk_state, mk_schedule, end_state_condition, disallow_schedule, visit_distances };
 
     let (inf,trains) = trivial_model();
+    let distances = visit_distances(&inf, &trains);
 
     let mut s = minisat::Solver::new();
-    let s1 = mk_state(&mut s, None,      &inf, &trains, None);
-    let s2 = mk_state(&mut s, Some(&s1), &inf, &trains, None);
+    let s1 = mk_state(&mut s, None,      &inf, &trains, &distances, None);
+    let s2 = mk_state(&mut s, Some(&s1), &inf, &trains, &distances, None);
     let states = vec![s1,s2];
 
     let model = s.solve_under_assumptions(end_state_condition(
@@ -146,12 +147,12 @@ fn test_overtake() {
 
     let trains = Usage {
         trains: hashmap!{ 
-            0 => Train { length: 100.0, visits: vec![hashset!{4}, hashset!{3}] },
-            1 => Train { length: 100.0, visits: vec![hashset!{4}, hashset!{2}] },
+            0 => Train { length: 100.0, max_velocity: 20.0, visits: vec![hashset!{4}, hashset!{3}] },
+            1 => Train { length: 100.0, max_velocity: 20.0, visits: vec![hashset!{4}, hashset!{2}] },
         },
         train_ord: vec![
-            TrainOrd { a: (0,0), b: (1,0) },
-            TrainOrd { a: (1,1), b: (0,1) },
+            TrainOrd { a: (0,0), b: (1,0), max_time: None },
+            TrainOrd { a: (1,1), b: (0,1), max_time: None },
         ]
     };
 
@@ -233,12 +234,12 @@ fn test_overtake_optimize() {
 
     let trains = Usage {
         trains: hashmap!{ 
-            0 => Train { length: 100.0, visits: vec![hashset!{4}, hashset!{3}] },
-            1 => Train { length: 100.0, visits: vec![hashset!{4}, hashset!{2}] },
+            0 => Train { length: 100.0, max_velocity: 20.0, visits: vec![hashset!{4}, hashset!{3}] },
+            1 => Train { length: 100.0, max_velocity: 20.0, visits: vec![hashset!{4}, hashset!{2}] },
         },
         train_ord: vec![
-            TrainOrd { a: (0,0), b: (1,0) },
-            TrainOrd { a: (1,1), b: (0,1) },
+            TrainOrd { a: (0,0), b: (1,0), max_time: None },
+            TrainOrd { a: (1,1), b: (0,1), max_time: None },
         ]
     };
 
@@ -260,3 +261,61 @@ fn test_overtake_optimize() {
     //    println!("step{}: {:?}", i, step);
     //}
 }
+
+/// Four routes of 1000 m in a line, where the first and the third conflict,
+/// and one train visiting the first and the last route.
+fn line_model(max_time :Option<f64>) -> (Infrastructure, Usage) {
+    let route = |entry, exit, conflicts| PartialRoute {
+        entry, exit, conflicts: vec![conflicts], wait_conflict: None, length: 1000.0 };
+    let inf = Infrastructure {
+        partial_routes: hashmap!{
+            (0,0) => route(SignalId::Boundary, SignalId::Signal(0), hashset!{((2,0),0)}),
+            (1,0) => route(SignalId::Signal(0), SignalId::Signal(1), hashset!{}),
+            (2,0) => route(SignalId::Signal(1), SignalId::Signal(2), hashset!{((0,0),0)}),
+            (3,0) => route(SignalId::Signal(2), SignalId::Boundary, hashset!{}),
+        },
+        elementary_routes: vec![
+            hashset!{ (0,0) }, hashset!{ (1,0) }, hashset!{ (2,0) }, hashset!{ (3,0) },
+        ],
+    };
+
+    let trains = Usage {
+        trains: hashmap!{ 0 => Train { length: 100.0, max_velocity: 20.0, visits: vec![hashset!{0}, hashset!{3}] } },
+        train_ord: vec![ TrainOrd { a: (0,0), b: (0,1), max_time } ],
+    };
+
+    (inf,trains)
+}
+
+#[test]
+fn max_time_unsat() {
+    let config = Config { n_before: 6, n_after: 0, exact_n :None, optimize_signals: false };
+
+    let (inf,trains) = line_model(None);
+    assert!(solver::plan(&config, &inf, &trains, |_| true).is_some());
+
+    // 10 s at 20 m/s is not enough to get from the first visit to the last,
+    // which is 2000 m away.
+    let (inf,trains) = line_model(Some(10.0));
+    assert!(solver::plan(&config, &inf, &trains, |_| true).is_none());
+}
+
+#[test]
+fn explain_blocking_visit() {
+    let config = Config { n_before: 3, n_after: 0, exact_n :None, optimize_signals: false };
+
+    let (inf,trains) = line_model(None);
+    assert!(solver::explain(&config, &inf, &trains).is_none());
+
+    // The second visit is on a route which cannot be reached.
+    let (mut inf,mut trains) = line_model(None);
+    inf.partial_routes.insert((4,0), PartialRoute { entry: SignalId::Signal(9), exit: SignalId::Boundary,
+        conflicts: vec![hashset!{}], wait_conflict: None, length: 1000.0 });
+    inf.elementary_routes.push(hashset!{ (4,0) });
+    trains.trains.get_mut(&0).unwrap().visits[1] = hashset!{4};
+
+    let conflict = solver::explain(&config, &inf, &trains).unwrap();
+    assert_eq!(conflict.visits, vec![(0,1)]);
+    assert!(conflict.trains.is_empty());
+    assert!(conflict.train_ord.is_empty());
+}
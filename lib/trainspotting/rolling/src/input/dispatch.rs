@@ -13,6 +13,8 @@ pub enum DispatchAction<RouteRef> {
     Wait(Option<f64>),
     Route(RouteRef),
     Train(TrainName, TrainParams, RouteRef), // train name, train params, entry route name
+    Couple(TrainName, TrainName), // front train name, rear train name
+    Split(TrainName, TrainName, f64), // train name, new rear train name, rear length
 }
 
 
@@ -31,6 +33,8 @@ pub enum ParseError {
 /// * wait 10.0
 /// * route rb1
 /// * train t1 (b1 -> 200.0) l=200.0 a=1.0 b=0.5 v=10.0
+/// * couple t1 t2
+/// * split t1 t3 100.0
 ///
 pub fn parse_dispatch(input: &str) -> Result<Dispatch<String>, ParseError> {
     let mut actions = Vec::new();
@@ -40,6 +44,10 @@ pub fn parse_dispatch(input: &str) -> Result<Dispatch<String>, ParseError> {
         .map_err(|e| ParseError::RegexError(format!("{:?}",e)))?;
     let route_re = Regex::new(r"^\s*route\s*([\w\.]+)\s*$")
         .map_err(|e| ParseError::RegexError(format!("{:?}",e)))?;
+    let couple_re = Regex::new(r"^\s*couple\s+(\w+)\s+(\w+)\s*$")
+        .map_err(|e| ParseError::RegexError(format!("{:?}",e)))?;
+    let split_re = Regex::new(r"^\s*split\s+(\w+)\s+(\w+)\s+([\d\.]+)\s*$")
+        .map_err(|e| ParseError::RegexError(format!("{:?}",e)))?;
     let train_re = Regex::new(r"(?x) ^ \s* train \s+ (?P<name>\w+) \s+
             l \s* = \s* (?P<len>[\d\.]+) \s+
             a \s* = \s* (?P<acc>[\d\.]+) \s+
@@ -61,6 +69,15 @@ pub fn parse_dispatch(input: &str) -> Result<Dispatch<String>, ParseError> {
             actions.push(DispatchAction::Route(groups[1].to_string()));
             continue;
         }
+        if let Some(groups) = couple_re.captures(line) {
+            actions.push(DispatchAction::Couple(groups[1].to_string(), groups[2].to_string()));
+            continue;
+        }
+        if let Some(groups) = split_re.captures(line) {
+            let length = groups[3].parse::<f64>().map_err(|_e| ParseError::NumberError)?;
+            actions.push(DispatchAction::Split(groups[1].to_string(), groups[2].to_string(), length));
+            continue;
+        }
         if let Some(groups) = train_re.captures(line) {
             actions.push(DispatchAction::Train(groups["name"].to_string(),
                                                TrainParams {
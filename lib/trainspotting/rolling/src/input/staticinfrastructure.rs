@@ -23,6 +23,7 @@ pub type NodeId = usize;
 pub type ObjectId = usize;
 
 #[derive(Debug)]
+#[derive(Serialize, Deserialize)]
 pub struct StaticInfrastructure {
     pub nodes: Vec<Node>,
     pub objects: Vec<StaticObject>,
@@ -30,6 +31,7 @@ pub struct StaticInfrastructure {
 
 
 #[derive(Debug)]
+#[derive(Serialize, Deserialize)]
 pub struct Node {
     pub other_node: NodeId,
     pub edges: Edges,
@@ -37,6 +39,7 @@ pub struct Node {
 }
 
 #[derive(Debug)]
+#[derive(Serialize, Deserialize)]
 pub enum Edges {
     Nothing,
     ModelBoundary,
@@ -45,6 +48,7 @@ pub enum Edges {
 }
 
 #[derive(Debug)]
+#[derive(Serialize, Deserialize)]
 pub enum StaticObject {
     Sight { distance: f64, signal: ObjectId},
     Signal { has_distant: bool },
@@ -61,6 +65,7 @@ pub enum StaticObject {
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Serialize, Deserialize)]
 pub enum SwitchPosition {
     Left,
     Right,
@@ -74,6 +79,7 @@ pub enum SwitchPosition {
 pub type Routes<Ref> = HashMap<Ref, Route>;
 
 #[derive(Debug,Copy,Clone,PartialEq,Eq,Hash,PartialOrd,Ord)]
+#[derive(Serialize, Deserialize)]
 pub enum RouteEntryExit {
     Boundary(Option<NodeId>),
     Signal(ObjectId),
@@ -88,6 +94,7 @@ impl RouteEntryExit {
 }
 
 #[derive(Debug,Clone)]
+#[derive(Serialize, Deserialize)]
 pub struct Route {
     pub entry: RouteEntryExit,
     pub exit: RouteEntryExit,
@@ -98,6 +105,7 @@ pub struct Route {
 }
 
 #[derive(Debug,Clone)]
+#[derive(Serialize, Deserialize)]
 pub struct RouteResources {
     pub sections: SmallVec<[ObjectId; 4]>,
     pub switch_positions: SmallVec<[(ObjectId, SwitchPosition); 2]>,
@@ -105,6 +113,7 @@ pub struct RouteResources {
 }
 
 #[derive(Debug,Clone)]
+#[derive(Serialize, Deserialize)]
 pub struct Release {
     pub trigger: ObjectId,
     pub length: f64,
@@ -113,6 +122,7 @@ pub struct Release {
 }
 
 #[derive(Debug,Clone)]
+#[derive(Serialize, Deserialize)]
 pub struct Overlap {
     pub name :Option<String>,
     pub sections: SmallVec<[ObjectId; 4]>,
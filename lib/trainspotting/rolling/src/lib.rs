@@ -4,6 +4,7 @@ extern crate ordered_float;
 extern crate regex;
 extern crate failure;
 #[macro_use] extern crate failure_derive;
+#[macro_use] extern crate serde;
 
 pub mod input;
 pub mod output;
@@ -18,6 +19,7 @@ use std::cell::RefCell;
 use output::history::InfrastructureLogEvent;
 use std::hash::Hash;
 use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 pub fn evaluate_plan<RouteRef : Hash + Eq + Debug + Clone >
                     (staticinfrastructure: &input::staticinfrastructure::StaticInfrastructure,
@@ -25,6 +27,19 @@ pub fn evaluate_plan<RouteRef : Hash + Eq + Debug + Clone >
                      routes: &HashMap<RouteRef,input::staticinfrastructure::Route>,
                      dispatch: &input::dispatch::Dispatch<RouteRef>, 
                      timestep :Option<f64>) -> output::history::History {
+    evaluate_plan_cancellable(staticinfrastructure, routes, dispatch, timestep, None)
+        .expect("simulation without cancellation")
+}
+
+/// Simulate the dispatch like `evaluate_plan`, but stop and return `None`
+/// as soon as the `cancel` flag is set.
+pub fn evaluate_plan_cancellable<RouteRef : Hash + Eq + Debug + Clone >
+                    (staticinfrastructure: &input::staticinfrastructure::StaticInfrastructure,
+                     routes: &HashMap<RouteRef,input::staticinfrastructure::Route>,
+                     dispatch: &input::dispatch::Dispatch<RouteRef>, 
+                     timestep :Option<f64>,
+                     cancel :Option<&AtomicBool>) -> Option<output::history::History> {
+    let cancelled = || cancel.map(|c| c.load(Ordering::Relaxed)).unwrap_or(false);
 
 
     let mut train_logs = Vec::new();
@@ -63,8 +78,15 @@ pub fn evaluate_plan<RouteRef : Hash + Eq + Debug + Clone >
     }
 
     let mut pending_routes = HashMap::new();
+    let mut route_requests = 0;
+    let mut drivers :Vec<railway::driver::DriverRef> = Vec::new();
+    fn find_train(logs :&[(String, railway::dynamics::TrainParams, Rc<RefCell<Vec<output::history::TrainLogEvent>>>)], 
+                  name :&str) -> Option<usize> {
+        logs.iter().rposition(|(n,_,_)| n == name)
+    }
 
     for action in &dispatch.actions {
+        if cancelled() { return None; }
         use input::dispatch::DispatchAction::*;
         match *action {
             Wait(Some(t)) => sim.advance_by(t),
@@ -84,7 +106,8 @@ pub fn evaluate_plan<RouteRef : Hash + Eq + Debug + Clone >
                     }
 
                     let activated = sim.start_process(Box::new(
-                        railway::route::ActivateRoute::new(route.clone(), conflict_events)));
+                        railway::route::ActivateRoute::new(route_requests, route.clone(), conflict_events)));
+                    route_requests += 1;
                     pending_routes.insert(route_name.clone(),activated);
                 },
                 _ => panic!("Unknown route \"{:?}\"", route_name),
@@ -104,8 +127,9 @@ pub fn evaluate_plan<RouteRef : Hash + Eq + Debug + Clone >
                             staticinfrastructure::RouteEntryExit::Boundary(Some(id)) => {
 
                                 let activated = sim.start_process(Box::new(
-                                    railway::route::ActivateRoute::new(route.clone(),
+                                    railway::route::ActivateRoute::new(route_requests, route.clone(),
                                     conflict_events)));
+                                route_requests += 1;
                                 pending_routes.insert(route_name.clone(), activated);
 
                                 (activated, id, route.length)
@@ -124,15 +148,44 @@ pub fn evaluate_plan<RouteRef : Hash + Eq + Debug + Clone >
                 });
 
                 let train_id = train_logs.len()-1;
-                let driver = Box::new(
+                let driver = Rc::new(RefCell::new(
                     railway::driver::Driver::new(&mut sim, train_id, activated, node_idx, auth_dist, 
-                          *params, logger, timestep));
-                sim.start_process(driver);
-            }
+                          *params, logger, timestep)));
+                drivers.push(driver.clone());
+                sim.start_process(Box::new(driver));
+            },
+            // Coupling and splitting is only done on standing trains,
+            // otherwise the action is ignored.
+            Couple(ref front, ref rear) => {
+                if let (Some(a), Some(b)) = (find_train(&train_logs, front), find_train(&train_logs, rear)) {
+                    if a != b {
+                        drivers[a].borrow_mut().couple(&mut sim, &mut drivers[b].borrow_mut());
+                    }
+                }
+            },
+            Split(ref name, ref rear_name, length) => {
+                if let Some(a) = find_train(&train_logs, name) {
+                    let train_log = Rc::new(RefCell::new(Vec::new()));
+                    let logger = {
+                        let train_log = train_log.clone();
+                        Box::new(move |i| train_log.borrow_mut().push(i))
+                    };
+                    let train_id = train_logs.len();
+                    let rear = drivers[a].borrow_mut().split(&mut sim, train_id, length, logger);
+                    if let Some(rear) = rear {
+                        train_logs.push((rear_name.clone(), *rear.params(), train_log));
+                        let driver = Rc::new(RefCell::new(rear));
+                        drivers.push(driver.clone());
+                        sim.start_process(Box::new(driver));
+                    }
+                }
+            },
         }
     }
 
-    sim.run();
+    while sim.step() {
+        if cancelled() { return None; }
+    }
 
 
     let h = output::history::History {
@@ -140,7 +193,7 @@ pub fn evaluate_plan<RouteRef : Hash + Eq + Debug + Clone >
         trains: train_logs.into_iter().map(|(n,p,v)| (n, p, v.replace(Vec::new()))).collect()
     };
 
-    h
+    Some(h)
 }
 
 
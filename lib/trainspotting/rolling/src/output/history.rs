@@ -4,6 +4,7 @@ use failure;
 use input::staticinfrastructure::NodeId;
 
 #[derive(Debug)]
+#[derive(Serialize, Deserialize)]
 pub struct History {
     pub inf: Vec<InfrastructureLogEvent>,
     pub trains: Vec<(String, TrainParams, Vec<TrainLogEvent>)>,
@@ -16,14 +17,16 @@ impl Default for History {
 }
 
 #[derive(Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize)]
 pub enum RouteStatus {
     Pending, Active, Released,
 }
 
 #[derive(Debug)]
+#[derive(Serialize, Deserialize)]
 pub enum InfrastructureLogEvent {
     Wait(f64),
-    Route(usize,RouteStatus), // TODO route identification is wrong?
+    Route(usize,RouteStatus), // sequence number of the route request in the dispatch
     Authority(usize, (Option<f64>, Option<f64>)), // signal objectid
     Reserved(usize, bool), // tvd objectid
     Occupied(usize, bool, NodeId, usize), // tvd objectid
@@ -31,12 +34,16 @@ pub enum InfrastructureLogEvent {
 }
 
 #[derive(Debug)]
+#[derive(Serialize, Deserialize)]
 pub enum TrainLogEvent {
     Wait(f64),
     Node(usize), // refer to nodeid
     Edge(usize, Option<usize>), // refer to two nodeid. if the second one is None then train is exiting model
     Sight(usize, bool), // has sight to signal objectid
     Move(f64, DriverAction, DistanceVelocity), 
+    Couple(usize, TrainParams), // train idx was coupled onto the rear, new params of this train
+    CoupledTo(usize), // this train was coupled onto train idx and does not continue
+    Split(usize, TrainParams), // rear part continues as train idx, new params of this train
 }
 
 /// Print one train node visits per line on the following format:
@@ -160,7 +160,7 @@ pub fn json_history<W: io::Write>(inf: &StaticInfrastructure,
         let mut first = true;
         let mut x = 0.0;
         let mut edges = Vec::new();
-        let trainlength = params.length;
+        let mut trainlength = params.length;
 
         for ev in his {
             use output::history::TrainLogEvent::*;
@@ -174,6 +174,10 @@ pub fn json_history<W: io::Write>(inf: &StaticInfrastructure,
                     edges.insert(0, ((n1, n2), (0.0, 0.0)));
                 }
                 Sight(_s, _x) => {}
+                Couple(_, p) | Split(_, p) => {
+                    trainlength = p.length;
+                }
+                CoupledTo(_) => {}
                 Move(dt, action, DistanceVelocity { dx, v }) => {
                     if first {
                         first = false;
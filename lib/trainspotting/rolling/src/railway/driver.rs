@@ -5,6 +5,8 @@ use smallvec::SmallVec;
 use super::dynamics::*;
 use output::history::TrainLogEvent;
 use super::Sim;
+use std::rc::Rc;
+use std::cell::RefCell;
 
 enum ModelContainment {
     Inside,
@@ -34,8 +36,13 @@ pub struct Driver {
     logger: Box<Fn(TrainLogEvent)>,
     activation: Activation,
     timestep: Option<f64>,
+    finished: bool,
 }
 
+/// Driver shared between the simulation and the dispatcher, so that
+/// standing trains can be coupled and split while the simulation runs.
+pub type DriverRef = Rc<RefCell<Driver>>;
+
 impl Driver {
     pub fn new(sim: &mut Sim,
                id :usize,
@@ -63,12 +70,162 @@ impl Driver {
             connected_signals: SmallVec::new(),
             logger: logger,
             activation: Activation::Wait(activated),
-            timestep: timestep
+            timestep: timestep,
+            finished: false,
         };
 
         d
     }
 
+    pub fn params(&self) -> &TrainParams {
+        &self.train.params
+    }
+
+    /// Train has entered the model and is not moving.
+    pub fn is_standing(&self) -> bool {
+        match self.activation {
+            Activation::Running => !self.finished && self.train.velocity < 1e-5,
+            _ => false,
+        }
+    }
+
+    /// Bring the log up to the current time before changing the train.
+    fn log_standing(&mut self, sim :&mut Sim) {
+        self.move_train_continuous(sim);
+        self.step = (DriverAction::Coast, *sim.time());
+    }
+
+    /// Couple the train driven by `rear` onto the rear end of this train.
+    /// The combined train has the sum of the lengths and the weaker
+    /// performance of the two. Both trains must be standing still, with the
+    /// front of `rear` on the edge leading to the node where this train's rear is.
+    /// A gap between the trains is counted as part of the combined length.
+    pub fn couple(&mut self, sim :&mut Sim, rear :&mut Driver) -> bool {
+        if !self.is_standing() || !rear.is_standing() { return false; }
+
+        // The end node of the edge where this train's rear is, and the rear's distance to it.
+        let (rear_node, rear_dist) = match self.train.under_train.first() {
+            Some((node,d)) => (Some(sim.world.statics.nodes[*node].other_node), *d),
+            None => { let (_, (end, dist)) = self.train.location; (end, dist + self.train.params.length) },
+        };
+        let (_, (rear_front_node, rear_front_dist)) = rear.train.location;
+        if rear_node.is_none() || rear_front_node != rear_node { return false; }
+        let gap = rear_front_dist - rear_dist;
+        if gap < -1e-5 { return false; }
+        let gap = gap.max(0.0);
+
+        self.log_standing(sim);
+        rear.log_standing(sim);
+
+        let (a,b) = (self.train.params, rear.train.params);
+        let params = TrainParams {
+            length: a.length + gap + b.length,
+            max_acc: a.max_acc.min(b.max_acc),
+            max_brk: a.max_brk.min(b.max_brk),
+            max_vel: a.max_vel.min(b.max_vel),
+        };
+
+        // Both trains occupy the section where the front of `rear` is,
+        // so the combined train removes one of the occupations.
+        let (rear_front_start, _) = rear.train.location;
+        if let Some(tvd) = edge_section(sim, rear_front_start, rear_front_node) {
+            sim.start_process(Box::new(DetectEvent::Exit(tvd, rear_front_start, self.id)));
+        }
+
+        // Nodes under this train are now cleared only when the rear
+        // train has also passed them. Nodes are ordered from the rear.
+        let mut under_train = rear.train.under_train.drain(..).collect::<SmallVec<[(NodeId,f64);4]>>();
+        under_train.extend(self.train.under_train.iter().map(|(n,d)| (*n, *d + gap + b.length)));
+        self.train.under_train = under_train;
+        self.train.params = params;
+
+        (self.logger)(TrainLogEvent::Couple(rear.id, params));
+        (rear.logger)(TrainLogEvent::CoupledTo(self.id));
+        rear.finished = true;
+        true
+    }
+
+    /// Split the rear `length` of this train off as a new train with 
+    /// the same performance. The new train stands still until it
+    /// gets authority from the signals in sight.
+    pub fn split(&mut self, sim :&mut Sim, id :usize, length :f64, 
+                 logger: Box<Fn(TrainLogEvent)>) -> Option<Driver> {
+        if !self.is_standing() { return None; }
+        let front_length = self.train.params.length - length;
+        if !(length > 0.0 && front_length > 0.0) { return None; }
+        self.log_standing(sim);
+
+        // Nodes which the front part has passed entirely belong to the rear part.
+        let (rear_under, front_under) :(SmallVec<[(NodeId,f64);4]>,SmallVec<[(NodeId,f64);4]>) = 
+            self.train.under_train.iter().cloned().partition(|(_,d)| *d <= length);
+
+        // Find the edge where the front of the rear part is located.
+        let (start, end, dist) = if let Some((node,d)) = rear_under.last() {
+            let (end, edge_length) = sim.world.edge_from(*node)?;
+            (*node, end, edge_length - (length - d))
+        } else if let Some((node,d)) = front_under.first() {
+            let end = sim.world.statics.nodes[*node].other_node;
+            let start = (0..sim.world.statics.nodes.len())
+                .find(|n| sim.world.edge_from(*n).map(|(e,_)| e) == Some(Some(end)))?;
+            (start, Some(end), d - length)
+        } else {
+            let (start, (end, dist)) = self.train.location;
+            (start, end, dist + front_length)
+        };
+
+        // The front of the rear part is inside a section which is also
+        // occupied by the front part, so the section gets an additional occupation.
+        if let Some(tvd) = edge_section(sim, start, end) {
+            sim.start_process(Box::new(DetectEvent::Enter(tvd, start, id)));
+        }
+
+        let mut params = self.train.params;
+        params.length = length;
+        let rear = Driver {
+            id: id,
+            train: Train {
+                location: (start, (end, dist)),
+                velocity: 0.0,
+                params: params,
+                under_train: rear_under,
+            },
+            authority: 0.0,
+            step: (DriverAction::Coast, *sim.time()),
+            connected_signals: self.connected_signals.iter()
+                .map(|(s,d)| (*s, *d + front_length)).collect(),
+            logger: logger,
+            activation: Activation::Running,
+            timestep: self.timestep,
+            finished: false,
+        };
+
+        if *sim.time() > 0.0 {
+            (rear.logger)(TrainLogEvent::Wait(*sim.time()));
+        }
+        let mut edges = rear.train.under_train.iter()
+            .filter_map(|(n,_)| sim.world.edge_from(*n).map(|(e,l)| (*n,e,l)))
+            .collect::<Vec<_>>();
+        edges.pop();
+        for (a,b,l) in edges {
+            (rear.logger)(TrainLogEvent::Edge(a,b));
+            (rear.logger)(TrainLogEvent::Move(0.0, DriverAction::Coast, 
+                                              DistanceVelocity { dx: l, v: 0.0 }));
+        }
+        let edge_length = sim.world.edge_from(start).map(|(_,l)| l).unwrap_or(dist);
+        (rear.logger)(TrainLogEvent::Edge(start,end));
+        (rear.logger)(TrainLogEvent::Move(0.0, DriverAction::Coast, 
+                                          DistanceVelocity { dx: edge_length - dist, v: 0.0 }));
+        for (s,_) in rear.connected_signals.iter() {
+            (rear.logger)(TrainLogEvent::Sight(*s, true));
+        }
+
+        self.train.params.length = front_length;
+        self.train.under_train = front_under.into_iter().map(|(n,d)| (n, d - length)).collect();
+        (self.logger)(TrainLogEvent::Split(id, self.train.params));
+
+        Some(rear)
+    }
+
     fn activate(&mut self, sim:&mut Sim) {
         if *sim.time() > 0.0 {
             (self.logger)(TrainLogEvent::Wait(*sim.time()));
@@ -258,8 +415,34 @@ impl Driver {
     }
 }
 
+/// The section containing the edge from `start` to `end`, given by the section
+/// limits at the ends of the edge, or further ahead if the edge has none.
+fn edge_section(sim :&Sim, start :NodeId, end :Option<NodeId>) -> Option<ObjectId> {
+    let statics = sim.world.statics;
+    let limit = |node :NodeId, entering :bool| statics.nodes[node].objects.iter()
+        .filter_map(|o| match statics.objects[*o] {
+            StaticObject::TVDLimit { enter, exit } => Some(if entering { enter } else { exit }),
+            _ => None,
+        }).next();
+    let (mut start, mut end) = (start, end);
+    for _ in 0..statics.nodes.len() {
+        let end_node = end?;
+        let ends = [(start, true), (statics.nodes[start].other_node, false),
+                    (end_node, true), (statics.nodes[end_node].other_node, false)];
+        for (node, entering) in ends.iter() {
+            if let Some(tvd) = limit(*node, *entering) { return tvd; }
+        }
+        start = statics.nodes[end_node].other_node;
+        end = sim.world.edge_from(start)?.0;
+    }
+    None
+}
+
 impl<'a> Process<Infrastructure<'a>> for Driver {
     fn resume(&mut self, sim: &mut Sim) -> ProcessState {
+        if self.finished {
+            return ProcessState::Finished;
+        }
         match self.activation {
             Activation::Wait(ev) => {
                 self.activation = Activation::Activate;
@@ -310,3 +493,157 @@ impl<'a> Process<Infrastructure<'a>> for Driver {
         }
     }
 }
+
+impl<'a> Process<Infrastructure<'a>> for DriverRef {
+    fn resume(&mut self, sim: &mut Sim) -> ProcessState {
+        self.borrow_mut().resume(sim)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use eventsim::{Scheduler, Simulation};
+
+    /// Two edges of 100 m, 1-2 and 3-4, in separate sections (objects 0 and 1),
+    /// with the section limit (object 2) between them at node 3, and the end of
+    /// the second section (object 3) at the boundary node 5.
+    fn line() -> StaticInfrastructure {
+        let node = |other_node, edges, objects :&[ObjectId]| Node { 
+            other_node, edges, objects: SmallVec::from_slice(objects) };
+        StaticInfrastructure {
+            nodes: vec![
+                node(1, Edges::ModelBoundary, &[]),
+                node(0, Edges::Single(2, 100.0), &[]),
+                node(3, Edges::Single(1, 100.0), &[]),
+                node(2, Edges::Single(4, 100.0), &[2]),
+                node(5, Edges::Single(3, 100.0), &[]),
+                node(4, Edges::ModelBoundary, &[3]),
+            ],
+            objects: vec![
+                StaticObject::TVDSection,
+                StaticObject::TVDSection,
+                StaticObject::TVDLimit { enter: Some(1), exit: Some(0) },
+                StaticObject::TVDLimit { enter: None, exit: Some(1) },
+            ],
+        }
+    }
+
+    fn params(length :f64, max_acc :f64, max_brk :f64, max_vel :f64) -> TrainParams {
+        TrainParams { length, max_acc, max_brk, max_vel }
+    }
+
+    /// Standing train with its front `dist` before the `end` node of the edge from `start`.
+    fn driver(sim :&mut Sim, id :usize, (start, end, dist) :(NodeId, NodeId, f64), 
+              params :TrainParams, under_train :&[(NodeId,f64)]) -> Driver {
+        let mut d = Driver::new(sim, id, 0, start, 0.0, params, Box::new(|_| {}), None);
+        d.train.location = (start, (Some(end), dist));
+        d.train.under_train = SmallVec::from_slice(under_train);
+        d.activation = Activation::Running;
+        d
+    }
+
+    fn occupied(sim :&Sim, tvd :ObjectId) -> usize {
+        match sim.world.state[tvd] {
+            ObjectState::TVDSection { ref occupied, .. } => *occupied.get(),
+            _ => panic!("Not a TVD section"),
+        }
+    }
+
+    fn sim<'a>(statics :&'a StaticInfrastructure) -> Sim<'a> {
+        let mut scheduler = Scheduler::new();
+        let world = Infrastructure::new(&mut scheduler, statics, Box::new(|_| {}));
+        Simulation::new_with_scheduler(world, scheduler)
+    }
+
+    #[test]
+    fn couple_adjacent() {
+        let statics = line();
+        let mut sim = sim(&statics);
+        // Front train is 40 m into edge 3-4, its rear 10 m before node 3.
+        let mut front = driver(&mut sim, 0, (3, 4, 60.0), params(50.0, 1.0, 0.9, 40.0), &[(3, 10.0)]);
+        // Rear train's front is right behind it.
+        let mut rear = driver(&mut sim, 1, (1, 2, 10.0), params(30.0, 0.5, 1.2, 30.0), &[]);
+        for tvd in &[0, 0, 1] { sim.start_process(Box::new(DetectEvent::Enter(*tvd, 3, 0))); }
+
+        assert!(front.couple(&mut sim, &mut rear));
+        let p = *front.params();
+        assert_eq!(p.length, 80.0);
+        assert_eq!((p.max_acc, p.max_brk, p.max_vel), (0.5, 0.9, 30.0));
+        assert!(rear.finished);
+        assert_eq!(&front.train.under_train[..], &[(3, 40.0)]);
+        assert_eq!((occupied(&sim, 0), occupied(&sim, 1)), (1, 1));
+    }
+
+    #[test]
+    fn couple_across_sections_is_ignored() {
+        let statics = line();
+        let mut sim = sim(&statics);
+        // Front train is entirely on edge 3-4.
+        let mut front = driver(&mut sim, 0, (3, 4, 60.0), params(30.0, 1.0, 1.0, 40.0), &[]);
+        let mut rear = driver(&mut sim, 1, (1, 2, 10.0), params(30.0, 1.0, 1.0, 40.0), &[]);
+
+        assert!(!front.couple(&mut sim, &mut rear));
+        assert_eq!(front.params().length, 30.0);
+        assert!(!rear.finished);
+    }
+
+    #[test]
+    fn split_at_length() {
+        let statics = line();
+        let mut sim = sim(&statics);
+        // Train is 60 m into edge 3-4, its rear 20 m before node 3.
+        let mut front = driver(&mut sim, 0, (3, 4, 40.0), params(80.0, 1.0, 1.0, 40.0), &[(3, 20.0)]);
+        for tvd in &[0, 1] { sim.start_process(Box::new(DetectEvent::Enter(*tvd, 3, 0))); }
+
+        let rear = front.split(&mut sim, 1, 30.0, Box::new(|_| {})).unwrap();
+        assert_eq!(front.params().length, 50.0);
+        assert_eq!(rear.params().length, 30.0);
+        assert!(front.train.under_train.is_empty());
+        // The rear part's front is 10 m into edge 3-4, behind the front part.
+        assert_eq!(rear.train.location, (3, (Some(4), 90.0)));
+        assert_eq!(&rear.train.under_train[..], &[(3, 20.0)]);
+        assert_eq!((occupied(&sim, 0), occupied(&sim, 1)), (1, 2));
+    }
+
+    /// Run the train out of the model through node 5.
+    fn leave(sim :&mut Sim, mut train :Driver) {
+        train.authority = 1000.0;
+        sim.start_process(Box::new(train));
+        sim.run();
+    }
+
+    #[test]
+    fn couple_on_one_edge() {
+        let statics = line();
+        let mut sim = sim(&statics);
+        // Both trains are on edge 3-4, 5 m apart.
+        let mut front = driver(&mut sim, 0, (3, 4, 20.0), params(30.0, 1.0, 1.0, 40.0), &[]);
+        let mut rear = driver(&mut sim, 1, (3, 4, 55.0), params(20.0, 1.0, 1.0, 40.0), &[]);
+        for tvd in &[1, 1] { sim.start_process(Box::new(DetectEvent::Enter(*tvd, 3, 0))); }
+
+        assert!(front.couple(&mut sim, &mut rear));
+        assert_eq!(front.params().length, 55.0);
+        assert_eq!(occupied(&sim, 1), 1);
+
+        leave(&mut sim, front);
+        assert_eq!(occupied(&sim, 1), 0);
+    }
+
+    #[test]
+    fn split_on_one_edge() {
+        let statics = line();
+        let mut sim = sim(&statics);
+        // Train is entirely on edge 3-4.
+        let mut front = driver(&mut sim, 0, (3, 4, 20.0), params(60.0, 1.0, 1.0, 40.0), &[]);
+        sim.start_process(Box::new(DetectEvent::Enter(1, 3, 0)));
+
+        let rear = front.split(&mut sim, 1, 25.0, Box::new(|_| {})).unwrap();
+        assert_eq!(rear.train.location, (3, (Some(4), 55.0)));
+        assert_eq!(occupied(&sim, 1), 2);
+
+        // The rear part still occupies the section when the front part has left.
+        leave(&mut sim, front);
+        assert_eq!(occupied(&sim, 1), 1);
+    }
+}
@@ -2,6 +2,7 @@ use smallvec::SmallVec;
 use std::f64::{NAN, INFINITY};
 
 #[derive(Copy, Clone, Debug)]
+#[derive(Serialize, Deserialize)]
 pub struct TrainParams {
     pub length: f64,
     pub max_acc: f64,
@@ -10,6 +11,7 @@ pub struct TrainParams {
 }
 
 #[derive(Copy,Clone, Debug)]
+#[derive(Serialize, Deserialize)]
 pub enum DriverAction {
     Accel,
     Brake,
@@ -17,6 +19,7 @@ pub enum DriverAction {
 }
 
 #[derive(Copy,Clone, Debug)]
+#[derive(Serialize, Deserialize)]
 pub struct DistanceVelocity {
     pub dx: f64,
     pub v: f64,
@@ -77,7 +77,7 @@ impl<'a> Process<Infrastructure<'a>> for MoveSwitch {
 
 
 #[derive(Copy, Clone)]
-enum DetectEvent {
+pub enum DetectEvent {
     Enter(ObjectId, NodeId, usize),
     Exit(ObjectId, NodeId, usize),
 }
@@ -13,21 +13,26 @@ enum ActivateRouteState {
 }
 
 pub struct ActivateRoute {
+    /// Sequence number of the route request in the dispatch, used in the log.
+    id: usize,
     route: Route,
     conditions :Vec<EventId>,
     overlap: Option<usize>,
     state: ActivateRouteState,
+    started: bool,
 }
 
 impl ActivateRoute {
-    pub fn new(r: Route, conditions :Vec<EventId>) -> Self {
+    pub fn new(id: usize, r: Route, conditions :Vec<EventId>) -> Self {
         let overlap = if r.overlaps.len() > 0 { Some(0) } else { None };
         //println!("NEW ACTIVATE ROUTE {:?} {:?}", overlap, r);
         ActivateRoute {
+            id: id,
             route: r,
             overlap: overlap,
             conditions: conditions,
             state: ActivateRouteState::Queued,
+            started: false,
         }
     }
 }
@@ -219,7 +224,10 @@ fn movable_events(r: &Route, sim: &mut Sim) -> Vec<EventId> {
 impl<'a> Process<Infrastructure<'a>> for ActivateRoute {
     fn resume(&mut self, sim: &mut Sim) -> ProcessState {
         let overlap = self.overlap.map(|i| self.route.overlaps[i].clone());
-        (sim.world.logger)(InfrastructureLogEvent::Route(0, RouteStatus::Pending)); // TODO id from where?
+        if !self.started {
+            self.started = true;
+            (sim.world.logger)(InfrastructureLogEvent::Route(self.id, RouteStatus::Pending));
+        }
 
         if let ActivateRouteState::Queued = self.state {
             while let Some(c) = self.conditions.pop() {
@@ -297,13 +305,14 @@ impl<'a> Process<Infrastructure<'a>> for ActivateRoute {
         //println!("ROUTE RELEASES: {:?}", self.route.resources.releases);
         for release in self.route.resources.releases.iter() {
             sim.start_process(Box::new(ReleaseRoute {
+                id: self.id,
                 trigger: release.trigger,
                 resources: release.resources.clone().to_vec(),
                 state: ReleaseRouteState::Start,
             }));
         }
 
-        (sim.world.logger)(InfrastructureLogEvent::Route(0, RouteStatus::Active)); // TODO id from where?
+        (sim.world.logger)(InfrastructureLogEvent::Route(self.id, RouteStatus::Active));
         ProcessState::Finished
     }
 }
@@ -429,6 +438,7 @@ enum ReleaseRouteState {
 }
 
 struct ReleaseRoute {
+    id: usize,
     trigger: ObjectId,
     resources: Vec<ObjectId>,
     state: ReleaseRouteState,
@@ -464,7 +474,7 @@ impl<'a> Process<Infrastructure<'a>> for ReleaseRoute {
                         _ => panic!("Not a resource"),
                     };;
                 }
-                (sim.world.logger)(InfrastructureLogEvent::Route(0,RouteStatus::Released)); // TODO id from where? TODO partial 
+                (sim.world.logger)(InfrastructureLogEvent::Route(self.id,RouteStatus::Released)); // TODO partial 
                 ProcessState::Finished
             }
         }
@@ -19,7 +19,7 @@ pub struct App {
 pub struct BackgroundJobs(threadpool::ThreadPool);
 
 impl BackgroundJobs {
-    pub fn new() -> Self { BackgroundJobs(threadpool::ThreadPool::new(2)) }
+    pub fn new() -> Self { BackgroundJobs(threadpool::ThreadPool::new(4)) }
 
     /// Run the given function as a background job.
     pub fn execute(&mut self, job: impl FnOnce() + Send + 'static) {
@@ -27,15 +27,30 @@ impl BackgroundJobs {
     }
 }
 
+/// Cooperative cancellation flag shared with background jobs.
+#[derive(Clone, Default)]
+pub struct CancelToken(std::sync::Arc<std::sync::atomic::AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self { Default::default() }
+    pub fn cancel(&self) { self.0.store(true, std::sync::atomic::Ordering::Relaxed); }
+    pub fn is_cancelled(&self) -> bool { self.0.load(std::sync::atomic::Ordering::Relaxed) }
+    pub fn flag(&self) -> &std::sync::atomic::AtomicBool { &self.0 }
+}
+
 pub struct Windows {
     pub config: bool,
     pub debug: bool,
     pub log: bool,
     pub quit: bool,
     pub vehicles: bool,
+    pub layout: bool,
     pub diagram_split :Option<f32>,
     pub import_window :import::ImportWindow,
     pub synthesis_window :Option<gui::windows::synthesis::SynthesisWindow>,
+    pub delays_window :Option<gui::windows::delays::DelaysWindow>,
+    pub capacity_window :Option<gui::windows::capacity::CapacityWindow>,
+    pub lint_window :Option<gui::windows::lint::LintWindow>,
 }
 
 impl Windows {
@@ -46,11 +61,15 @@ impl Windows {
             log: false,
             quit: false,
             vehicles: false,
+            layout: false,
 
             diagram_split: None,
 
             import_window: import::ImportWindow::new(bg),
             synthesis_window: None,
+            delays_window: None,
+            capacity_window: None,
+            lint_window: None,
         }
     }
 }
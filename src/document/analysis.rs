@@ -6,6 +6,8 @@ use crate::document::model::*;
 use crate::document::dgraph::*;
 use crate::document::topology;
 use crate::document::interlocking;
+use crate::document::invalidate;
+use crate::document::cache;
 use crate::document::infview::unround_coord;
 
 use crate::document::history;
@@ -14,7 +16,11 @@ use crate::util;
 use crate::util::VecMap;
 use crate::document::dispatch;
 use crate::document::plan;
+use crate::document::ars;
+use crate::document::diagnostics;
 use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use rolling::output::history::History;
 use nalgebra_glm as glm;
 
 pub type Generation = usize;
@@ -27,6 +33,14 @@ pub struct AnalysisOutput {
     pub dispatch :Vec<Option<(Generation, dispatch::DispatchOutput)>>,
     //pub plandispatches :HashMap<usize, Vec<Option<(Generation, dispatch::DispatchOutput)>>>,
     pub plandispatches :Vec<Option<(Generation, Vec<dispatch::DispatchOutput>)>>,
+    pub timetables :Vec<Option<(Generation, Result<dispatch::DispatchOutput, String>)>>,
+    /// Whether manual dispatches copied from a plan still satisfy the plan.
+    pub plan_checks :Vec<Option<(Generation, Result<(), String>)>>,
+    /// Tightest achievable time difference for each time-bounded ordering
+    /// constraint (by index into `PlanSpec::order`), when the plan has no dispatches.
+    pub plan_bounds :Vec<Option<(Generation, Vec<(usize, Option<f64>)>)>>,
+    /// Why the planner found no dispatches.
+    pub plan_explanations :Vec<Option<(Generation, plan::PlanExplanation)>>,
 }
 
 pub struct Analysis {
@@ -35,6 +49,9 @@ pub struct Analysis {
     output: AnalysisOutput,
     chan :Option<Receiver<SetData>>,
     bg :app::BackgroundJobs,
+    cancel :app::CancelToken,
+    /// Last analysed model, used to find the results invalidated by an edit.
+    analysed :Option<(Generation, Model)>,
 }
 
 #[derive(Debug)]
@@ -43,6 +60,10 @@ pub enum SetData {
     Interlocking(Generation, Arc<interlocking::Interlocking>),
     Dispatch(Generation, usize,dispatch::DispatchOutput),
     PlanDispatch(Generation, usize,Vec<dispatch::DispatchOutput>),
+    Timetable(Generation, usize, Result<dispatch::DispatchOutput, String>),
+    PlanCheck(Generation, usize, Result<(), String>),
+    PlanBounds(Generation, usize, Vec<(usize, Option<f64>)>),
+    PlanExplanation(Generation, usize, plan::PlanExplanation),
 }
 
 impl app::BackgroundUpdates for Analysis {
@@ -61,6 +82,18 @@ impl app::BackgroundUpdates for Analysis {
                         //.vecmap_insert(dispatch_idx, (g, h));
                     self.output.plandispatches.vecmap_insert(plan_idx, (g,hs));
                 },
+                SetData::Timetable(g, idx, d) => {
+                    self.output.timetables.vecmap_insert(idx, (g, d));
+                },
+                SetData::PlanCheck(g, idx, r) => {
+                    self.output.plan_checks.vecmap_insert(idx, (g, r));
+                },
+                SetData::PlanBounds(g, idx, b) => {
+                    self.output.plan_bounds.vecmap_insert(idx, (g, b));
+                },
+                SetData::PlanExplanation(g, idx, e) => {
+                    self.output.plan_explanations.vecmap_insert(idx, (g, e));
+                },
             }
         }
     }
@@ -70,6 +103,7 @@ impl Analysis {
     pub fn model(&self) -> &Model { &self.model.get() }
     pub fn data(&self) -> &AnalysisOutput { &self.output }
     pub fn generation(&self) -> &Generation { &self.model_generation }
+    pub fn background_jobs(&self) -> app::BackgroundJobs { self.bg.clone() }
 
     pub fn from_model(model :Model, bg: app::BackgroundJobs) -> Self {
         let mut a = Analysis {
@@ -78,78 +112,300 @@ impl Analysis {
             output: Default::default(),
             chan: None,
             bg: bg,
+            cancel: app::CancelToken::new(),
+            analysed: None,
         };
         a.update();
         a
     }
 
     fn update(&mut self) {
+        // Stop the jobs working on the previous model.
+        self.cancel.cancel();
+        let cancel = app::CancelToken::new();
+        self.cancel = cancel.clone();
+
         let model = self.model.get().clone(); // persistent structs
         let gen = self.model_generation;
 
-        let topology = Arc::new(topology::convert(&model, 50.0).unwrap());
+        let (prev_gen, invalid) = match &self.analysed {
+            Some((g, old)) => (Some(*g), invalidate::invalidated(old, &model)),
+            None => (None, invalidate::Invalidated::all(&model)),
+        };
+        self.analysed = Some((gen, model.clone()));
+        if let Some(prev_gen) = prev_gen { self.keep_valid_outputs(&model, &invalid, prev_gen); }
+
+        // Reuse the infrastructure analysis if it was finished and is still valid.
+        let out = &self.output;
+        let infrastructure = match (&out.topology, &out.dgraph, &out.interlocking) {
+            (Some((g1,t)), Some((g2,d)), Some((g3,il))) 
+                if !invalid.infrastructure && [g1,g2,g3].iter().all(|g| Some(**g) == prev_gen) 
+                => Some((t.clone(), d.clone(), il.clone())),
+            _ => None,
+        };
+
+        let topology = match &infrastructure {
+            Some((t,_,_)) => t.clone(),
+            None => Arc::new(topology::convert(&model, 50.0).unwrap()),
+        };
         self.output.topology = Some((gen,topology.clone()));
+        let infrastructure = infrastructure.map(|(_,d,il)| {
+            self.output.dgraph = Some((gen, d.clone()));
+            self.output.interlocking = Some((gen, il.clone()));
+            (d,il)
+        });
+
+        let out = &self.output;
+        let is_current = |g :Option<&Generation>| g == Some(&gen);
+        let dispatch_ids = model.dispatches.iter().map(|(i,_)| *i)
+            .filter(|i| !is_current(out.dispatch.vecmap_get(*i).map(|(g,_)| g))).collect::<Vec<_>>();
+        let plan_ids = model.plans.iter().map(|(i,_)| *i)
+            .filter(|i| !is_current(out.plandispatches.vecmap_get(*i).map(|(g,_)| g))).collect::<Vec<_>>();
+        let tt_ids = model.timetables.iter().map(|(i,_)| *i)
+            .filter(|i| !is_current(out.timetables.vecmap_get(*i).map(|(g,_)| g))).collect::<Vec<_>>();
 
         let (tx,rx) = channel();
         self.chan = Some(rx);
 
+        if infrastructure.is_some() && dispatch_ids.is_empty() && plan_ids.is_empty() && tt_ids.is_empty() {
+            info!("Model edit did not invalidate any analysis results");
+            return;
+        }
+
+        let mut bg = self.bg.clone();
         self.bg.execute(move || {
             info!("Background thread starting");
-            let model = model;  // move model into thread
+            let model = Arc::new(model);  // move model into thread
             let tx = tx;        // move sender into thread
 
-            //let dgraph = dgraph::calc(&model); // calc dgraph from model.
-            let dgraph = DGraphBuilder::convert(&topology).expect("dgraph conversion failed");
-            let dgraph = Arc::new(dgraph);
-
-            info!("Dgraph successful with {:?} nodes", dgraph.rolling_inf.nodes.len());
-
-            let send_ok = tx.send(SetData::DGraph(gen, dgraph.clone()));
-            if !send_ok.is_ok() { println!("job canceled after dgraph"); return; }
-            // if tx fails (channel is closed), we don't need 
-            // to proceed to next step. Also, there is no harm
-            // in *trying* to send the data from an obsolete thread,
-            // because the update function will have replaced its 
-            // receiver end of the channel, so it will anyway not
-            // be placed into the struct.
-
-            let interlocking = interlocking::calc(&dgraph); 
-            let interlocking = Arc::new(interlocking);
-                // calc interlocking from dgraph
-            let send_ok = tx.send(SetData::Interlocking(gen, interlocking.clone()));
-            if !send_ok.is_ok() { println!("job canceled after interlocking"); return; }
-            info!("Interlocking successful with {:?} routes", interlocking.routes.len());
-
-            for (i,dispatch) in model.dispatches.iter() {
-                //let history = dispatch::run(&dgraph, &interlocking, &dispatch);
-                let (history,route_refs) = history::get_history(model.vehicles.data(),
-                                                   &dgraph.rolling_inf,
-                                                   &interlocking,
-                                                   &(dispatch.commands)).unwrap();
-                info!("Simulation successful {:?}", &dispatch.commands);
-                let view = dispatch::DispatchOutput::from_history(dispatch.clone(), &dgraph, history);
-                let send_ok = tx.send(SetData::Dispatch(gen, *i, view));
-                if !send_ok.is_ok() { println!("job canceled after dispatch"); return; }
+            let (dgraph, interlocking, il_key) = match infrastructure {
+                Some((dgraph, interlocking)) => {
+                    let il_key = cache::interlocking_key(&dgraph);
+                    (dgraph, interlocking, il_key)
+                },
+                None => {
+                    //let dgraph = dgraph::calc(&model); // calc dgraph from model.
+                    let dgraph = DGraphBuilder::convert(&topology).expect("dgraph conversion failed");
+                    let dgraph = Arc::new(dgraph);
+
+                    info!("Dgraph successful with {:?} nodes", dgraph.rolling_inf.nodes.len());
+
+                    let send_ok = tx.send(SetData::DGraph(gen, dgraph.clone()));
+                    if !send_ok.is_ok() || cancel.is_cancelled() { println!("job canceled after dgraph"); return; }
+                    // if tx fails (channel is closed), we don't need 
+                    // to proceed to next step. Also, there is no harm
+                    // in *trying* to send the data from an obsolete thread,
+                    // because the update function will have replaced its 
+                    // receiver end of the channel, so it will anyway not
+                    // be placed into the struct.
+
+                    let il_key = cache::interlocking_key(&dgraph);
+                    let interlocking = cache::load::<interlocking::Interlocking>(il_key).unwrap_or_else(|| {
+                        // calc interlocking from dgraph
+                        let interlocking = interlocking::calc(&dgraph);
+                        cache::store(il_key, &interlocking);
+                        interlocking
+                    });
+                    let interlocking = Arc::new(interlocking);
+                    let send_ok = tx.send(SetData::Interlocking(gen, interlocking.clone()));
+                    if !send_ok.is_ok() || cancel.is_cancelled() { println!("job canceled after interlocking"); return; }
+                    info!("Interlocking successful with {:?} routes", interlocking.routes.len());
+                    (dgraph, interlocking, il_key)
+                },
+            };
+
+            // Dispatches, plans and timetables are independent, so each
+            // of them is computed as a separate job.
+            for i in dispatch_ids {
+                let (tx, cancel) = (tx.clone(), cancel.clone());
+                let (model, dgraph, interlocking) = (model.clone(), dgraph.clone(), interlocking.clone());
+                bg.execute(move || {
+                    if cancel.is_cancelled() { return; }
+                    let dispatch = model.dispatches.get(i).unwrap();
+                    //let history = dispatch::run(&dgraph, &interlocking, &dispatch);
+                    let (history,route_refs) = match get_history_cached(&model, &dgraph, &interlocking, il_key,
+                                                                        &dispatch.commands, cancel.flag()) {
+                        Ok(h) => h,
+                        Err(e) => {
+                            if cancel.is_cancelled() { return; }
+                            info!("Dispatch {} not simulated: {}", i, e);
+                            let mut view = dispatch::DispatchOutput::from_history(dispatch.clone(), &dgraph,
+                                                                                 Default::default());
+                            view.error = Some(e);
+                            let _ = tx.send(SetData::Dispatch(gen, i, view));
+                            return;
+                        },
+                    };
+                    info!("Simulation successful {:?}", &dispatch.commands);
+                    if let Some(origin) = dispatch.origin {
+                        let check = match model.plans.get(origin.plan) {
+                            Some(plan) => plan::eval_plan(&dgraph, plan, &history)
+                                .map_err(|e| format!("Plan is not satisfied: {}", e.message(plan))),
+                            None => Err(format!("Plan does not exist anymore")),
+                        };
+                        let send_ok = tx.send(SetData::PlanCheck(gen, i, check));
+                        if !send_ok.is_ok() { println!("job canceled after plan check"); return; }
+                    }
+                    let mut view = dispatch::DispatchOutput::from_history(dispatch.clone(), &dgraph, history);
+                    view.diagnostics = diagnostics::check(&dgraph, &interlocking, model.vehicles.data(),
+                                                          &dispatch.commands, &view.history);
+                    let send_ok = tx.send(SetData::Dispatch(gen, i, view));
+                    if !send_ok.is_ok() { println!("job canceled after dispatch"); return; }
+                });
             }
 
-            for (plan_idx,plan) in model.plans.iter() {
-                let planresults = plan::get_dispatches(&dgraph, &interlocking,
-                                             model.vehicles.data(),
-                                             plan).unwrap();
+            for plan_idx in plan_ids {
+                let (tx, cancel) = (tx.clone(), cancel.clone());
+                let (model, dgraph, interlocking) = (model.clone(), dgraph.clone(), interlocking.clone());
+                bg.execute(move || {
+                    if cancel.is_cancelled() { return; }
+                    let plan = model.plans.get(plan_idx).unwrap();
+                    let key = cache::plan_key(&model, plan);
+                    let cached = cache::load::<(Vec<Dispatch>, plan::Rejections)>(key).and_then(|(ds,rejections)| 
+                            Some((simulate_cached(&model, &dgraph, &interlocking, il_key, ds, cancel.flag())?, rejections)));
+                    let (planresults, rejections) = match cached {
+                        Some(r) => r,
+                        None => match plan::get_dispatches(&dgraph, &interlocking,
+                                                     model.vehicles.data(),
+                                                     plan, Some(cancel.flag())) {
+                            Ok(r) => {
+                                cache::store(key, &(r.0.iter().map(|(d,_)| d).collect::<Vec<_>>(), &r.1));
+                                r
+                            },
+                            Err(e) => {
+                                if cancel.is_cancelled() { return; }
+                                info!("Plan {} not finished: {}", plan_idx, e);
+                                let explanation = plan::PlanExplanation { conflict: None,
+                                    rejections: Default::default(), error: Some(e) };
+                                let _ = tx.send(SetData::PlanExplanation(gen, plan_idx, explanation));
+                                let _ = tx.send(SetData::PlanDispatch(gen, plan_idx, Vec::new()));
+                                return;
+                            },
+                        },
+                    };
+
+                    info!("Planning successful. {:?}", planresults);
+
+                    if planresults.is_empty() {
+                        let conflict = plan::plan_conflict(&dgraph, &interlocking, model.vehicles.data(), plan)
+                            .unwrap_or(None);
+                        let explanation = plan::PlanExplanation { conflict, rejections, error: None };
+                        let send_ok = tx.send(SetData::PlanExplanation(gen, plan_idx, explanation));
+                        if !send_ok.is_ok() { println!("job cancelled after plan explanation {}", plan_idx); return; }
+                    }
 
-                info!("Planning successful. {:?}", planresults);
+                    let bounded = plan.order.iter().any(|(_,_,dt)| dt.is_some());
+                    if planresults.is_empty() && bounded {
+                        if let Ok(bounds) = plan::tightest_time_differences(&dgraph, &interlocking,
+                                                                            model.vehicles.data(), plan,
+                                                                            Some(cancel.flag())) {
+                            let send_ok = tx.send(SetData::PlanBounds(gen, plan_idx, bounds));
+                            if !send_ok.is_ok() { println!("job cancelled after plan bounds {}", plan_idx); return; }
+                        }
+                    }
 
-                let dispatches = planresults.into_iter().map(|(d,h)| {
-                    dispatch::DispatchOutput::from_history(d, &dgraph, h)
-                }).collect();
+                    let dispatches = planresults.into_iter().map(|(d,h)| {
+                        dispatch::DispatchOutput::from_history(d, &dgraph, h)
+                    }).collect();
 
-                let send_ok = tx.send(SetData::PlanDispatch(gen, *plan_idx, dispatches));
-                if !send_ok.is_ok() { println!("job cancelled after plan dispatch {}", plan_idx); }
+                    let send_ok = tx.send(SetData::PlanDispatch(gen, plan_idx, dispatches));
+                    if !send_ok.is_ok() { println!("job cancelled after plan dispatch {}", plan_idx); }
+                });
+            }
+
+            for tt_idx in tt_ids {
+                let (tx, cancel) = (tx.clone(), cancel.clone());
+                let (model, dgraph, interlocking) = (model.clone(), dgraph.clone(), interlocking.clone());
+                bg.execute(move || {
+                    if cancel.is_cancelled() { return; }
+                    let timetable = model.timetables.get(tt_idx).unwrap();
+                    let key = cache::timetable_key(&model, timetable);
+                    let cached = cache::load::<Dispatch>(key).and_then(|d| 
+                            simulate_cached(&model, &dgraph, &interlocking, il_key, vec![d], cancel.flag()))
+                        .and_then(|mut r| r.pop());
+                    let result = match cached {
+                        Some(r) => Ok(r),
+                        None => ars::get_dispatch(&dgraph, &interlocking, model.vehicles.data(), timetable)
+                            .map(|r| { cache::store(key, &r.0); r }),
+                    };
+                    let result = result
+                        .map(|(d,h)| {
+                            let mut view = dispatch::DispatchOutput::from_history(d, &dgraph, h);
+                            view.diagnostics = diagnostics::check(&dgraph, &interlocking, model.vehicles.data(),
+                                                                  &view.dispatch.commands, &view.history);
+                            view
+                        });
+                    if cancel.is_cancelled() { return; }
+                    if let Err(e) = &result { warn!("Timetable {} failed: {}", tt_idx, e); }
+                    let send_ok = tx.send(SetData::Timetable(gen, tt_idx, result));
+                    if !send_ok.is_ok() { println!("job cancelled after timetable {}", tt_idx); return; }
+                });
             }
 
         });
     }
 
+    /// Move the results of the previous analysis which are not affected by
+    /// the edit to the current generation, so they are not recomputed.
+    fn keep_valid_outputs(&mut self, model :&Model, invalid :&invalidate::Invalidated, prev_gen :Generation) {
+        let gen = self.model_generation;
+        let out = &mut self.output;
+        let dispatch_ids = model.dispatches.iter().map(|(i,_)| *i)
+            .filter(|i| !invalid.dispatches.contains(i)).collect::<Vec<_>>();
+        let plan_ids = model.plans.iter().map(|(i,_)| *i)
+            .filter(|i| !invalid.plans.contains(i)).collect::<Vec<_>>();
+        let tt_ids = model.timetables.iter().map(|(i,_)| *i)
+            .filter(|i| !invalid.timetables.contains(i)).collect::<Vec<_>>();
+
+        for i in &dispatch_ids {
+            if let Some((g,view)) = out.dispatch.vecmap_get_mut(*i) {
+                if *g == prev_gen { 
+                    *g = gen; 
+                    view.dispatch = model.dispatches.get(*i).unwrap().clone();
+                }
+            }
+        }
+        retag(&mut out.plan_checks, &dispatch_ids, prev_gen, gen);
+        retag(&mut out.plandispatches, &plan_ids, prev_gen, gen);
+        retag(&mut out.plan_bounds, &plan_ids, prev_gen, gen);
+        retag(&mut out.plan_explanations, &plan_ids, prev_gen, gen);
+        for i in &tt_ids {
+            if let Some((g,result)) = out.timetables.vecmap_get_mut(*i) {
+                if *g == prev_gen {
+                    *g = gen;
+                    if let Ok(view) = result {
+                        view.dispatch.name = model.timetables.get(*i).unwrap().name.clone();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Progress of the background analysis of the current model,
+    /// as (stage name, finished, total) for each stage.
+    pub fn progress(&self) -> Vec<(&'static str, usize, usize)> {
+        let gen = self.model_generation;
+        let model = self.model();
+        let out = &self.output;
+        let is_current = |g :Option<&Generation>| g == Some(&gen);
+
+        let inf = is_current(out.dgraph.as_ref().map(|(g,_)| g)) as usize + 
+                  is_current(out.interlocking.as_ref().map(|(g,_)| g)) as usize;
+        let count = |ids :Vec<usize>, done :&dyn Fn(usize) -> bool| {
+            (ids.iter().filter(|i| done(**i)).count(), ids.len())
+        };
+        let (d_done, d_total) = count(model.dispatches.iter().map(|(i,_)| *i).collect(), 
+                                      &|i| is_current(out.dispatch.vecmap_get(i).map(|(g,_)| g)));
+        let (p_done, p_total) = count(model.plans.iter().map(|(i,_)| *i).collect(),
+                                      &|i| is_current(out.plandispatches.vecmap_get(i).map(|(g,_)| g)));
+        let (t_done, t_total) = count(model.timetables.iter().map(|(i,_)| *i).collect(),
+                                      &|i| is_current(out.timetables.vecmap_get(i).map(|(g,_)| g)));
+        vec![("Infrastructure", inf, 2),
+             ("Dispatches", d_done, d_total),
+             ("Plans", p_done, p_total),
+             ("Timetables", t_done, t_total)]
+    }
+
     pub fn edit_model(&mut self, mut f :impl FnOnce(&mut Model) -> Option<EditClass>) {
         let mut new_model = self.model.get().clone();
         let cl = f(&mut new_model);
@@ -241,3 +497,33 @@ impl Analysis {
 
 }
 
+fn retag<T>(outputs :&mut Vec<Option<(Generation,T)>>, ids :&[usize], prev_gen :Generation, gen :Generation) {
+    for i in ids {
+        if let Some((g,_)) = outputs.vecmap_get_mut(*i) {
+            if *g == prev_gen { *g = gen; }
+        }
+    }
+}
+
+/// Simulate the commands, or load the history from the result cache.
+/// `il_key` is the interlocking's `cache::interlocking_key`.
+fn get_history_cached(model :&Model, dgraph :&DGraph, il :&interlocking::Interlocking, il_key :cache::Key,
+                      commands :&[(usize,(f64,Command))], cancel :&AtomicBool) 
+    -> Result<(History, history::RouteRefs), String> {
+    let key = cache::history_key(il_key, model.vehicles.data(), commands);
+    if let Some(h) = cache::load(key) { return Ok(h); }
+    let h = history::get_history_cancellable(model.vehicles.data(), &dgraph.rolling_inf,
+                                             il, commands, Some(cancel))?;
+    cache::store(key, &h);
+    Ok(h)
+}
+
+/// Simulate dispatches loaded from the result cache. Returns `None` if any
+/// of them cannot be simulated, so that they are computed again.
+fn simulate_cached(model :&Model, dgraph :&DGraph, il :&interlocking::Interlocking, il_key :cache::Key,
+                   dispatches :Vec<Dispatch>, cancel :&AtomicBool) -> Option<Vec<(Dispatch, History)>> {
+    dispatches.into_iter().map(|d| {
+        let (history, _) = get_history_cached(model, dgraph, il, il_key, &d.commands, cancel).ok()?;
+        Some((d, history))
+    }).collect()
+}
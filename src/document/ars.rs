@@ -0,0 +1,220 @@
+//! Automatic route setting (ARS) for timetable-driven dispatches.
+//!
+//! The dispatch is built incrementally: the trains are simulated with the
+//! routes requested so far, and the next route request is taken from the
+//! time each train sights the entry signal of its next route.
+
+use ordered_float::OrderedFloat;
+use rolling::input::staticinfrastructure as rolling_inf;
+use rolling::output::history::*;
+
+use crate::document::model::*;
+use crate::document::dgraph::DGraph;
+use crate::document::interlocking::Interlocking;
+use crate::document::history;
+
+struct TrainState {
+    cmd_id :usize,
+    priority :i32,
+    routes :Vec<usize>,
+    /// Number of routes requested so far (including the entry route).
+    requested :usize,
+    last_request :f64,
+    /// Earliest time of the next request, when held back by a train with higher priority.
+    hold :f64,
+    /// Time of sighting the entry signal of the next route in the last simulated history.
+    /// Unknown after the train's own next request.
+    sight :Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate {
+    train :usize,
+    route :usize,
+    t :f64,
+    priority :i32,
+}
+
+/// The next route request among the candidates (ordered by train): first come, first
+/// served, ties broken by priority. With the priority policy, a conflicting request from a
+/// train with higher priority within the look-ahead time goes first, and the other train
+/// is held back until then. Returns the chosen request and the held back train.
+fn decide(candidates :&[Candidate], policy :ConflictPolicy, look_ahead :f64,
+          conflict :&dyn Fn(usize,usize) -> bool) -> Option<(Candidate, Option<(usize,f64)>)> {
+    let first = *candidates.iter().min_by_key(|c| (OrderedFloat(c.t), -c.priority))?;
+    if policy == ConflictPolicy::Priority {
+        let preferred = candidates.iter()
+            .filter(|c| c.priority > first.priority && c.t <= first.t + look_ahead &&
+                    conflict(c.route, first.route))
+            .min_by_key(|c| OrderedFloat(c.t));
+        if let Some(c) = preferred {
+            return Some((*c, Some((first.train, c.t))));
+        }
+    }
+    Some((first, None))
+}
+
+/// Route indices along the train's path.
+pub fn path_routes(il :&Interlocking, path :&[Ref]) -> Result<Vec<usize>, String> {
+    if path.len() < 2 {
+        return Err(format!("Train path needs an entry and at least one more location."));
+    }
+    path.windows(2).map(|w| {
+        let spec = RouteSpec { from: w[0], to: w[1], alternative: 0 };
+        il.find_route(&spec).cloned()
+            .ok_or_else(|| format!("No route from {:?} to {:?}", w[0], w[1]))
+    }).collect()
+}
+
+pub fn routes_conflict(a :&rolling_inf::Route, b :&rolling_inf::Route) -> bool {
+    a.resources.sections.iter().any(|s| b.resources.sections.contains(s)) ||
+        a.resources.switch_positions.iter().any(|(sw,_)|
+            b.resources.switch_positions.iter().any(|(sw2,_)| sw == sw2))
+}
+
+/// First time at or after `t0` when the train sights the given signal.
+fn sight_time(events :&[TrainLogEvent], signal :rolling_inf::ObjectId, t0 :f64) -> Option<f64> {
+    let mut t = 0.0;
+    for ev in events {
+        match ev {
+            TrainLogEvent::Wait(dt) | TrainLogEvent::Move(dt,_,_) => { t += dt; },
+            TrainLogEvent::Sight(s, true) if *s == signal && t >= t0 => { return Some(t); },
+            _ => {},
+        }
+    }
+    None
+}
+
+fn entry_signal(route :&rolling_inf::Route) -> Option<rolling_inf::ObjectId> {
+    match route.entry {
+        rolling_inf::RouteEntryExit::Signal(s) |
+        rolling_inf::RouteEntryExit::SignalTrigger { signal: s, .. } => Some(s),
+        rolling_inf::RouteEntryExit::Boundary(_) => None,
+    }
+}
+
+pub fn get_dispatch(dgraph :&DGraph, il :&Interlocking, vehicles :&[(usize,Vehicle)],
+                    timetable :&Timetable) -> Result<(Dispatch, History), String> {
+    let look_ahead = timetable.look_ahead.max(0.0);
+    let mut commands :Commands = Vec::new();
+    let mut trains = Vec::new();
+    for (train_id, train) in timetable.trains.iter() {
+        let routes = path_routes(il, &train.path)
+            .map_err(|e| format!("Train {}: {}", train_id, e))?;
+        let vehicle = train.vehicle.ok_or_else(|| format!("Train {}: no vehicle selected", train_id))?;
+        if !vehicles.iter().any(|(id,_)| *id == vehicle) {
+            return Err(format!("Train {}: vehicle does not exist", train_id));
+        }
+        let cmd_id = commands.len();
+        commands.push((cmd_id, (train.entry_time, Command::Train(vehicle, il.routes[routes[0]].id))));
+        trains.push(TrainState {
+            cmd_id,
+            priority: train.priority,
+            routes,
+            requested: 1,
+            last_request: train.entry_time,
+            hold: train.entry_time,
+            sight: None,
+        });
+    }
+    commands.sort_by_key(|(_,(t,_))| OrderedFloat(*t));
+    let conflict = |a :usize, b :usize| routes_conflict(&il.routes[a].route, &il.routes[b].route);
+
+    let mut t_now = 0.0f64;
+    loop {
+        let (history, _) = history::get_history(vehicles, &dgraph.rolling_inf, il, &commands)?;
+        for train in trains.iter_mut() {
+            train.sight = None;
+            let route = match train.routes.get(train.requested) { Some(r) => *r, None => continue };
+            let signal = entry_signal(&il.routes[route].route)
+                .ok_or_else(|| format!("Route {:?} does not start at a signal", il.routes[route].id))?;
+            let name = history::train_name(train.cmd_id);
+            if let Some((_,_,events)) = history.trains.iter().find(|(n,_,_)| *n == name) {
+                train.sight = sight_time(events, signal, train.last_request);
+            }
+        }
+
+        // The history before the earliest request made since the simulation is still valid.
+        // Trains without a sighting before that time may sight their signal later,
+        // but not before it. Requests are decided from this history as long as such 
+        // later sightings cannot change the decision.
+        let mut changed = std::f64::INFINITY;
+        loop {
+            let request_time = |train :&TrainState, sight :f64| 
+                (sight - look_ahead).max(train.hold).max(train.last_request).max(t_now);
+            let mut known = Vec::new();
+            let mut bounds = Vec::new();
+            for (i,train) in trains.iter().enumerate() {
+                let route = match train.routes.get(train.requested) { Some(r) => *r, None => continue };
+                match train.sight.filter(|t| *t < changed) {
+                    Some(sight) => {
+                        let c = Candidate { train: i, route, t: request_time(train, sight), priority: train.priority };
+                        known.push(c); bounds.push(c);
+                    },
+                    None if changed.is_finite() => {
+                        bounds.push(Candidate { train: i, route, t: request_time(train, changed), 
+                                                priority: train.priority });
+                    },
+                    None => {},
+                }
+            }
+
+            let decision = decide(&known, timetable.policy, look_ahead, &conflict);
+            if decision.is_none() && !changed.is_finite() {
+                return Ok((Dispatch::from_vec(timetable.name.clone(), commands), history));
+            }
+            if decision.is_none() || decide(&bounds, timetable.policy, look_ahead, &conflict) != decision {
+                break;
+            }
+            let (chosen, held) = decision.unwrap();
+            if let Some((i,t)) = held { trains[i].hold = t; }
+
+            let t = chosen.t;
+            let train = &mut trains[chosen.train];
+            train.requested += 1;
+            train.last_request = t;
+            train.sight = None;
+            t_now = t;
+            changed = changed.min(t);
+
+            // Requests at the same time are made in the order they were decided.
+            let cmd_id = commands.len();
+            let idx = commands.iter().position(|(_,(ct,_))| *ct > t).unwrap_or(commands.len());
+            commands.insert(idx, (cmd_id, (t, Command::Route(il.routes[chosen.route].id))));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(train :usize, route :usize, t :f64, priority :i32) -> Candidate {
+        Candidate { train, route, t, priority }
+    }
+
+    #[test]
+    fn priority_route_ordering() {
+        // Routes 0 and 1 conflict, route 2 conflicts with nothing.
+        let conflict = |a :usize, b :usize| a != 2 && b != 2;
+        let low = candidate(0, 0, 10.0, 0);
+        let high = candidate(1, 1, 15.0, 1);
+
+        let fcfs = ConflictPolicy::FirstComeFirstServed;
+        assert_eq!(decide(&[low, high], fcfs, 10.0, &conflict), Some((low, None)));
+
+        // The train with higher priority goes first, and the other one waits for it.
+        let prio = ConflictPolicy::Priority;
+        assert_eq!(decide(&[low, high], prio, 10.0, &conflict), Some((high, Some((0, 15.0)))));
+
+        // Not when the request is beyond the look-ahead time, or the routes do not conflict.
+        assert_eq!(decide(&[low, high], prio, 4.0, &conflict), Some((low, None)));
+        let other = candidate(1, 2, 15.0, 1);
+        assert_eq!(decide(&[low, other], prio, 10.0, &conflict), Some((low, None)));
+
+        // Simultaneous requests are ordered by priority.
+        let same = candidate(1, 1, 10.0, 1);
+        assert_eq!(decide(&[low, same], fcfs, 10.0, &conflict), Some((same, None)));
+        assert_eq!(decide(&[], prio, 10.0, &conflict), None);
+    }
+}
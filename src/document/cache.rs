@@ -0,0 +1,159 @@
+use log::*;
+use serde::{Serialize, Deserialize};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use crate::document::model::*;
+use crate::document::dgraph::DGraph;
+
+/// Results stored in the user's cache directory across sessions.  Dispatches
+/// found by the planner or the timetable ARS are keyed by the model.  The
+/// interlocking and simulated histories refer to the dgraph's node and object
+/// ids, so they are keyed by the dgraph contents instead.
+#[derive(Serialize,Deserialize)]
+struct CacheEntry<T> {
+    version :String,
+    data :T,
+}
+
+pub type Key = u64;
+
+fn hash_bytes(h :&mut DefaultHasher, x :&impl Serialize) {
+    serde_cbor::to_vec(x).unwrap_or_default().hash(h);
+}
+
+/// Hash of the infrastructure and vehicles, independent of the
+/// iteration order of the model's hash sets.
+fn hash_inputs(h :&mut DefaultHasher, model :&Model) {
+    env!("CARGO_PKG_VERSION").hash(h);
+
+    let mut linesegs = model.linesegs.iter()
+        .map(|(a,b)| ((a.x,a.y),(b.x,b.y))).collect::<Vec<_>>();
+    linesegs.sort();
+    linesegs.hash(h);
+
+    let mut objects = model.objects.iter().collect::<Vec<_>>();
+    objects.sort_by_key(|(p,_)| (p.x,p.y));
+    for (p,o) in objects { (p.x,p.y).hash(h); hash_bytes(h, o); }
+
+    let mut node_data = model.node_data.iter().collect::<Vec<_>>();
+    node_data.sort_by_key(|(p,_)| (p.x,p.y));
+    for (p,nd) in node_data { (p.x,p.y).hash(h); hash_bytes(h, nd); }
+
+    for lengths in &[&model.segment_lengths, &model.track_lengths] {
+        let mut lengths = lengths.iter().map(|((a,b),l)| (((a.x,a.y),(b.x,b.y)),*l)).collect::<Vec<_>>();
+        lengths.sort_by_key(|(seg,_)| *seg);
+        hash_bytes(h, &lengths);
+    }
+
+    hash_bytes(h, &model.vehicles);
+}
+
+pub fn plan_key(model :&Model, plan :&PlanSpec) -> Key {
+    let mut h = DefaultHasher::new();
+    hash_inputs(&mut h, model);
+    "plan".hash(&mut h);
+    hash_bytes(&mut h, &(&plan.trains, &plan.order, &plan.search));
+    h.finish()
+}
+
+pub fn timetable_key(model :&Model, timetable :&Timetable) -> Key {
+    let mut h = DefaultHasher::new();
+    hash_inputs(&mut h, model);
+    "timetable".hash(&mut h);
+    hash_bytes(&mut h, &(timetable.look_ahead, &timetable.policy, &timetable.trains));
+    h.finish()
+}
+
+/// Inputs of a signal designer run: the infrastructure, the synthesis
+/// constraints, and the enabled plans with their running time weights.
+pub fn synthesis_key(model :&Model, plans :&[PlanSpec], plan_weights :&[f64]) -> Key {
+    let mut h = DefaultHasher::new();
+    hash_inputs(&mut h, model);
+    "synthesis".hash(&mut h);
+    let mut constraints = model.synthesis_constraints.iter()
+        .map(|(p,c)| ((p.x,p.y),*c)).collect::<Vec<_>>();
+    constraints.sort_by_key(|(p,_)| *p);
+    hash_bytes(&mut h, &constraints);
+    for plan in plans {
+        hash_bytes(&mut h, &(&plan.trains, &plan.order, &plan.search));
+    }
+    hash_bytes(&mut h, &plan_weights);
+    h.finish()
+}
+
+/// Interlocking computed from the dgraph's infrastructure, boundary nodes and signals.
+pub fn interlocking_key(dgraph :&DGraph) -> Key {
+    let mut h = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut h);
+    "interlocking".hash(&mut h);
+    hash_bytes(&mut h, &dgraph.rolling_inf);
+    let mut node_ids = dgraph.node_ids.iter().map(|(n,p)| (*n,(p.x,p.y))).collect::<Vec<_>>();
+    node_ids.sort();
+    node_ids.hash(&mut h);
+    let mut object_ids = dgraph.object_ids.iter().map(|(o,p)| (*o,(p.x,p.y))).collect::<Vec<_>>();
+    object_ids.sort();
+    object_ids.hash(&mut h);
+    h.finish()
+}
+
+/// Simulation of the commands on the interlocking with the given key.
+pub fn history_key(interlocking :Key, vehicles :&[(usize,Vehicle)], commands :&[(usize,(f64,Command))]) -> Key {
+    let mut h = DefaultHasher::new();
+    interlocking.hash(&mut h);
+    "history".hash(&mut h);
+    hash_bytes(&mut h, &(vehicles, commands));
+    h.finish()
+}
+
+const MAX_ENTRIES :usize = 500;
+
+fn cache_file(key :Key) -> Option<PathBuf> {
+    let mut path = dirs::cache_dir()?;
+    path.push(env!("CARGO_PKG_NAME"));
+    path.push(format!("{:016x}.cbor", key));
+    Some(path)
+}
+
+pub fn load<T :for<'de> Deserialize<'de>>(key :Key) -> Option<T> {
+    let file = File::open(cache_file(key)?).ok()?;
+    let entry :CacheEntry<T> = serde_cbor::from_reader(file)
+        .map_err(|e| warn!("Could not read cached result {:016x}: {}", key, e)).ok()?;
+    if entry.version != env!("CARGO_PKG_VERSION") {
+        let _ = std::fs::remove_file(cache_file(key)?);
+        return None;
+    }
+    info!("Using cached result {:016x}", key);
+    Some(entry.data)
+}
+
+pub fn store<T :Serialize>(key :Key, data :&T) {
+    let result = (|| -> Result<(), String> {
+        let path = cache_file(key).ok_or(format!("No cache directory"))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("{}", e))?;
+        }
+        let entry = CacheEntry { version: env!("CARGO_PKG_VERSION").to_string(), data: data };
+        serde_cbor::to_writer(&File::create(&path).map_err(|e| format!("{}", e))?, &entry)
+            .map_err(|e| format!("{}", e))?;
+        match path.parent() { Some(dir) => prune(dir), None => Ok(()) }
+    })();
+    if let Err(e) = result { warn!("Could not store cached result {:016x}: {}", key, e); }
+}
+
+/// Remove the oldest entries when there are more than `MAX_ENTRIES`.
+fn prune(dir :&Path) -> Result<(), String> {
+    let mut entries = std::fs::read_dir(dir).map_err(|e| format!("{}", e))?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().extension().map(|x| x == "cbor").unwrap_or(false))
+        .filter_map(|e| Some((e.metadata().ok()?.modified().ok()?, e.path())))
+        .collect::<Vec<_>>();
+    if entries.len() <= MAX_ENTRIES { return Ok(()); }
+    entries.sort();
+    for (_,path) in &entries[..(entries.len() - MAX_ENTRIES)] {
+        std::fs::remove_file(path).map_err(|e| format!("{}", e))?;
+    }
+    Ok(())
+}
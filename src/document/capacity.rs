@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use crate::document::dispatch::*;
+use rolling::input::staticinfrastructure::ObjectId;
+
+/// Parameters of the capacity consumption analysis (UIC 406).
+#[derive(Debug, Copy, Clone)]
+pub struct CapacityParams {
+    /// Time for setting the route and clearing the signal,
+    /// added before the section is reserved.
+    pub setup_time :f64,
+    /// Time for releasing the route, added after the section is released.
+    pub release_time :f64,
+    /// Line section (mileage interval) to analyse.
+    pub section :(f64,f64),
+    /// Evaluation time window. Trains starting inside the window are compressed,
+    /// and the consumption is relative to the window length. When `None`,
+    /// the span of the timetable in the line section is used.
+    pub window :Option<(f64,f64)>,
+}
+
+impl Default for CapacityParams {
+    fn default() -> Self {
+        CapacityParams { setup_time: 6.0, release_time: 3.0, section: (std::f64::NEG_INFINITY, std::f64::INFINITY), window: None }
+    }
+}
+
+/// Blocking time of one train in one section.
+#[derive(Debug, Copy, Clone)]
+pub struct BlockingTime {
+    pub tvd :ObjectId,
+    pub pos :(f64,f64),
+    pub time :(f64,f64),
+}
+
+/// Blocking times of a train within the analysed line section.
+#[derive(Debug, Clone)]
+pub struct Stairway {
+    pub train :usize,
+    pub blocks :Vec<BlockingTime>,
+}
+
+impl Stairway {
+    pub fn start(&self) -> f64 { self.blocks.iter().map(|b| b.time.0).fold(std::f64::INFINITY, f64::min) }
+    pub fn end(&self) -> f64 { self.blocks.iter().map(|b| b.time.1).fold(std::f64::NEG_INFINITY, f64::max) }
+
+    fn shifted(&self, dt :f64) -> Stairway {
+        Stairway {
+            train: self.train,
+            blocks: self.blocks.iter().map(|b| BlockingTime { time: (b.time.0 + dt, b.time.1 + dt), ..*b }).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CapacityConsumption {
+    pub stairways :Vec<Stairway>,
+    pub compressed :Vec<Stairway>,
+    /// Evaluation window, or the time interval of the original timetable in the line section.
+    pub period :(f64,f64),
+    /// Time needed for the compressed timetable.
+    pub occupation_time :f64,
+    /// Occupation time as a percentage of the period length.
+    pub consumption :f64,
+}
+
+/// Blocking-time stairways for each train, from the block reservation and
+/// occupation intervals of the diagram, limited to the line section.
+pub fn stairways(diagram :&Diagram, params :&CapacityParams) -> Vec<Stairway> {
+    let (a,b) = params.section;
+    let mut trains :HashMap<usize, Vec<BlockingTime>> = HashMap::new();
+    for block in diagram.blocks.iter() {
+        if block.pos.1 <= a || block.pos.0 >= b { continue; }
+        let start = block.reserved.0.min(block.occupied.0) - params.setup_time;
+        let end = block.reserved.1.max(block.occupied.1) + params.release_time;
+        trains.entry(block.train).or_insert(Vec::new())
+            .push(BlockingTime { tvd: block.tvd, pos: (block.pos.0.max(a), block.pos.1.min(b)), time: (start,end) });
+    }
+    let mut stairways = trains.into_iter().map(|(train,blocks)| Stairway { train, blocks }).collect::<Vec<_>>();
+    stairways.sort_by(|x,y| x.start().partial_cmp(&y.start()).unwrap());
+    stairways
+}
+
+/// Compress the timetable: keep the order of the trains, and move each train as
+/// early as possible without overlapping the blocking times of the preceding trains
+/// and without starting before the preceding train.
+pub fn compress(stairways :&[Stairway]) -> Vec<Stairway> {
+    let mut compressed :Vec<Stairway> = Vec::new();
+    let mut section_free :HashMap<ObjectId, f64> = HashMap::new();
+    for s in stairways {
+        let mut dt = match compressed.last() {
+            Some(prev) => prev.start() - s.start(),
+            None => 0.0,
+        };
+        for b in s.blocks.iter() {
+            if let Some(free) = section_free.get(&b.tvd) {
+                dt = dt.max(free - b.time.0);
+            }
+        }
+        let s = s.shifted(dt);
+        for b in s.blocks.iter() {
+            let free = section_free.entry(b.tvd).or_insert(std::f64::NEG_INFINITY);
+            *free = free.max(b.time.1);
+        }
+        compressed.push(s);
+    }
+    compressed
+}
+
+pub fn capacity_consumption(diagram :&Diagram, params :&CapacityParams) -> Option<CapacityConsumption> {
+    evaluate(stairways(diagram, params), params.window)
+}
+
+/// Compress the stairways starting inside the evaluation window and relate the
+/// resulting occupation time to the window length.
+pub fn evaluate(stairways :Vec<Stairway>, window :Option<(f64,f64)>) -> Option<CapacityConsumption> {
+    let stairways = match window {
+        Some((a,b)) => stairways.into_iter().filter(|s| a <= s.start() && s.start() < b).collect::<Vec<_>>(),
+        None => stairways,
+    };
+    if stairways.is_empty() { return None; }
+    let compressed = compress(&stairways);
+    let period = window.unwrap_or_else(||
+                 (stairways.iter().map(|s| s.start()).fold(std::f64::INFINITY, f64::min),
+                  stairways.iter().map(|s| s.end()).fold(std::f64::NEG_INFINITY, f64::max)));
+    let occupation_time = compressed.iter().map(|s| s.end()).fold(std::f64::NEG_INFINITY, f64::max) -
+                          compressed.iter().map(|s| s.start()).fold(std::f64::INFINITY, f64::min);
+    let consumption = 100.0 * occupation_time / (period.1 - period.0).max(1e-6);
+    Some(CapacityConsumption { stairways, compressed, period, occupation_time, consumption })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stairway(train :usize, blocks :&[(usize,f64,f64)]) -> Stairway {
+        Stairway {
+            train,
+            blocks: blocks.iter().enumerate().map(|(i,(tvd,t0,t1))| BlockingTime {
+                tvd: *tvd, pos: (i as f64, i as f64 + 1.0), time: (*t0,*t1) }).collect(),
+        }
+    }
+
+    fn times(s :&Stairway) -> Vec<(f64,f64)> { s.blocks.iter().map(|b| b.time).collect() }
+
+    #[test]
+    fn compress_follows_blocking_times() {
+        let timetable = vec![
+            stairway(0, &[(1, 0.0, 60.0), (2, 40.0, 100.0)]),
+            stairway(1, &[(1, 300.0, 360.0), (2, 340.0, 400.0)]),
+        ];
+        let compressed = compress(&timetable);
+        assert_eq!(times(&compressed[0]), times(&timetable[0]));
+        // The second train can enter the first section when the first train releases it.
+        assert_eq!(times(&compressed[1]), vec![(60.0, 120.0), (100.0, 160.0)]);
+    }
+
+    #[test]
+    fn compress_keeps_train_order() {
+        // The second train is faster and would otherwise be moved before the first one.
+        let timetable = vec![
+            stairway(0, &[(1, 0.0, 100.0)]),
+            stairway(1, &[(2, 500.0, 520.0)]),
+            stairway(2, &[(1, 600.0, 650.0)]),
+        ];
+        let compressed = compress(&timetable);
+        assert_eq!(compressed.iter().map(|s| s.train).collect::<Vec<_>>(), vec![0,1,2]);
+        assert_eq!(times(&compressed[1]), vec![(0.0, 20.0)]);
+        assert_eq!(times(&compressed[2]), vec![(100.0, 150.0)]);
+    }
+
+    #[test]
+    fn consumption_is_occupation_over_period() {
+        let timetable = vec![
+            stairway(0, &[(1, 0.0, 60.0)]),
+            stairway(1, &[(1, 100.0, 160.0)]),
+            stairway(2, &[(1, 3000.0, 3060.0)]),
+        ];
+
+        let c = evaluate(timetable.clone(), None).unwrap();
+        assert_eq!(c.period, (0.0, 3060.0));
+        assert_eq!(c.occupation_time, 180.0);
+        assert!((c.consumption - 100.0*180.0/3060.0).abs() < 1e-9);
+
+        let c = evaluate(timetable.clone(), Some((0.0, 600.0))).unwrap();
+        assert_eq!(c.stairways.len(), 2);
+        assert_eq!(c.occupation_time, 120.0);
+        assert!((c.consumption - 20.0).abs() < 1e-9);
+
+        assert!(evaluate(timetable, Some((4000.0, 5000.0))).is_none());
+    }
+}
@@ -0,0 +1,307 @@
+//! Monte Carlo simulation of delay propagation in a dispatch.
+
+use std::collections::HashMap;
+use ordered_float::OrderedFloat;
+use rolling::output::history::*;
+
+use crate::document::model::*;
+use crate::document::dgraph::DGraph;
+use crate::document::interlocking::Interlocking;
+use crate::document::history;
+
+pub const PERCENTILES :[f64;3] = [50.0, 90.0, 95.0];
+
+#[derive(Debug, Copy, Clone)]
+pub struct DelayDistributions {
+    /// Mean entry delay of each train (exponential distribution).
+    pub entry_delay :f64,
+    /// Largest extra waiting time before each route request,
+    /// modelling dwell time variation (uniform distribution).
+    pub dwell_variation :f64,
+    /// Lowest driver performance factor (uniform distribution up to 1.0).
+    /// Scales the train's acceleration and maximum velocity.
+    pub min_performance :f64,
+}
+
+impl Default for DelayDistributions {
+    fn default() -> Self {
+        DelayDistributions {
+            entry_delay: 60.0,
+            dwell_variation: 30.0,
+            min_performance: 0.9,
+        }
+    }
+}
+
+/// Seeded xorshift generator, so that each run can be reproduced.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed :u64) -> Rng {
+        Rng(seed.wrapping_mul(0x9E3779B97F4A7C15) | 1)
+    }
+
+    /// Uniform sample in [0,1).
+    pub fn sample(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        let x = self.0.wrapping_mul(0x2545F4914F6CDD1D);
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn uniform(&mut self, a :f64, b :f64) -> f64 {
+        a + (b-a)*self.sample()
+    }
+
+    pub fn exponential(&mut self, mean :f64) -> f64 {
+        -mean * (1.0 - self.sample()).ln()
+    }
+}
+
+/// Passing times at model nodes for each train in a history.
+pub type TrainVisits = Vec<(String, Vec<(Pt, f64)>)>;
+
+pub fn train_visits(dgraph :&DGraph, history :&History) -> TrainVisits {
+    history.trains.iter().map(|(name, _params, events)| {
+        let mut t = 0.0;
+        let mut visits :Vec<(Pt,f64)> = Vec::new();
+        for ev in events {
+            match ev {
+                TrainLogEvent::Wait(dt) | TrainLogEvent::Move(dt,_,_) => { t += dt; },
+                TrainLogEvent::Node(n) => {
+                    if let Some(pt) = dgraph.node_ids.get_by_left(n) {
+                        if visits.last().map(|(p,_)| p) != Some(pt) {
+                            visits.push((*pt, t));
+                        }
+                    }
+                },
+                _ => {},
+            }
+        }
+        (name.clone(), visits)
+    }).collect()
+}
+
+#[derive(Debug)]
+pub struct RunResult {
+    pub seed :u64,
+    pub entry_delays :HashMap<String, f64>,
+    pub visits :TrainVisits,
+}
+
+/// Sample delays and performance factors, giving a new set of
+/// commands and vehicles for one simulation run.
+/// Route requests are shifted by the entry delay of the train that uses
+/// them, i.e. the train whose previous route ends where the route starts.
+pub fn perturb(commands :&Commands, vehicles :&[(usize,Vehicle)],
+               dist :&DelayDistributions, rng :&mut Rng)
+    -> (Commands, Vec<(usize,Vehicle)>, HashMap<String,f64>) {
+
+    let mut new_vehicles = vehicles.to_vec();
+    let mut next_vehicle = vehicles.iter().map(|(i,_)| *i + 1).max().unwrap_or(0);
+    let mut entry_delays = HashMap::new();
+
+    // Delay of each train by command id, and where each train's last route ends.
+    let mut train_delays :HashMap<usize,f64> = HashMap::new();
+    let mut route_ends :Vec<(usize,Ref)> = Vec::new();
+
+    let mut sorted = commands.clone();
+    sorted.sort_by_key(|(_,(t,_))| OrderedFloat(*t));
+
+    let mut new_commands = sorted.iter().map(|(id,(t,cmd))| {
+        match cmd {
+            Command::Train(v, route) => {
+                let delay = rng.exponential(dist.entry_delay);
+                entry_delays.insert(history::train_name(*id), delay);
+                train_delays.insert(*id, delay);
+                route_ends.push((*id, route.to));
+
+                // Each train gets its own copy of the vehicle
+                let factor = rng.uniform(dist.min_performance, 1.0);
+                let mut vehicle = vehicles.iter().find(|(i,_)| i == v)
+                    .map(|(_,v)| v.clone()).unwrap_or_default();
+                vehicle.max_acc *= factor as f32;
+                vehicle.max_vel *= factor as f32;
+                let vehicle_id = next_vehicle;
+                next_vehicle += 1;
+                new_vehicles.push((vehicle_id, vehicle));
+
+                (*id, (t + delay, Command::Train(vehicle_id, *route)))
+            },
+            Command::Route(route) => {
+                let delay = match route_ends.iter_mut().rev().find(|(_,end)| *end == route.from) {
+                    Some((train, end)) => {
+                        *end = route.to;
+                        train_delays.get(&*train).cloned().unwrap_or(0.0)
+                    },
+                    None => 0.0,
+                };
+                let dwell = rng.uniform(0.0, dist.dwell_variation);
+                (*id, (t + delay + dwell, Command::Route(*route)))
+            },
+            Command::Couple(front, rear) => {
+                let delay = [front, rear].iter().filter_map(|i| train_delays.get(*i))
+                    .cloned().fold(0.0, f64::max);
+                (*id, (t + delay, *cmd))
+            },
+            Command::Split(train, _) => {
+                let delay = train_delays.get(train).cloned().unwrap_or(0.0);
+                train_delays.insert(*id, delay);
+                (*id, (t + delay, *cmd))
+            },
+        }
+    }).collect::<Vec<_>>();
+    new_commands.sort_by_key(|(_,(t,_))| OrderedFloat(*t));
+
+    (new_commands, new_vehicles, entry_delays)
+}
+
+pub fn simulate_run(dgraph :&DGraph, il :&Interlocking, vehicles :&[(usize,Vehicle)],
+                    commands :&Commands, dist :&DelayDistributions, seed :u64) -> Result<RunResult, String> {
+    let mut rng = Rng::new(seed);
+    let (commands, vehicles, entry_delays) = perturb(commands, vehicles, dist, &mut rng);
+    let (history, _route_refs) = history::get_history(&vehicles, &dgraph.rolling_inf, il, &commands)?;
+    Ok(RunResult { seed, entry_delays, visits: train_visits(dgraph, &history) })
+}
+
+#[derive(Debug, Clone)]
+pub struct DelayStats {
+    pub mean :f64,
+    /// Delay at each of the `PERCENTILES`.
+    pub percentiles :Vec<f64>,
+    /// Mean delay which is not explained by the train's own entry delay.
+    pub knock_on :f64,
+    /// Number of runs where the train did not reach the location.
+    pub missing :usize,
+}
+
+impl DelayStats {
+    fn from(mut delays :Vec<(f64,f64)>, missing :usize) -> DelayStats {
+        let n = delays.len().max(1) as f64;
+        let mean = delays.iter().map(|(d,_)| d).sum::<f64>() / n;
+        let knock_on = delays.iter().map(|(d,e)| (d-e).max(0.0)).sum::<f64>() / n;
+        delays.sort_by_key(|(d,_)| OrderedFloat(*d));
+        let percentiles = PERCENTILES.iter().map(|p| {
+            if delays.len() == 0 { return 0.0; }
+            let rank = ((p / 100.0) * delays.len() as f64).ceil() as usize;
+            delays[rank.max(1).min(delays.len()) - 1].0
+        }).collect();
+        DelayStats { mean, percentiles, knock_on, missing }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TrainDelays {
+    pub name :String,
+    /// Delay at the last visited location.
+    pub total :DelayStats,
+    pub visits :Vec<(Pt, f64, DelayStats)>,
+}
+
+/// Compare the runs to the nominal (unperturbed) history.
+pub fn delay_statistics(nominal :&TrainVisits, runs :&[RunResult]) -> Vec<TrainDelays> {
+    nominal.iter().map(|(name, nominal_visits)| {
+        let run_visits = runs.iter().map(|r| {
+            (r.visits.iter().find(|(n,_)| n == name).map(|(_,v)| v),
+             r.entry_delays.get(name).cloned().unwrap_or(0.0))
+        }).collect::<Vec<_>>();
+
+        let visits = nominal_visits.iter().enumerate().map(|(i,(pt,t))| {
+            let mut delays = Vec::new();
+            let mut missing = 0;
+            for (visits, entry) in run_visits.iter() {
+                match visits.and_then(|v| v.get(i)) {
+                    Some((p, t_run)) if p == pt => delays.push((t_run - t, *entry)),
+                    _ => missing += 1,
+                }
+            }
+            (*pt, *t, DelayStats::from(delays, missing))
+        }).collect::<Vec<_>>();
+
+        let total = visits.last().map(|(_,_,s)| s.clone())
+            .unwrap_or_else(|| DelayStats::from(Vec::new(), runs.len()));
+        TrainDelays { name: name.clone(), total, visits }
+    }).collect()
+}
+
+pub fn to_csv(stats :&[TrainDelays]) -> String {
+    use std::fmt::Write;
+    let mut s = String::new();
+    write!(s, "train,location,nominal_time,mean").unwrap();
+    for p in PERCENTILES.iter() { write!(s, ",p{}", p).unwrap(); }
+    writeln!(s, ",knock_on,missing").unwrap();
+
+    let row = |s :&mut String, train :&str, loc :&str, t :Option<f64>, d :&DelayStats| {
+        write!(s, "{},{},{},{:.2}", train, loc, t.map(|t| format!("{:.2}", t)).unwrap_or_default(), d.mean).unwrap();
+        for p in d.percentiles.iter() { write!(s, ",{:.2}", p).unwrap(); }
+        writeln!(s, ",{:.2},{}", d.knock_on, d.missing).unwrap();
+    };
+
+    for train in stats {
+        row(&mut s, &train.name, "total", None, &train.total);
+        for (pt, t, d) in train.visits.iter() {
+            row(&mut s, &train.name, &format!("\"({},{})\"", pt.x, pt.y), Some(*t), d);
+        }
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm as glm;
+
+    fn route(from :i32, to :i32) -> RouteSpec {
+        RouteSpec { from: Ref::Node(glm::vec2(from,0)), to: Ref::Node(glm::vec2(to,0)), alternative: 0 }
+    }
+
+    fn commands() -> Commands {
+        vec![(0, (0.0, Command::Train(0, route(0,1)))),
+             (1, (30.0, Command::Train(0, route(10,11)))),
+             (2, (60.0, Command::Route(route(1,2)))),
+             (3, (90.0, Command::Route(route(11,12)))),
+             (4, (120.0, Command::Route(route(2,3))))]
+    }
+
+    #[test]
+    fn zero_delay_is_nominal() {
+        let dist = DelayDistributions { entry_delay: 0.0, dwell_variation: 0.0, min_performance: 1.0 };
+        let vehicles = vec![(0, Vehicle::default())];
+        let (perturbed, new_vehicles, entry_delays) = perturb(&commands(), &vehicles, &dist, &mut Rng::new(1));
+        let times = |c :&Commands| c.iter().map(|(id,(t,_))| (*id,*t)).collect::<Vec<_>>();
+        assert_eq!(times(&perturbed), times(&commands()));
+        assert!(entry_delays.values().all(|d| *d == 0.0));
+        for (_,v) in new_vehicles.iter().skip(1) {
+            assert_eq!(v.max_vel, vehicles[0].1.max_vel);
+            assert_eq!(v.max_acc, vehicles[0].1.max_acc);
+        }
+    }
+
+    #[test]
+    fn routes_follow_train_delay() {
+        let dist = DelayDistributions { entry_delay: 60.0, dwell_variation: 0.0, min_performance: 1.0 };
+        let vehicles = vec![(0, Vehicle::default())];
+        let (perturbed, _, entry_delays) = perturb(&commands(), &vehicles, &dist, &mut Rng::new(7));
+        let time = |id :usize| perturbed.iter().find(|(i,_)| *i == id).map(|(_,(t,_))| *t).unwrap();
+        let d0 = entry_delays[&history::train_name(0)];
+        let d1 = entry_delays[&history::train_name(1)];
+        assert!((time(2) - (60.0 + d0)).abs() < 1e-9);
+        assert!((time(3) - (90.0 + d1)).abs() < 1e-9);
+        assert!((time(4) - (120.0 + d0)).abs() < 1e-9);
+        assert!(perturbed.windows(2).all(|w| (w[0].1).0 <= (w[1].1).0));
+    }
+
+    #[test]
+    fn percentiles_are_ordered() {
+        let mut rng = Rng::new(42);
+        let delays = (0..1000).map(|_| { let d = rng.exponential(60.0); (d, d) }).collect::<Vec<_>>();
+        let stats = DelayStats::from(delays, 0);
+        assert_eq!(stats.percentiles.len(), PERCENTILES.len());
+        assert!(stats.percentiles.windows(2).all(|w| w[0] <= w[1]));
+        assert!(stats.percentiles[0] > 0.0);
+        // Median of an exponential distribution is below its mean.
+        assert!(stats.percentiles[0] < stats.mean);
+        assert!(stats.knock_on.abs() < 1e-9);
+    }
+}
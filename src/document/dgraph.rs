@@ -19,12 +19,17 @@ pub struct DGraph {
     pub rolling_inf :rolling_inf::StaticInfrastructure, 
     pub node_ids :BiMap<rolling_inf::NodeId, Pt>,
     pub switch_ids :BiMap<rolling_inf::ObjectId, Pt>,
+    /// Location of every switch object, including the switches of slip crossings,
+    /// which are not in `switch_ids`.
+    pub switch_ports :HashMap<rolling_inf::ObjectId, (Pt, Port)>,
     pub object_ids :BiMap<rolling_inf::ObjectId, PtA>,
     pub detector_ids :BiMap<rolling_inf::NodeId, PtA>,
     pub tvd_edges :HashMap<rolling_inf::ObjectId, Vec<(rolling_inf::NodeId, rolling_inf::NodeId)>>,
     pub tvd_entry_nodes :HashMap<rolling_inf::ObjectId, Vec<rolling_inf::NodeId>>,
     pub edge_lines :HashMap<(rolling_inf::NodeId, rolling_inf::NodeId), Vec<PtC>>,
     pub mileage :HashMap<rolling_inf::NodeId, f64>,
+    /// Graph node located at each model node and track object.
+    pub ref_nodes :HashMap<Ref, rolling_inf::NodeId>,
     pub all_paths :(f64, Vec<allpaths::Path>),
 }
 
@@ -36,6 +41,10 @@ impl DGraph {
         let km_b = *self.mileage.get(&b)?;
         Some(glm::lerp_scalar(km_a,km_b,param))
     }
+
+    pub fn ref_mileage(&self, r :&Ref) -> Option<f64> {
+        self.mileage.get(self.ref_nodes.get(r)?).cloned()
+    }
 }
 
 pub fn edge_length(rolling_inf :&rolling_inf::StaticInfrastructure, a :rolling_inf::NodeId, b: rolling_inf::NodeId) -> Option<f64> {
@@ -79,18 +88,29 @@ impl DGraphBuilder {
         let mut detector_nodes : HashSet<(rolling_inf::NodeId, rolling_inf::NodeId)> = HashSet::new();
         let mut object_ids = BiMap::new();
         let mut detector_ids = BiMap::new();
-        let (node_ids, switch_ids, crossing_edges) = m.create_network(
+        let mut ref_nodes = HashMap::new();
+        let (node_ids, switch_ids, switch_ports, crossing_edges) = m.create_network(
             tracks, &locs, 
             |track_idx,mut cursor,dg| {
+                // Remember the graph nodes at the track ends, before any edges are split.
+                if let Cursor::Node(start) = cursor {
+                    let (_, (pt_a,_), (pt_b,_)) = tracks[track_idx];
+                    ref_nodes.entry(Ref::Node(pt_a)).or_insert(start);
+                    if let rolling_inf::Edges::Single(end,_) = dg.dgraph.nodes[start].edges {
+                        ref_nodes.entry(Ref::Node(pt_b)).or_insert(end);
+                    }
+                }
+
                 let mut last_pos = 0.0;
                 let mut objs :Vec<(f64,PtA,Function,Option<AB>)> = trackobjects[track_idx].clone();
-                objs.sort_by_key(|(pos,_,_,_)| OrderedFloat(*pos));
+                objs.sort_by_key(|(pos,id,_,_)| (OrderedFloat(*pos), (id.x,id.y)));
                 for (pos, id, func, dir) in objs {
 
                     // TODO stack overflow here
                     cursor = cursor.advance_single(&dg.dgraph, pos - last_pos).unwrap();
 
                     cursor = dg.insert_node_pair(cursor);
+                    if let Cursor::Node(n) = cursor { ref_nodes.insert(Ref::Object(id), n); }
 
                     match func {
                         Function::Detector => { 
@@ -114,6 +134,8 @@ impl DGraphBuilder {
             } );
 
         // Sight to signals
+        let mut signal_cursors = signal_cursors.into_iter().collect::<Vec<_>>();
+        signal_cursors.sort_by_key(|(id,_)| (id.x,id.y));
         for (id,cursor) in signal_cursors {
             let objid = static_signals[&id];
             let sight_dist = 200.0; // TODO configurable
@@ -144,7 +166,9 @@ impl DGraphBuilder {
         let rev_edge_lines = edge_lines.iter().map(|((a,b),v)| ((*b,*a),{ let mut v= v.clone(); v.reverse(); v })).collect::<Vec<_>>();
         edge_lines.extend(rev_edge_lines.into_iter());
 
-        let mileage = mileage::auto(&node_ids, &m.dgraph);
+        let mileage_specs = topology.mileages.iter()
+            .filter_map(|(r,spec)| Some((*ref_nodes.get(r)?, *spec))).collect();
+        let mileage = mileage::auto(&node_ids, &m.dgraph, &mileage_specs);
         //println!("MILEAGES {:?}", mileage);
         //mileage::test_lsq();
         //let mileage = std::iter::empty().collect();
@@ -155,12 +179,14 @@ impl DGraphBuilder {
             rolling_inf: m.dgraph,
             node_ids: node_ids,
             switch_ids: switch_ids,
+            switch_ports: switch_ports,
             object_ids: object_ids,
             detector_ids: detector_ids,
             tvd_edges: tvd_edges,
             tvd_entry_nodes: tvd_entry_nodes,
             edge_lines: edge_lines,
             mileage: mileage,
+            ref_nodes: ref_nodes,
             all_paths: all_paths,
         })
 
@@ -263,10 +289,12 @@ impl DGraphBuilder {
         mut each_track: impl FnMut(usize,Cursor,&mut Self)) -> 
         (BiMap<rolling_inf::NodeId, Pt>,
          BiMap<rolling_inf::ObjectId, Pt>,
+         HashMap<rolling_inf::ObjectId, (Pt, Port)>,
          HashSet<(rolling_inf::NodeId, rolling_inf::NodeId)>) {
 
         let mut node_ids = BiMap::new();
         let mut switch_ids = BiMap::new();
+        let mut switch_ports = HashMap::new();
         let mut crossing_edges = HashSet::new();
         let mut ports :HashMap<(Pt,Port), rolling_inf::NodeId>  = HashMap::new();
         for (i,(len,a,b)) in tracks.iter().enumerate() {
@@ -280,7 +308,10 @@ impl DGraphBuilder {
             each_track(i,Cursor::Node(start_b), self);
         }
 
-        for (pt,(node,_)) in nodes.iter() {
+        // Sorted, so that node and object ids are the same in every session.
+        let mut nodes = nodes.iter().collect::<Vec<_>>();
+        nodes.sort_by_key(|(pt,_)| (pt.x,pt.y));
+        for (pt,(node,_)) in nodes {
             match node {
                 NDType::BufferStop => {},
                 NDType::OpenEnd => {
@@ -300,6 +331,7 @@ impl DGraphBuilder {
                     });
 
                     switch_ids.insert(sw_obj, *pt);
+                    switch_ports.insert(sw_obj, (*pt, Port::Trunk));
 
                     self.dgraph.nodes[ports[&(*pt, Port::Left)]].edges  = 
                         rolling_inf::Edges::Single(ports[&(*pt,Port::Trunk)], 0.0);
@@ -325,6 +357,8 @@ impl DGraphBuilder {
                                 branch_side: Side::Right.as_switch_position(),
                             });
 
+                            switch_ports.insert(sw_a, (*pt, Port::Cross(*dir, 0)));
+                            switch_ports.insert(sw_b, (*pt, Port::Cross(dir.other(), 1)));
                             self.dgraph.nodes[ports[&(*pt, Port::Cross(*dir, 0))]].edges = rolling_inf::Edges::Switchable(sw_a);
                             self.dgraph.nodes[ports[&(*pt, Port::Cross(dir.other(), 1))]].edges = rolling_inf::Edges::Switchable(sw_b);
                         } else {
@@ -343,7 +377,7 @@ impl DGraphBuilder {
                 NDType::Err => {},
             }
         }
-        (node_ids, switch_ids, crossing_edges)
+        (node_ids, switch_ids, switch_ports, crossing_edges)
     }
 }
 
@@ -0,0 +1,184 @@
+//! Diagnostics for dispatch commands which do not have the intended effect.
+
+use std::collections::{HashMap, HashSet};
+use rolling::output::history::*;
+
+use crate::document::model::*;
+use crate::document::dgraph::DGraph;
+use crate::document::interlocking::Interlocking;
+use crate::document::history;
+use crate::document::dispatch::tvd_max_pos_interval;
+use crate::document::ars::routes_conflict;
+
+#[derive(Debug, Clone)]
+pub enum Blocking {
+    /// Section (mileage interval) still occupied at the end of the simulation.
+    Occupied(Option<(f64,f64)>),
+    /// Section still reserved by another route.
+    Reserved(Option<(f64,f64)>),
+    /// Switch still locked by another route.
+    Switch(Option<Pt>),
+    /// A conflicting route requested earlier (by the given command) never became active.
+    Queued(usize),
+}
+
+#[derive(Debug, Clone)]
+pub enum CommandIssue {
+    /// The route does not exist in the interlocking, typically after an infrastructure edit.
+    RouteMissing,
+    VehicleMissing,
+    /// No train is created by the referenced command.
+    TrainMissing(usize),
+    /// Coupling or splitting was ignored, because the trains were not standing still.
+    NoEffect,
+    /// The route was requested but never became active.
+    NeverActive(Option<Blocking>),
+    /// The route became active, but no train entered it.
+    NeverUsed,
+}
+
+#[derive(Debug, Clone)]
+pub struct CommandDiagnostic {
+    pub cmd_id :usize,
+    pub issue :CommandIssue,
+}
+
+fn km_text(km :&Option<(f64,f64)>) -> String {
+    match km {
+        Some((a,b)) => format!(" at km {:.3}-{:.3}", a/1000.0, b/1000.0),
+        None => String::new(),
+    }
+}
+
+impl CommandIssue {
+    pub fn message(&self) -> String {
+        match self {
+            CommandIssue::RouteMissing => format!("Route does not exist in the infrastructure."),
+            CommandIssue::VehicleMissing => format!("Vehicle does not exist, using default train."),
+            CommandIssue::TrainMissing(id) => format!("No train {} exists at this time.", history::train_name(*id)),
+            CommandIssue::NoEffect => format!("Ignored because the trains were not standing still."),
+            CommandIssue::NeverActive(None) => format!("Route never becomes active."),
+            CommandIssue::NeverActive(Some(b)) => format!("Route never becomes active: {}.", match b {
+                Blocking::Occupied(km) => format!("section{} is occupied", km_text(km)),
+                Blocking::Reserved(km) => format!("section{} is reserved", km_text(km)),
+                Blocking::Switch(Some(pt)) => format!("switch at ({},{}) is locked", pt.x, pt.y),
+                Blocking::Switch(None) => format!("switch is locked"),
+                Blocking::Queued(id) => format!("waiting for conflicting route of command {}", id),
+            }),
+            CommandIssue::NeverUsed => format!("No train reaches the route."),
+        }
+    }
+}
+
+pub fn check(dgraph :&DGraph, il :&Interlocking, vehicles :&[(usize,Vehicle)],
+             commands :&Commands, history :&History) -> Vec<CommandDiagnostic> {
+    let mut issues = Vec::new();
+
+    // Route request status and final resource state from the infrastructure log.
+    let mut t = 0.0;
+    let mut activated :HashMap<usize,f64> = HashMap::new();
+    let mut reserved :HashMap<usize,bool> = HashMap::new();
+    let mut occupied :HashMap<usize,bool> = HashMap::new();
+    for ev in history.inf.iter() {
+        match ev {
+            InfrastructureLogEvent::Wait(dt) => { t += dt; },
+            InfrastructureLogEvent::Route(req, RouteStatus::Active) => { activated.insert(*req, t); },
+            InfrastructureLogEvent::Reserved(obj, b) => { reserved.insert(*obj, *b); },
+            InfrastructureLogEvent::Occupied(obj, b, _, _) => { occupied.insert(*obj, *b); },
+            _ => {},
+        }
+    }
+
+    // Edges entered by any train, and coupling/splitting events per train.
+    let mut edges = Vec::new();
+    let mut couplings :HashMap<&str,usize> = HashMap::new();
+    let mut splits :HashMap<&str,usize> = HashMap::new();
+    for (name, _params, events) in history.trains.iter() {
+        let mut t = 0.0;
+        for ev in events.iter() {
+            match ev {
+                TrainLogEvent::Wait(dt) | TrainLogEvent::Move(dt,_,_) => { t += dt; },
+                TrainLogEvent::Edge(a, Some(b)) => { edges.push((t, *a, *b)); },
+                TrainLogEvent::Couple(_,_) => { *couplings.entry(name.as_str()).or_insert(0) += 1; },
+                TrainLogEvent::Split(_,_) => { *splits.entry(name.as_str()).or_insert(0) += 1; },
+                _ => {},
+            }
+        }
+    }
+    let train_exists = |id :usize| {
+        let name = history::train_name(id);
+        history.trains.iter().any(|(n,_,_)| *n == name)
+    };
+
+    let mut request = 0;
+    let mut never_active :Vec<(usize,usize)> = Vec::new();
+    let mut used_couplings :HashMap<String,usize> = HashMap::new();
+    let mut used_splits :HashMap<String,usize> = HashMap::new();
+    let mut report = |cmd_id :usize, issue :CommandIssue| issues.push(CommandDiagnostic { cmd_id, issue });
+    for (cmd_id, (_t, cmd)) in commands.iter() {
+        if let Command::Train(v,_) = cmd {
+            if !vehicles.iter().any(|(id,_)| id == v) {
+                report(*cmd_id, CommandIssue::VehicleMissing);
+            }
+        }
+
+        if let Some(spec) = cmd.route() {
+            let route_idx = match il.find_route(spec) {
+                Some(r) => *r,
+                None => { report(*cmd_id, CommandIssue::RouteMissing); continue; },
+            };
+            let route = &il.routes[route_idx];
+            let req = request;
+            request += 1;
+
+            match activated.get(&req) {
+                None => {
+                    let queued = never_active.iter()
+                        .find(|(_,r)| routes_conflict(&il.routes[*r].route, &route.route))
+                        .map(|(id,_)| Blocking::Queued(*id));
+                    let resources = &route.route.resources;
+                    let section = |state :&HashMap<usize,bool>| resources.sections.iter()
+                        .find(|s| state.get(*s) == Some(&true))
+                        .map(|s| tvd_max_pos_interval(dgraph, *s));
+                    let blocking = queued
+                        .or_else(|| section(&occupied).map(Blocking::Occupied))
+                        .or_else(|| section(&reserved).map(Blocking::Reserved))
+                        .or_else(|| resources.switch_positions.iter()
+                                 .find(|(sw,_)| reserved.get(sw) == Some(&true))
+                                 .map(|(sw,_)| Blocking::Switch(dgraph.switch_ids.get_by_left(sw).cloned())));
+                    report(*cmd_id, CommandIssue::NeverActive(blocking));
+                    never_active.push((*cmd_id, route_idx));
+                },
+                Some(t_active) => {
+                    if let (Command::Route(_), Some((a,b))) = (cmd, route.path.first()) {
+                        let used = edges.iter().any(|(t,ea,eb)| t >= t_active && ea == a && eb == b);
+                        if !used { report(*cmd_id, CommandIssue::NeverUsed); }
+                    }
+                },
+            }
+        }
+
+        let (train, counts, used) = match cmd {
+            Command::Couple(front, rear) => {
+                if !train_exists(*rear) { report(*cmd_id, CommandIssue::TrainMissing(*rear)); continue; }
+                (*front, &couplings, &mut used_couplings)
+            },
+            Command::Split(train, _) => (*train, &splits, &mut used_splits),
+            _ => continue,
+        };
+        if !train_exists(train) { report(*cmd_id, CommandIssue::TrainMissing(train)); continue; }
+        let name = history::train_name(train);
+        let n = used.entry(name.clone()).or_insert(0);
+        *n += 1;
+        if *n > counts.get(name.as_str()).cloned().unwrap_or(0) {
+            report(*cmd_id, CommandIssue::NoEffect);
+        }
+    }
+
+    issues
+}
+
+/// Commands which have issues.
+pub fn command_ids(diagnostics :&[CommandDiagnostic]) -> HashSet<usize> {
+    diagnostics.iter().map(|d| d.cmd_id).collect()
+}
@@ -6,6 +6,7 @@ use crate::document::objects::*;
 use crate::document::dgraph::*;
 use crate::document::history::*;
 use crate::document::analysis::*;
+use crate::document::diagnostics;
 
 use crate::util::VecMap;
 
@@ -22,6 +23,9 @@ pub struct DispatchOutput {
     pub pos_interval :(f32,f32),
     pub instant :Instant,
     pub diagram :Diagram, 
+    pub diagnostics :Vec<diagnostics::CommandDiagnostic>,
+    /// Why the dispatch could not be simulated. The history is then empty.
+    pub error :Option<String>,
 }
 
 fn pos_range(diagram :&Diagram) -> (f32,f32) {
@@ -55,11 +59,21 @@ impl DispatchOutput {
             pos_interval: (pos1, pos2),
             instant: instant,
             diagram: diagram,
+            diagnostics: Vec::new(),
+            error: None,
         }
     }
 }
 
-pub type DispatchRef = (Result<usize, (usize,usize)>, f32);
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DispatchSource {
+    Manual(usize),
+    /// Plan index and index of the plan's dispatch.
+    Plan(usize,usize),
+    Timetable(usize),
+}
+
+pub type DispatchRef = (DispatchSource, f32);
 #[derive(Debug)]
 pub struct InstantCache {
     cached : Option<(Generation, DispatchRef, Instant)>,
@@ -72,14 +86,18 @@ impl InstantCache {
         let (dgraph_gen,dgraph) = analysis.data().dgraph.as_ref()?;
         let (d,time) = r;
         let (dispatch_gen, dispatch) = match d {
-            Ok(d) => {
+            DispatchSource::Manual(d) => {
                 let (gen,d) = analysis.data().dispatch.get(d)?.as_ref()?;
                 (gen,d)
             }
-            Err((p,d)) => {
+            DispatchSource::Plan(p,d) => {
                 let (gen,ds) = analysis.data().plandispatches.get(p)?.as_ref()?;
                 (gen,ds.get(d)?)
             }
+            DispatchSource::Timetable(t) => {
+                let (gen,d) = analysis.data().timetables.get(t)?.as_ref()?;
+                (gen,d.as_ref().ok()?)
+            }
         };
         let cached_gen = self.cached.as_ref().map(|x| &x.0);
         let cached_ref = self.cached.as_ref().map(|x| &x.1);
@@ -163,7 +181,9 @@ pub struct BlockGraph {
     pub pos :(f64,f64),
     pub reserved :(f64,f64),
     pub occupied :(f64,f64),
+    /// Index of the train in the history.
     pub train :usize,
+    pub tvd :ObjectId,
     pub info: String,
 }
 
@@ -172,6 +192,16 @@ pub struct TrainGraph {
     pub segments :Vec<TrainGraphSegment>,
 }
 
+impl TrainGraph {
+    /// Approximate position of the train front at the given time.
+    pub fn km_at(&self, t :f64) -> Option<f64> {
+        let s = self.segments.iter().rev().find(|s| s.start_time <= t)
+            .or(self.segments.first())?;
+        let param = if s.dt > 0.0 { glm::clamp_scalar((t - s.start_time)/s.dt, 0.0, 1.0) } else { 1.0 };
+        Some(glm::lerp_scalar(s.kms[0], s.kms[3], param))
+    }
+}
+
 #[derive(Debug)]
 pub struct TrainGraphSegment {
     pub start_time :f64,
@@ -202,8 +232,8 @@ fn plot_blocks(history :&History, dgraph :&DGraph) -> Vec<BlockGraph> {
     use rolling::output::history::*;
     let mut t = 0.0;
     let mut reserved : HashMap<ObjectId,f64> = HashMap::new(); // Reserved at time
-    let mut occupied : HashMap<ObjectId,(f64,(f64,NodeId))> = HashMap::new(); // Became occupied at time
-    let mut vacant : HashMap<ObjectId,(f64,(f64,NodeId),(f64,NodeId))> = HashMap::new(); // Became occupied at time
+    let mut occupied : HashMap<ObjectId,(f64,(f64,NodeId),usize)> = HashMap::new(); // Became occupied at time
+    let mut vacant : HashMap<ObjectId,(f64,(f64,NodeId),(f64,NodeId),usize)> = HashMap::new(); // Became vacant at time
     for infevent in &history.inf {
         //println!("infevent {:?}", infevent);
         //println!("rserved {:?}", reserved);
@@ -214,23 +244,24 @@ fn plot_blocks(history :&History, dgraph :&DGraph) -> Vec<BlockGraph> {
             InfrastructureLogEvent::Reserved(tvd,on) if *on => { reserved.insert(*tvd, t); }
             InfrastructureLogEvent::Occupied(tvd,on,node,train) if *on => {
                 if let Some(reserved_t) = reserved.remove(tvd) {
-                    occupied.insert(*tvd, (reserved_t, (t, *node)));
+                    occupied.insert(*tvd, (reserved_t, (t, *node), *train));
                 }
             },
-            InfrastructureLogEvent::Occupied(tvd,on,node,train) if !*on => {
-                if let Some((reserved_t,(occupied_t,occ_node))) = occupied.remove(tvd) {
-                    vacant.insert(*tvd, (reserved_t, (occupied_t,occ_node),(t, *node)));
+            InfrastructureLogEvent::Occupied(tvd,on,node,_train) if !*on => {
+                if let Some((reserved_t,(occupied_t,occ_node),train)) = occupied.remove(tvd) {
+                    vacant.insert(*tvd, (reserved_t, (occupied_t,occ_node),(t, *node),train));
                 }
             },
             InfrastructureLogEvent::Reserved(tvd,on) if !*on => { 
-                if let Some((res_t, (occ_t, occ_node), (vac_t, vac_node))) = vacant.remove(tvd) {
+                if let Some((res_t, (occ_t, occ_node), (vac_t, vac_node), train)) = vacant.remove(tvd) {
                     if let Some(pos1) = dgraph.mileage.get(&occ_node) {
                         if let Some(pos2) = dgraph.mileage.get(&vac_node) {
                             output.push(BlockGraph {
                                 pos: (pos1.min(*pos2), pos1.max(*pos2)),
                                 reserved: (res_t, t),
                                 occupied: (occ_t, vac_t),
-                                train: 0, // TODO
+                                train: train,
+                                tvd: *tvd,
                                 info: format!("info"), // TODO
                             });
                         }
@@ -242,25 +273,27 @@ fn plot_blocks(history :&History, dgraph :&DGraph) -> Vec<BlockGraph> {
     }
 
     // Any boxes that are still reserved or occupied should also be painted
-    for (tvd, (reserved_t, (occupied_t, occ_node))) in occupied {
+    for (tvd, (reserved_t, (occupied_t, _occ_node), train)) in occupied {
         if let Some(tvd_interval) = tvd_max_pos_interval(dgraph, tvd) {
             output.push(BlockGraph {
                 pos: tvd_interval,
                 reserved: (reserved_t, t),
                 occupied: (occupied_t,t),
-                train: 0,
+                train: train,
+                tvd: tvd,
                 info: format!("info"), // TODO
             });
         }
     }
-    for (tvd, (reserved_t, (occupied_t, occ_node), (vac_t, vac_node))) in vacant {
+    for (tvd, (reserved_t, (occupied_t, occ_node), (vac_t, vac_node), train)) in vacant {
         if let Some(pos1) = dgraph.mileage.get(&occ_node) {
             if let Some(pos2) = dgraph.mileage.get(&vac_node) {
                 output.push(BlockGraph {
                     pos: (pos1.min(*pos2), pos1.max(*pos2)),
                     reserved: (reserved_t, t),
                     occupied: (occupied_t,vac_t),
-                    train: 0,
+                    train: train,
+                    tvd: tvd,
                     info: format!("info"), // TODO
                 });
             }
@@ -285,6 +318,7 @@ pub fn tvd_max_pos_interval(dgraph :&DGraph, tvd :rolling_inf::ObjectId) -> Opti
 fn plot_trains(history :&History, dgraph :&DGraph) -> Vec<TrainGraph> {
     let mut output = Vec::new();
     for (train_i, (name, params, events)) in history.trains.iter().enumerate() {
+        let mut params = *params;
         let mut segments =  Vec::new();
         use rolling::railway::dynamics::*;
         use rolling::output::history::*;
@@ -360,6 +394,7 @@ fn plot_trains(history :&History, dgraph :&DGraph) -> Vec<TrainGraph> {
                     prev_v = *v;
                     edge_x += dx;
                 },
+                TrainLogEvent::Couple(_,p) | TrainLogEvent::Split(_,p) => { params = *p; },
                 _ => {},
             }
         }
@@ -449,6 +484,7 @@ impl TrainInstant {
 pub fn draw_train(time :f64, history :&History, dgraph :&DGraph) -> Vec<TrainInstant> {
     let mut trains = Vec::new();
     for (train_i, (name, params, events)) in history.trains.iter().enumerate() {
+        let mut params = *params;
 
         use rolling::railway::dynamics::*;
         use rolling::output::history::*;
@@ -463,7 +499,7 @@ pub fn draw_train(time :f64, history :&History, dgraph :&DGraph) -> Vec<TrainIns
                 TrainLogEvent::Edge(a,b) => { edges.push(((*a,*b), 0.0, 0.0)); },
                 TrainLogEvent::Move(dt, action, DistanceVelocity { dx, v }) => {
                     let update_x = if t + *dt < time { *dx } else {
-                        dynamic_update(params, velocity, DriverPlan { action: *action, dt: time - t}).dx };
+                        dynamic_update(&params, velocity, DriverPlan { action: *action, dt: time - t}).dx };
                     edges.last_mut().unwrap().2 += update_x;
                     truncate_edge_list(&mut edges, params.length);
                     velocity = *v;
@@ -475,6 +511,8 @@ pub fn draw_train(time :f64, history :&History, dgraph :&DGraph) -> Vec<TrainIns
                         if *value { sighted.insert(*pta); } else { sighted.remove(pta); }
                     }
                 },
+                TrainLogEvent::Couple(_,p) | TrainLogEvent::Split(_,p) => { params = *p; },
+                TrainLogEvent::CoupledTo(_) => { edges.clear(); sighted.clear(); },
                 _ => {},
             }
 
@@ -1,5 +1,6 @@
 use rolling::input::staticinfrastructure as rolling_inf;
 pub use rolling::output::history::History;
+use std::sync::atomic::AtomicBool;
 
 use crate::document::model::*;
 use crate::document::interlocking::*;
@@ -13,11 +14,25 @@ pub fn convert_vehicle(vehicle :&Vehicle) -> rolling::railway::dynamics::TrainPa
     }
 }
 
+/// Trains in the history are named by the command which created them.
+pub fn train_name(cmd_id :usize) -> String {
+    format!("train{}", cmd_id)
+}
+
 pub type RouteRefs = Vec<(f32,usize)>;
 pub fn get_history<'a>(vehicles :&[(usize,Vehicle)], 
                    inf :&rolling_inf::StaticInfrastructure, 
                    il :&Interlocking,
                    commands :&[(usize, (f64, Command))]) -> Result<(History, RouteRefs) , String> {
+    get_history_cancellable(vehicles, inf, il, commands, None)
+}
+
+/// Returns an error if the simulation was cancelled.
+pub fn get_history_cancellable(vehicles :&[(usize,Vehicle)], 
+                   inf :&rolling_inf::StaticInfrastructure, 
+                   il :&Interlocking,
+                   commands :&[(usize, (f64, Command))],
+                   cancel :Option<&AtomicBool>) -> Result<(History, RouteRefs) , String> {
 
     // infrastructure and routes are already prepared by the dgraph module
     // we only need to convert commands to the rolling dispatch structs
@@ -28,7 +43,6 @@ pub fn get_history<'a>(vehicles :&[(usize,Vehicle)],
     let mut route_refs = Vec::new();
     let mut dispatch = Vec::new();
     let mut t0 = 0.0;
-    let mut train_no = 0;
     for (cmd_id,(t,c)) in commands {
         if *t > t0 {
             dispatch.push(DispatchAction::Wait(Some((t-t0) as _ )));
@@ -55,14 +69,17 @@ pub fn get_history<'a>(vehicles :&[(usize,Vehicle)],
 
                     let train_params = convert_vehicle(&vehicle);
 
-                    // just make some name for now
-                    let name = format!("train{}", train_no+1);
-                    train_no += 1;
-
+                    let name = train_name(*cmd_id);
                     dispatch.push(DispatchAction::Train(name, train_params, *route_idx));
                     route_refs.push((*t as f32, *route_idx));
                 }
             },
+            Command::Couple(front, rear) => {
+                dispatch.push(DispatchAction::Couple(train_name(*front), train_name(*rear)));
+            },
+            Command::Split(train, length) => {
+                dispatch.push(DispatchAction::Split(train_name(*train), train_name(*cmd_id), *length));
+            },
         }
     }
 
@@ -73,9 +90,10 @@ pub fn get_history<'a>(vehicles :&[(usize,Vehicle)],
 
     // TODO don't convert on the fly?
     //println!("Starting rolling");
-    let history = rolling::evaluate_plan(inf,
+    let history = rolling::evaluate_plan_cancellable(inf,
                                          &il.routes.iter().map(|r| r.route.clone()).enumerate().collect(),
-                                         &rolling::input::dispatch::Dispatch { actions: dispatch }, None);
+                                         &rolling::input::dispatch::Dispatch { actions: dispatch }, None,
+                                         cancel).ok_or(format!("Simulation cancelled"))?;
 
     //println!("History output: {:?}", history);
     // TODO Convert back? Or just keep it like this
@@ -1,10 +1,14 @@
-use std::collections::HashSet;
-use crate::document::model::{Ref, PtA, PtC, Pt};
+use std::collections::{HashMap, HashSet};
+use crate::document::model::{Ref, PtA, PtC, Pt, Model};
 use nalgebra_glm as glm;
 use crate::document::view::*;
 use crate::document::objects::*;
 use crate::document::dispatch;
 use crate::gui::ImVec2;
+use crate::document::analysis::Generation;
+use crate::document::layout::LayoutOptions;
+use crate::export::infrastructure::SchematicOptions;
+use std::sync::mpsc;
 
 #[derive(Debug)]
 pub struct InfView {
@@ -12,6 +16,15 @@ pub struct InfView {
     pub selection :HashSet<Ref>,
     pub view :View,
     pub instant_cache: dispatch::InstantCache,
+    /// World position where the context menu was opened.
+    pub context_pos :Option<PtC>,
+    pub export_options :SchematicOptions,
+    pub layout_options :LayoutOptions,
+    /// Running auto-layout, started from the given model generation.
+    pub layout_job :Option<(Generation, mpsc::Receiver<Result<(Model, HashMap<Pt,Pt>), String>>)>,
+    /// Objects drawn on top of the infrastructure without being part of the model,
+    /// e.g. a design selected in the signal designer.
+    pub preview_objects :Vec<Object>,
 }
 
 #[derive(Debug)]
@@ -41,6 +54,11 @@ impl InfView {
             selection: HashSet::new(),
             view: View::default(),
             instant_cache: dispatch::InstantCache::new(),
+            context_pos: None,
+            export_options: Default::default(),
+            layout_options: Default::default(),
+            layout_job: None,
+            preview_objects: Vec::new(),
         }
     }
 }
@@ -1,9 +1,11 @@
 use std::collections::HashMap;
+use serde::{Serialize,Deserialize};
 use rolling::input::staticinfrastructure as rolling_inf;
 use crate::document::model::*;
 use crate::document::dgraph::*;
 
 #[derive(Debug)]
+#[derive(Serialize,Deserialize)]
 pub struct Interlocking {
     pub routes: Vec<RouteInfo>,
     pub boundary_routes: HashMap<Pt, Vec<usize>>,
@@ -29,6 +31,7 @@ impl Interlocking {
 
 
 #[derive(Debug)]
+#[derive(Serialize,Deserialize)]
 pub struct RouteInfo {
     pub route :rolling_inf::Route,
     pub id :RouteSpec,
@@ -0,0 +1,89 @@
+use std::collections::HashSet;
+use crate::document::model::*;
+
+/// Parts of the model whose derived data must be recomputed after an edit.
+#[derive(Debug, Default)]
+pub struct Invalidated {
+    /// Topology, dgraph and interlocking, and everything computed from them.
+    pub infrastructure :bool,
+    pub vehicles :HashSet<ListId>,
+    pub dispatches :HashSet<ListId>,
+    pub plans :HashSet<ListId>,
+    pub timetables :HashSet<ListId>,
+}
+
+impl Invalidated {
+    pub fn all(model :&Model) -> Invalidated {
+        Invalidated {
+            infrastructure: true,
+            vehicles: model.vehicles.iter().map(|(i,_)| *i).collect(),
+            dispatches: model.dispatches.iter().map(|(i,_)| *i).collect(),
+            plans: model.plans.iter().map(|(i,_)| *i).collect(),
+            timetables: model.timetables.iter().map(|(i,_)| *i).collect(),
+        }
+    }
+}
+
+fn changed_ids<T :Clone>(old :&ImShortGenList<T>, new :&ImShortGenList<T>,
+                         same :impl Fn(&T,&T) -> bool) -> HashSet<ListId> {
+    let mut ids = HashSet::new();
+    for (i,t) in new.iter() {
+        if !old.get(*i).map(|o| same(o,t)).unwrap_or(false) { ids.insert(*i); }
+    }
+    for (i,_) in old.iter() {
+        if new.get(*i).is_none() { ids.insert(*i); }
+    }
+    ids
+}
+
+/// Vehicle used by a train, where `None` may mean any vehicle.
+fn uses_vehicle(vehicles :&HashSet<ListId>, vehicle :Option<ListId>) -> bool {
+    match vehicle {
+        Some(v) => vehicles.contains(&v),
+        None => !vehicles.is_empty(),
+    }
+}
+
+/// Compare two versions of the model and find the derived data which
+/// depends on the changed parts. Names are not used in any computation,
+/// so renaming invalidates nothing.
+pub fn invalidated(old :&Model, new :&Model) -> Invalidated {
+    let infrastructure = old.linesegs != new.linesegs ||
+        old.objects != new.objects ||
+        old.node_data != new.node_data ||
+        old.mileages != new.mileages ||
+        old.segment_lengths != new.segment_lengths ||
+        old.track_lengths != new.track_lengths;
+    if infrastructure { return Invalidated::all(new); }
+
+    let vehicles = changed_ids(&old.vehicles, &new.vehicles, |a,b| a == b);
+
+    let mut plans = changed_ids(&old.plans, &new.plans,
+                            |a,b| a.trains == b.trains && a.order == b.order && a.search == b.search);
+    for (i,plan) in new.plans.iter() {
+        if plan.trains.iter().any(|(_,(v,_))| uses_vehicle(&vehicles, *v)) {
+            plans.insert(*i);
+        }
+    }
+
+    let mut dispatches = changed_ids(&old.dispatches, &new.dispatches,
+                            |a,b| a.commands == b.commands && a.origin == b.origin);
+    for (i,dispatch) in new.dispatches.iter() {
+        let vehicle_changed = dispatch.commands.iter().any(|(_,(_,c))| match c {
+            Command::Train(v,_) => vehicles.contains(v),
+            _ => false,
+        });
+        let origin_changed = dispatch.origin.map(|o| plans.contains(&o.plan)).unwrap_or(false);
+        if vehicle_changed || origin_changed { dispatches.insert(*i); }
+    }
+
+    let mut timetables = changed_ids(&old.timetables, &new.timetables,
+                            |a,b| a.look_ahead == b.look_ahead && a.policy == b.policy && a.trains == b.trains);
+    for (i,timetable) in new.timetables.iter() {
+        if timetable.trains.iter().any(|(_,t)| uses_vehicle(&vehicles, t.vehicle)) {
+            timetables.insert(*i);
+        }
+    }
+
+    Invalidated { infrastructure, vehicles, dispatches, plans, timetables }
+}
@@ -0,0 +1,426 @@
+use std::collections::{HashMap, HashSet};
+use matches::matches;
+use nalgebra_glm as glm;
+use ordered_float::OrderedFloat;
+use railplotlib::model as plot;
+use railplotlib::solvers::{SchematicSolver, LevelsSatSolver, Goal, conv_line};
+
+use crate::document::model::*;
+use crate::document::topology::Topology;
+use crate::document::dgraph::DGraph;
+use crate::document::infview::round_coord;
+use crate::import::line_segments;
+use crate::util;
+
+type TrackEnd = (usize, AB);
+
+/// Options for the automatic layout.
+#[derive(Debug, Clone)]
+pub struct LayoutOptions {
+    /// Optimization goals in lexicographic order, and whether each goal is used.
+    pub goals :Vec<(Goal, bool)>,
+    /// Tracks (by their end nodes) which should be drawn close together.
+    pub main_tracks :HashSet<(Pt,Pt)>,
+    /// Nodes with fixed x and/or y coordinates.
+    pub pinned :HashMap<Pt, (bool,bool)>,
+}
+
+impl Default for LayoutOptions {
+    fn default() -> Self {
+        LayoutOptions {
+            goals: vec![(Goal::Bends, true), (Goal::Height, true), (Goal::Width, true),
+                        (Goal::LocalX, true), (Goal::LocalY, true), (Goal::MainTrackHeight, false),
+                        (Goal::Diagonals, false), (Goal::Nodeshapes, false), (Goal::Shortedges, false)],
+            main_tracks: HashSet::new(),
+            pinned: HashMap::new(),
+        }
+    }
+}
+
+impl LayoutOptions {
+    pub fn criteria(&self) -> Vec<Goal> {
+        self.goals.iter().filter(|(_,used)| *used).map(|(g,_)| *g).collect()
+    }
+
+    /// Follow nodes moved by a layout.
+    pub fn moved(&mut self, nodes :&HashMap<Pt,Pt>) {
+        let m = |p :&Pt| *nodes.get(p).unwrap_or(p);
+        self.pinned = self.pinned.iter().map(|(p,x)| (m(p),*x)).collect();
+        self.main_tracks = self.main_tracks.iter().map(|(a,b)| util::order_ivec(m(a),m(b))).collect();
+    }
+}
+
+pub fn goal_name(goal :Goal) -> &'static str {
+    match goal {
+        Goal::Width => "Width",
+        Goal::Height => "Height",
+        Goal::Bends => "Bends",
+        Goal::Diagonals => "Diagonal edges",
+        Goal::Nodeshapes => "Slanted nodes",
+        Goal::Shortedges => "Short edges",
+        Goal::LocalY => "Local vertical distance",
+        Goal::LocalX => "Local horizontal distance",
+        Goal::MainTrackHeight => "Main track height",
+    }
+}
+
+/// Tracks to lay out and the nodes at their ends. Nodes which also connect
+/// to tracks outside the selection keep their position.
+struct Selected {
+    tracks :Vec<usize>,
+    nodes :Vec<Pt>,
+    fixed :HashSet<Pt>,
+}
+
+/// Grid points of a track, from end A to end B.
+fn track_points(topo :&Topology, track :usize) -> Vec<Pt> {
+    let mut pts = topo.interval_lines[track].iter()
+        .map(|(_,p)| glm::vec2(p.x.round() as i32, p.y.round() as i32)).collect::<Vec<Pt>>();
+    if pts.first() != Some(&(topo.tracks[track].1).0) { pts.reverse(); }
+    pts
+}
+
+fn track_segments(topo :&Topology, track :usize) -> Vec<(Pt,Pt)> {
+    let pts = track_points(topo, track);
+    pts.iter().zip(pts.iter().skip(1)).map(|(a,b)| util::order_ivec(*a,*b)).collect()
+}
+
+fn end_pt(topo :&Topology, (track,ab) :TrackEnd) -> Pt {
+    let (_,a,b) = &topo.tracks[track];
+    match ab { AB::A => a.0, AB::B => b.0 }
+}
+
+fn is_selected(topo :&Topology, selection :&HashSet<Ref>, track :usize) -> bool {
+    let (_,(a,_),(b,_)) = &topo.tracks[track];
+    track_segments(topo, track).iter().any(|(p,q)| selection.contains(&Ref::LineSeg(*p,*q))) ||
+        (selection.contains(&Ref::Node(*a)) && selection.contains(&Ref::Node(*b)))
+}
+
+/// End nodes of the selected tracks.
+pub fn selected_tracks(topo :&Topology, selection :&HashSet<Ref>) -> Vec<(Pt,Pt)> {
+    (0..topo.tracks.len()).filter(|i| is_selected(topo, selection, *i))
+        .map(|i| util::order_ivec((topo.tracks[i].1).0, (topo.tracks[i].2).0)).collect()
+}
+
+fn select(topo :&Topology, selection :Option<&HashSet<Ref>>) -> Result<Selected, String> {
+    let tracks = (0..topo.tracks.len())
+        .filter(|i| selection.map(|s| is_selected(topo, s, *i)).unwrap_or(true)).collect::<Vec<_>>();
+    if tracks.is_empty() { return Err(format!("No tracks selected.")); }
+
+    let mut total :HashMap<Pt,usize> = HashMap::new();
+    for (_,(a,_),(b,_)) in topo.tracks.iter() {
+        *total.entry(*a).or_insert(0) += 1;
+        *total.entry(*b).or_insert(0) += 1;
+    }
+    let mut selected :HashMap<Pt,usize> = HashMap::new();
+    for i in tracks.iter() {
+        let (_,(a,_),(b,_)) = &topo.tracks[*i];
+        *selected.entry(*a).or_insert(0) += 1;
+        *selected.entry(*b).or_insert(0) += 1;
+    }
+
+    let mut nodes = selected.keys().cloned().collect::<Vec<_>>();
+    nodes.sort_by_key(|p| (p.x,p.y));
+    let mut fixed = HashSet::new();
+    for pt in nodes.iter() {
+        let n = selected[pt];
+        if n == total[pt] { continue; }
+        if n > 1 {
+            return Err(format!("The node at ({}, {}) connects selected and unselected tracks. \
+                                Select all of its tracks or only one of them.", pt.x, pt.y));
+        }
+        fixed.insert(*pt);
+    }
+    Ok(Selected { tracks, nodes, fixed })
+}
+
+/// Node mileages from the dgraph, or the x coordinates if some node has no mileage.
+fn node_mileages(nodes :&[Pt], dgraph :Option<&DGraph>) -> HashMap<Pt,f64> {
+    let kms = dgraph.and_then(|d| nodes.iter().map(|pt| {
+        d.node_ids.get_by_right(pt).and_then(|n| d.mileage.get(n)).map(|km| (*pt,*km))
+    }).collect::<Option<HashMap<_,_>>>());
+    kms.unwrap_or_else(|| nodes.iter().map(|pt| (*pt, pt.x as f64)).collect())
+}
+
+/// Convert the selected tracks to railplotlib's schematic graph. Edges are
+/// in the order of `sel.tracks`, and the returned flags tell which edges go
+/// from the track's B end to its A end.
+fn schematic_graph(topo :&Topology, sel :&Selected, km :&HashMap<Pt,f64>,
+                   pins :&HashMap<Pt,(bool,bool)>, main_tracks :&HashSet<(Pt,Pt)>)
+    -> Result<(plot::SchematicGraph<()>, Vec<bool>), String> {
+    let key = |pt :Pt| (OrderedFloat(km[&pt]), pt.x, pt.y);
+    let name = |pt :Pt| format!("n{}", sel.nodes.iter().position(|p| *p == pt).unwrap());
+    let other_end = |(t,ab) :TrackEnd| end_pt(topo, (t, ab.other()));
+    let err_node = |pt :&Pt| format!("Unrecognized node at ({}, {}).", pt.x, pt.y);
+    let err_dir = |pt :&Pt| format!("Mileage changes direction at ({}, {}).", pt.x, pt.y);
+
+    let mut ends :HashMap<Pt, Vec<(TrackEnd, Port)>> = HashMap::new();
+    for t in sel.tracks.iter() {
+        let (_,(a,pa),(b,pb)) = &topo.tracks[*t];
+        ends.entry(*a).or_insert(Vec::new()).push(((*t,AB::A),*pa));
+        ends.entry(*b).or_insert(Vec::new()).push(((*t,AB::B),*pb));
+    }
+
+    let mut nodes = Vec::new();
+    let mut ports :HashMap<TrackEnd, plot::Port> = HashMap::new();
+    for pt in sel.nodes.iter() {
+        let conns = &ends[pt];
+        // Does the track from this end go towards increasing mileage?
+        let up = |e :TrackEnd| key(other_end(e)) > key(*pt);
+        let ndtype = if sel.fixed.contains(pt) { NDType::OpenEnd }
+                     else { topo.locations.get(pt).map(|(t,_)| *t).unwrap_or(NDType::Err) };
+        let shape = match ndtype {
+            NDType::OpenEnd | NDType::BufferStop => {
+                let (e,_) = conns[0];
+                if up(e) { ports.insert(e, plot::Port::Out); plot::Shape::Begin }
+                else     { ports.insert(e, plot::Port::In);  plot::Shape::End }
+            },
+            NDType::Cont => {
+                if conns.iter().filter(|(e,_)| up(*e)).count() != 1 { return Err(err_dir(pt)); }
+                for (e,_) in conns.iter() {
+                    ports.insert(*e, if up(*e) { plot::Port::Out } else { plot::Port::In });
+                }
+                plot::Shape::Continuation
+            },
+            NDType::Sw(side) => {
+                let (trunk,_) = conns.iter().find(|(_,p)| matches!(p, Port::Trunk)).ok_or(err_node(pt))?;
+                for (e,p) in conns.iter() {
+                    if *e != *trunk && up(*e) == up(*trunk) { return Err(err_dir(pt)); }
+                    ports.insert(*e, match p {
+                        Port::Trunk => plot::Port::Trunk,
+                        Port::Left => plot::Port::Left,
+                        Port::Right => plot::Port::Right,
+                        _ => return Err(err_node(pt)),
+                    });
+                }
+                let side = match side { Side::Left => plot::Side::Left, Side::Right => plot::Side::Right };
+                plot::Shape::Switch(side, if up(*trunk) { plot::Dir::Down } else { plot::Dir::Up })
+            },
+            NDType::Crossing(_) => {
+                let mut pairs = Vec::new();
+                for n in 0..2 {
+                    let pair = conns.iter().filter(|(_,p)| matches!(p, Port::Cross(_,m) if *m == n))
+                        .map(|(e,_)| *e).collect::<Vec<_>>();
+                    if pair.len() != 2 { return Err(err_node(pt)); }
+                    let (inc,out) = if up(pair[0]) { (pair[1],pair[0]) } else { (pair[0],pair[1]) };
+                    if up(inc) || !up(out) { return Err(err_dir(pt)); }
+                    pairs.push((inc,out));
+                }
+                // The incoming track coming from below continues to the upper left port.
+                let adjacent_y = |(t,ab) :TrackEnd| {
+                    let pts = track_points(topo, t);
+                    match ab { AB::A => pts[1].y, AB::B => pts[pts.len()-2].y }
+                };
+                pairs.sort_by_key(|(inc,_)| adjacent_y(*inc));
+                ports.insert(pairs[0].0, plot::Port::InLeft);
+                ports.insert(pairs[0].1, plot::Port::OutLeft);
+                ports.insert(pairs[1].0, plot::Port::InRight);
+                ports.insert(pairs[1].1, plot::Port::OutRight);
+                plot::Shape::Crossing
+            },
+            NDType::Err => return Err(err_node(pt)),
+        };
+        nodes.push(plot::Node { name: name(*pt), pos: km[pt], shape });
+    }
+
+    let mut reversed = Vec::new();
+    let mut edges = Vec::new();
+    for t in sel.tracks.iter() {
+        let (ea,eb) = ((*t,AB::A),(*t,AB::B));
+        let rev = key(end_pt(topo,ea)) > key(end_pt(topo,eb));
+        let (ea,eb) = if rev { (eb,ea) } else { (ea,eb) };
+        reversed.push(rev);
+        edges.push(plot::Edge {
+            a: (name(end_pt(topo,ea)), ports[&ea]),
+            b: (name(end_pt(topo,eb)), ports[&eb]),
+            objects: Vec::new(),
+        });
+    }
+
+    let main_tracks_edges = sel.tracks.iter().enumerate()
+        .filter(|(_,t)| main_tracks.contains(&util::order_ivec((topo.tracks[**t].1).0, (topo.tracks[**t].2).0)))
+        .map(|(k,_)| k).collect();
+    let fixed_nodes = pins.iter().map(|(pt,(x,y))| (name(*pt), plot::FixedPos {
+        x: if *x { Some(pt.x as f64) } else { None },
+        y: if *y { Some(pt.y as f64) } else { None },
+    })).collect();
+
+    Ok((plot::SchematicGraph { nodes, edges, main_tracks_edges, fixed_nodes }, reversed))
+}
+
+fn round_pt((x,y) :(f64,f64)) -> Result<(i32,i32), String> {
+    let tol = 0.05;
+    if (x.round() - x).abs() > tol || (y.round() - y).abs() > tol {
+        return Err(format!("Solution contains point not on grid."));
+    }
+    Ok((x.round() as i32, y.round() as i32))
+}
+
+/// Map the solver's x coordinates to model x coordinates so that each anchor
+/// `(solver x, model x)` lands on its model x, by widening the column after
+/// each anchor. Anchors must be sorted by solver x.
+fn map_x(anchors :&[(i32,i32)], x :i32) -> i32 {
+    match anchors.iter().rposition(|(s,_)| *s <= x) {
+        None => anchors[0].1 + (x - anchors[0].0),
+        Some(k) => {
+            let (s,t) = anchors[k];
+            if s == x { return t; }
+            let extra = anchors.get(k+1).map(|(s1,t1)| (t1 - t) - (s1 - s)).unwrap_or(0);
+            t + extra + (x - s)
+        },
+    }
+}
+
+/// Point and unit normal at the fraction `f` of a line given as grid segments.
+fn point_along(segs :&[(Pt,Pt)], f :f64) -> (PtC, PtC) {
+    let s = f.max(0.0).min(1.0) * segs.len() as f64;
+    let i = (s.floor() as usize).min(segs.len() - 1);
+    let (a,b) = segs[i];
+    let (a,b) = (glm::vec2(a.x as f32, a.y as f32), glm::vec2(b.x as f32, b.y as f32));
+    let tangent = glm::normalize(&(b - a));
+    (glm::lerp(&a, &b, (s - i as f64) as f32), glm::vec2(-tangent.y, tangent.x))
+}
+
+/// Lay out the tracks of the model (or only the selected tracks) using
+/// railplotlib, keeping the mileage order of nodes and the positions of
+/// objects along their tracks. Nodes connecting the selection to the rest of
+/// the model stay where they are, as do pinned coordinates. Returns the new
+/// model and the new position of each node.
+pub fn auto_layout(model :&Model, topo :&Topology, dgraph :Option<&DGraph>,
+                   selection :Option<&HashSet<Ref>>, options :&LayoutOptions)
+    -> Result<(Model, HashMap<Pt,Pt>), String> {
+    let sel = select(topo, selection)?;
+    let km = node_mileages(&sel.nodes, dgraph);
+    let mut pins = sel.nodes.iter().filter_map(|pt| options.pinned.get(pt).map(|p| (*pt,*p)))
+        .collect::<HashMap<_,_>>();
+    for pt in sel.fixed.iter() { pins.insert(*pt, (true,true)); }
+    let (graph, reversed) = schematic_graph(topo, &sel, &km, &pins, &options.main_tracks)?;
+
+    let criteria = options.criteria();
+    if criteria.is_empty() { return Err(format!("No layout goals selected.")); }
+    let solver = LevelsSatSolver { criteria, nodes_distinct: false };
+    let output = solver.solve(graph)?;
+
+    let coords = output.nodes.iter().map(|(_,pt)| round_pt(*pt)).collect::<Result<Vec<_>,_>>()?;
+    let node_idx = |name :&str| -> usize { name[1..].parse().unwrap() };
+
+    // Anchor the layout at the fixed nodes, or at the old bounding box.
+    let fixed = |pin :fn(&(bool,bool)) -> bool| {
+        let mut v = sel.nodes.iter().zip(coords.iter())
+            .filter(|(pt,_)| pins.get(*pt).map(pin).unwrap_or(false))
+            .map(|(pt,(x,y))| ((*x,*y),*pt)).collect::<Vec<_>>();
+        v.sort_by_key(|((x,_),pt)| (*x,pt.x));
+        v
+    };
+    let (fixed_x, fixed_y) = (fixed(|p| p.0), fixed(|p| p.1));
+    let anchors = if fixed_x.is_empty() {
+        let min_x = coords.iter().map(|(x,_)| *x).min().unwrap();
+        vec![(min_x, sel.nodes.iter().map(|p| p.x).min().unwrap())]
+    } else {
+        for w in fixed_x.windows(2) {
+            let (((s0,_),t0),((s1,_),t1)) = (w[0],w[1]);
+            if t1.x - t0.x < s1 - s0 || (s0 == s1 && t0.x != t1.x) {
+                return Err(format!("The tracks do not fit between the fixed nodes."));
+            }
+        }
+        fixed_x.iter().map(|((x,_),pt)| (*x,pt.x)).collect::<Vec<_>>()
+    };
+    let dy = if fixed_y.is_empty() {
+        sel.nodes.iter().map(|p| p.y).min().unwrap() - coords.iter().map(|(_,y)| *y).min().unwrap()
+    } else {
+        let dy = (fixed_y[0].1).y - (fixed_y[0].0).1;
+        if fixed_y.iter().any(|((_,y),pt)| y + dy != pt.y) {
+            return Err(format!("The tracks cannot be laid out without moving the fixed nodes. \
+                                Try selecting a larger part of the model."));
+        }
+        dy
+    };
+    let new_pts = coords.iter().map(|(x,y)| glm::vec2(map_x(&anchors, *x), y + dy)).collect::<Vec<Pt>>();
+
+    // Grid segments of each track from end A to end B.
+    let mut lines :Vec<Vec<(Pt,Pt)>> = Vec::new();
+    for (k,(edge,pts)) in output.lines.iter().enumerate() {
+        let (a,b) = (new_pts[node_idx(&edge.a.0)], new_pts[node_idx(&edge.b.0)]);
+        let level = round_pt(pts[1])?.1 + dy;
+        let polyline = conv_line((a.x as f64, a.y as f64), level as f64, (b.x as f64, b.y as f64))
+            .into_iter().map(round_pt).collect::<Result<Vec<_>,_>>()?;
+        let mut segs = Vec::new();
+        for (p,q) in polyline.iter().zip(polyline.iter().skip(1)) {
+            segs.extend(line_segments(glm::vec2(p.0,p.1), glm::vec2(q.0,q.1))
+                .map_err(|_| format!("Line segment conversion failed."))?);
+        }
+        if reversed[k] {
+            segs = segs.into_iter().rev().map(|(p,q)| (q,p)).collect();
+        }
+        lines.push(segs);
+    }
+
+    let mut new_model = model.clone();
+    for (k,t) in sel.tracks.iter().enumerate() {
+        // Tracks with a given length keep their current length on the new segments.
+        let mut has_length = false;
+        for (a,b) in track_segments(topo, *t) {
+            new_model.linesegs.remove(&(a,b));
+            new_model.linesegs.remove(&(b,a));
+            has_length |= new_model.track_lengths.remove(&(a,b)).is_some();
+            has_length |= new_model.segment_lengths.remove(&(a,b)).is_some();
+        }
+        if let (true, Some((a,b))) = (has_length, lines[k].first()) {
+            new_model.track_lengths.insert(util::order_ivec(*a,*b), topo.tracks[*t].0);
+        }
+    }
+
+    if !sel.fixed.is_empty() {
+        let occupied = new_model.linesegs.iter().flat_map(|(a,b)| vec![*a,*b]).collect::<HashSet<Pt>>();
+        if lines.iter().flat_map(|l| l.iter()).flat_map(|(a,b)| vec![*a,*b])
+            .any(|p| occupied.contains(&p) && !sel.fixed.contains(&p)) {
+            return Err(format!("The new layout overlaps the surrounding tracks."));
+        }
+    }
+
+    for (a,b) in lines.iter().flat_map(|l| l.iter()) {
+        new_model.linesegs.insert(util::order_ivec(*a,*b));
+    }
+
+    let moved = sel.nodes.iter().zip(new_pts.iter())
+        .filter(|(pt,_)| !sel.fixed.contains(*pt)).collect::<Vec<_>>();
+    let node_map = moved.iter().map(|(a,b)| (**a,**b)).collect::<HashMap<Pt,Pt>>();
+    let node_data = moved.iter().filter_map(|(old,new)| model.node_data.get(*old).map(|d| (**new,*d)))
+        .collect::<Vec<_>>();
+    for (old,_) in moved.iter() { new_model.node_data.remove(*old); }
+    for (pt,d) in node_data { new_model.node_data.insert(pt,d); }
+    let mileages = moved.iter().filter_map(|(old,new)| model.mileages.get(&Ref::Node(**old)).map(|m| (**new,*m)))
+        .collect::<Vec<_>>();
+    for (old,_) in moved.iter() { new_model.mileages.remove(&Ref::Node(**old)); }
+    for (pt,m) in mileages { new_model.mileages.insert(Ref::Node(pt),m); }
+
+    // Put objects at the same relative position along their track, on the same side.
+    let mut objects :HashMap<PtA, (usize, f64, Option<AB>)> = HashMap::new();
+    for (k,t) in sel.tracks.iter().enumerate() {
+        let length = topo.tracks[*t].0;
+        for (pos, id, _, dir) in topo.trackobjects[*t].iter() {
+            let entry = objects.entry(*id).or_insert((k, pos/length, None));
+            if dir.is_some() { entry.2 = *dir; }
+        }
+    }
+    for id in objects.keys() { new_model.objects.remove(id); }
+    for (id,(k,f,dir)) in objects {
+        let mut obj = match model.objects.get(&id) { Some(o) => o.clone(), None => continue };
+        let (pt,normal) = point_along(&lines[k], f);
+        let pt = match dir {
+            Some(AB::B) => pt + 0.25*normal,
+            Some(AB::A) => pt - 0.25*normal,
+            None => pt,
+        };
+        obj.move_to(&new_model, pt);
+        if let Some(m) = new_model.mileages.remove(&Ref::Object(id)) {
+            new_model.mileages.insert(Ref::Object(round_coord(obj.loc)), m);
+        }
+        if let Some(c) = new_model.synthesis_constraints.remove(&id) {
+            new_model.synthesis_constraints.insert(round_coord(obj.loc), c);
+        }
+        new_model.objects.insert(round_coord(obj.loc), obj);
+    }
+
+    Ok((new_model, node_map))
+}
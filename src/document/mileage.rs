@@ -3,7 +3,7 @@ use std::collections::{HashMap, HashSet};
 use matches::matches;
 use rolling::input::staticinfrastructure::*;
 use petgraph::unionfind::UnionFind;
-use crate::document::model::Pt;
+use crate::document::model::{Pt, MileageSpec};
 
 
 fn take_boundary(node_ids :&BiMap<NodeId, Pt>, boundaries :&mut HashSet<NodeId>) -> Option<NodeId> {
@@ -13,29 +13,52 @@ fn take_boundary(node_ids :&BiMap<NodeId, Pt>, boundaries :&mut HashSet<NodeId>)
     } else { None }
 }
 
-pub fn auto(node_ids :&BiMap<NodeId,Pt>, inf :&StaticInfrastructure) -> HashMap<NodeId, f64> {
+/// Estimate the mileage of each node in the graph from the edge lengths.
+/// The `specs` give user-defined fixed mileages, direction reversals and
+/// chainage discontinuities at graph nodes (either node of a node pair).
+pub fn auto(node_ids :&BiMap<NodeId,Pt>, inf :&StaticInfrastructure, 
+            specs :&HashMap<NodeId, MileageSpec>) -> HashMap<NodeId, f64> {
+    let spec_at = |n :NodeId| specs.get(&n).or_else(|| specs.get(&inf.nodes[n].other_node)).cloned();
     let mut boundaries : HashSet<NodeId> = inf.nodes.iter().enumerate().filter_map(|(i,n)| {
         if matches!(n.edges, Edges::ModelBoundary) { Some(i) } else { None } }).collect();
-    // TODO select leftmost boundaries first
     // TODO match unconnected components' km by x coordinate?
 
+    // First guess of the km by traversing from the leftmost boundary of each
+    // connected component. The direction `dir` is +1 when the km increases
+    // along the node's outgoing edges.
     let mut edges : Vec<(NodeId,NodeId,f64)> = Vec::new();
     let mut km0 : HashMap<NodeId,f64> = HashMap::new();
+    let mut dirs : HashMap<NodeId,isize> = HashMap::new();
+    let mut component : HashMap<NodeId,usize> = HashMap::new();
     let mut uf = UnionFind::new(inf.nodes.len());
-    let mut fixed = Vec::new();
+    let mut anchors = Vec::new();
     while let Some(boundary) = take_boundary(node_ids, &mut boundaries) {
-        fixed.push(boundary);
+        let comp = anchors.len();
+        anchors.push(boundary);
         let mut stack = vec![(boundary, 0.0, -1)];
         while let Some((node,pos,dir)) = stack.pop() {
             if km0.get(&node).is_some() { continue; }
             km0.insert(node,pos);
-            stack.push((inf.nodes[node].other_node, pos, -dir));
-            uf.union(node, inf.nodes[node].other_node);
+            dirs.insert(node,dir);
+            component.insert(node,comp);
+            let other = inf.nodes[node].other_node;
+            match spec_at(node) {
+                Some(MileageSpec::Reversal) => {
+                    stack.push((other, pos, dir));
+                    edges.push((node, other, 0.0));
+                },
+                Some(MileageSpec::Jump(from,to)) => {
+                    stack.push((other, pos - (dir as f64)*(to - from), -dir));
+                },
+                _ => {
+                    stack.push((other, pos, -dir));
+                    uf.union(node, other);
+                },
+            }
             if matches!(inf.nodes[node].edges, Edges::ModelBoundary) { boundaries.remove(&node); }
             match inf.nodes[node].edges {
                 Edges::Single(b,d) => {
                     stack.push((b, pos + (dir as f64)*d, -dir));
-                    //println!("edge from {} to {} dir {}", node, b, dir);
                     if !km0.contains_key(&b) { // TODO this smells a little
                         edges.push((node,b,(dir as f64)*d));
                     }
@@ -54,106 +77,170 @@ pub fn auto(node_ids :&BiMap<NodeId,Pt>, inf :&StaticInfrastructure) -> HashMap<
         }
     }
 
+    // Use the fixed mileages to decide the direction of each component.
+    for comp in 0..anchors.len() {
+        let pairs = km0.iter().filter(|(n,_)| component[*n] == comp)
+            .filter_map(|(n,km)| match spec_at(*n) {
+                Some(MileageSpec::Fixed(x)) => Some((*km,x)),
+                _ => None,
+            }).collect::<Vec<_>>();
+        if pairs.len() < 2 { continue; }
+        let (m0,m1) = (pairs.iter().map(|p| p.0).sum::<f64>() / pairs.len() as f64,
+                       pairs.iter().map(|p| p.1).sum::<f64>() / pairs.len() as f64);
+        let cov = pairs.iter().map(|(a,b)| (a-m0)*(b-m1)).sum::<f64>();
+        if cov < 0.0 {
+            for (n,c) in component.iter() { if *c == comp { *dirs.get_mut(n).unwrap() *= -1; } }
+            for e in edges.iter_mut() { if component[&e.0] == comp { e.2 *= -1.0; } }
+        }
+    }
 
-    // Now we have a first guess of the km, 
-    // a list of edges,
-    // and a map from original NodeIds to unknown variables
+    // Known values for the variables (the union-find classes), from fixed mileages and 
+    // from both sides of chainage discontinuities.
+    let mut known : HashMap<NodeId,f64> = HashMap::new();
+    for n in km0.keys() {
+        match spec_at(*n) {
+            Some(MileageSpec::Fixed(x)) => { known.insert(uf.find_mut(*n), x); },
+            Some(MileageSpec::Jump(from,to)) => {
+                known.insert(uf.find_mut(*n), if dirs[n] > 0 { to } else { from });
+            },
+            _ => {},
+        }
+    }
 
-    //println!("edges {:?}", edges);
+    // Components without any known value are anchored at zero on their first boundary.
+    let mut known_comps = HashSet::new();
+    for n in km0.keys() {
+        if known.contains_key(&uf.find_mut(*n)) { known_comps.insert(component[n]); }
+    }
+    let anchors = anchors.into_iter().enumerate()
+        .filter(|(c,_)| !known_comps.contains(c))
+        .map(|(_,n)| n).collect::<Vec<_>>();
 
     let mut varmap : HashMap<NodeId, usize> = HashMap::new();
-    let mut varidx = -1isize;
-    let mut fixed :HashMap<NodeId, Option<usize>> = fixed.into_iter().map(|n| (n,None)).collect();
-    for (n,km) in km0.iter() {
-        let v = varmap.entry(uf.find_mut(*n)).or_insert_with(|| { varidx += 1; varidx as usize });
-        fixed.entry(*n).and_modify(|nvar| { *nvar = Some(*v); });
+    for n in km0.keys() {
+        let v = uf.find_mut(*n);
+        if known.contains_key(&v) { continue; }
+        let idx = varmap.len();
+        varmap.entry(v).or_insert(idx);
     }
 
-    let mut rhs : Vec<f64> = edges.iter().map(|(_,_,d)| *d).collect();
-    for (_,km) in fixed.iter() { rhs.push(0.0); } // TODO fixed positions can be != 0.0 as well
-
-
-    //println!("varmap {:?}", varmap);
-    //println!("fixed {:?}", fixed);
-    //println!("rhs {:?}", rhs);
-    //for (n,var) in &varmap {
-        //println!("var {:03}/{:03} km {:.3}", n, var, km0[n]);
-    //}
-
-     let params = lsqr::Params {
-         damp: 0.0,
-         rel_mat_err: 1e-6,
-         rel_rhs_err: 1e-6,
-         condlim :0.0,
-         iterlim: inf.nodes.len(), 
-     };
-
-    // Now we have a first guess of the km, indexed by actual variables to solve
-     let (sol,stats) = lsqr::lsqr(|msg| {}, //println!("{}", msg),
-                edges.len() + fixed.len(), varmap.len(), 
-                params,
-                |prod| {
-                    match prod {
-                        lsqr::Product::YAddAx { x, y } => {
-                            //println!("YAddAx");
-                            //println!("x = {:?}", x);
-                            //println!("y = {:?}", y);
-                            // compute y += A * x
-                            // y contains edge adjustments(?), x contains node kms
-                            // [n_e +1, n_n] * [n_n, 1] = [n_e +1, 1]
-                           for (e, (a,b,d)) in edges.iter().enumerate() {
-                               let (a,b) = (varmap[&uf.find_mut(*a)], varmap[&uf.find_mut(*b)]);
-                               y[e] += x[b] - x[a];
-                           }
-                           for (i,(_,v)) in fixed.iter().enumerate() { if let Some(var) = v { 
-                               //println!("edges.len()  = {}", edges.len());
-                               //println!("i = {}", i);
-                               //println!("var = {}", *var);
-                               y[edges.len() + i] += x[*var]; 
-                           } }
-                             //println!("YAddAx end");
-                            //println!("x = {:?}", x);
-                            //println!("y = {:?}", y);
-                        },
-                        lsqr::Product::XAddATy { x, y } => {
-                            //println!("XAddATy");
-                            //println!("x = {:?}", x);
-                            //println!("y = {:?}", y);
-                            // compute x += A^T * y
-                            // y contains something, 
-                            for (e, (a,b,d)) in edges.iter().enumerate() {
-                                let (a,b) = (varmap[&uf.find_mut(*a)], varmap[&uf.find_mut(*b)]);
-                                x[a] += -1. * y[e];
-                                x[b] +=  1. * y[e];
-                            }
-                           for (i,(_,v)) in fixed.iter().enumerate() { if let Some(var) = v { 
-                                x[*var] += y[edges.len() + i] ;
-                           } }
-                            //println!("XAddATy end");
-                            //println!("x = {:?}", x);
-                            //println!("y = {:?}", y);
-                        },
+    // Sparse rows of the equation system with the known values moved to the right hand side.
+    let mut rows :Vec<Vec<(usize,f64)>> = Vec::new();
+    let mut rhs :Vec<f64> = Vec::new();
+    for (a,b,d) in edges.iter() {
+        let (a,b) = (uf.find_mut(*a), uf.find_mut(*b));
+        let mut row = Vec::new();
+        let mut r = *d;
+        match varmap.get(&a) { Some(v) => row.push((*v,-1.0)), None => r += known[&a] };
+        match varmap.get(&b) { Some(v) => row.push((*v, 1.0)), None => r -= known[&b] };
+        if row.is_empty() { continue; }
+        rows.push(row);
+        rhs.push(r);
+    }
+    for n in anchors.iter() {
+        if let Some(v) = varmap.get(&uf.find_mut(*n)) {
+            rows.push(vec![(*v,1.0)]);
+            rhs.push(0.0);
+        }
+    }
+
+    let sol = if varmap.is_empty() { Vec::new() } else {
+        let params = lsqr::Params {
+            damp: 0.0,
+            rel_mat_err: 1e-6,
+            rel_rhs_err: 1e-6,
+            condlim :0.0,
+            iterlim: inf.nodes.len(), 
+        };
+        let (sol,_stats) = lsqr::lsqr(|_msg| {}, rows.len(), varmap.len(), params,
+            |prod| match prod {
+                lsqr::Product::YAddAx { x, y } => {
+                    for (i,row) in rows.iter().enumerate() {
+                        for (v,c) in row { y[i] += c * x[*v]; }
                     }
                 },
-                &mut rhs);
-
-     //println!("sol {:?}", sol);
-
-     // This should be our solution, now we can map back to node kms
-     let km :HashMap<NodeId,f64> = km0.into_iter().map(|(n,_)| (n, sol[varmap[&uf.find_mut(n)]])).collect();
+                lsqr::Product::XAddATy { x, y } => {
+                    for (i,row) in rows.iter().enumerate() {
+                        for (v,c) in row { x[*v] += c * y[i]; }
+                    }
+                },
+            }, &mut rhs);
+        sol
+    };
+
+    km0.into_iter().map(|(n,_)| {
+        let v = uf.find_mut(n);
+        (n, match varmap.get(&v) { Some(i) => sol[*i], None => known[&v] })
+    }).collect()
+}
 
-     let mut v = km.iter().collect::<Vec<_>>();
-     v.sort_by_key(|a| a.0);
-     for (n,k) in v {
-         if n%2 == 0 {
-             //println!("node {:03} km {:.3}", n, k);
-         }
-     }
+pub fn format_mileage(m :f64) -> String {
+    format!("km {:.3}", m / 1000.0)
+}
 
-     //println!("solver stats {:#?}", stats);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm as glm;
+
+    /// A line of three node pairs, 1000 m and 500 m apart, with boundaries
+    /// at node 0 (left) and node 5 (right).
+    fn line() -> (BiMap<NodeId,Pt>, StaticInfrastructure) {
+        let node = |other_node, edges| Node { other_node, edges, objects: Default::default() };
+        let inf = StaticInfrastructure {
+            nodes: vec![
+                node(1, Edges::ModelBoundary),
+                node(0, Edges::Single(2, 1000.0)),
+                node(3, Edges::Single(1, 1000.0)),
+                node(2, Edges::Single(4, 500.0)),
+                node(5, Edges::Single(3, 500.0)),
+                node(4, Edges::ModelBoundary),
+            ],
+            objects: vec![],
+        };
+        let mut node_ids = BiMap::new();
+        node_ids.insert(0, glm::vec2(0,0));
+        node_ids.insert(5, glm::vec2(20,0));
+        (node_ids, inf)
+    }
 
-     km
-}
+    fn assert_km(km :&HashMap<NodeId,f64>, expected :&[f64]) {
+        for (n,x) in expected.iter().enumerate() {
+            assert!((km[&n] - x).abs() < 1e-3, "node {}: {} != {}", n, km[&n], x);
+        }
+    }
 
+    #[test]
+    fn fixed_node_pins_offset() {
+        let (node_ids, inf) = line();
+        let km = auto(&node_ids, &inf, &HashMap::new());
+        assert_km(&km, &[0.0, 0.0, 1000.0, 1000.0, 1500.0, 1500.0]);
+
+        let specs = vec![(2, MileageSpec::Fixed(5000.0))].into_iter().collect();
+        let km = auto(&node_ids, &inf, &specs);
+        assert_km(&km, &[4000.0, 4000.0, 5000.0, 5000.0, 5500.0, 5500.0]);
+
+        // Decreasing fixed mileages turn the direction of the line.
+        let specs = vec![(0, MileageSpec::Fixed(10000.0)),
+                         (5, MileageSpec::Fixed(8500.0))].into_iter().collect();
+        let km = auto(&node_ids, &inf, &specs);
+        assert_km(&km, &[10000.0, 10000.0, 9000.0, 9000.0, 8500.0, 8500.0]);
+    }
 
+    #[test]
+    fn jump() {
+        let (node_ids, inf) = line();
+        let specs = vec![(2, MileageSpec::Jump(1000.0, 3000.0))].into_iter().collect();
+        let km = auto(&node_ids, &inf, &specs);
+        assert_km(&km, &[0.0, 0.0, 1000.0, 3000.0, 3500.0, 3500.0]);
+    }
 
+    #[test]
+    fn reversal() {
+        let (node_ids, inf) = line();
+        let specs = vec![(3, MileageSpec::Reversal)].into_iter().collect();
+        let km = auto(&node_ids, &inf, &specs);
+        // The mileage decreases from the reversal point in both directions.
+        assert_km(&km, &[0.0, 0.0, 1000.0, 1000.0, 500.0, 500.0]);
+    }
+}
@@ -4,6 +4,8 @@ pub mod objects;
 
 // derived data updates
 pub mod analysis;
+pub mod invalidate;
+pub mod cache;
 
 // derived data computation
 pub mod dgraph;
@@ -13,6 +15,11 @@ pub mod history;
 pub mod dispatch;
 pub mod mileage;
 pub mod plan;
+pub mod ars;
+pub mod diagnostics;
+pub mod delays;
+pub mod capacity;
+pub mod layout;
 
 // graphical view representation
 pub mod infview;
@@ -76,6 +83,7 @@ impl Document {
 pub enum DispatchView {
     Manual(ManualDispatchView),
     Auto(AutoDispatchView),
+    Timetable(TimetableView),
 }
 
 #[derive(Clone,Copy)]
@@ -123,7 +131,11 @@ pub struct AutoDispatchView {
     pub dispatch :Option<ManualDispatchView>,
 }
 
-
+#[derive(Clone,Copy)]
+pub struct TimetableView {
+    pub timetable_idx :usize,
+    pub dispatch :ManualDispatchView,
+}
 
 #[derive(Debug, Copy, Clone)]
 #[derive(PartialEq, Eq, Hash)]
@@ -144,7 +156,8 @@ impl UpdateTime for DispatchView {
     fn advance(&mut self, dt :f64) {
         match self {
             DispatchView::Manual(m) |
-            DispatchView::Auto(AutoDispatchView { dispatch: Some(m), .. }) 
+            DispatchView::Auto(AutoDispatchView { dispatch: Some(m), .. }) |
+            DispatchView::Timetable(TimetableView { dispatch: m, .. })
                 => { if m.play { m.time += dt; } },
             _ => {},
         }
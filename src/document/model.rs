@@ -43,7 +43,7 @@ pub type Vc = Pt;
 
 
 #[derive(Clone)]
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 #[derive(Serialize,Deserialize)]
 pub struct Vehicle {
     pub name :String,
@@ -98,6 +98,7 @@ impl Port {
 
 
 #[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+#[derive(Serialize,Deserialize)]
 pub enum AB { A, B }
 
 impl AB {
@@ -127,21 +128,53 @@ pub struct RouteSpec {
 }
 
 #[derive(Copy, Clone)]
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 #[derive(Serialize,Deserialize)]
 pub enum Command {
     Train(usize, RouteSpec),
     Route(RouteSpec),
+    /// Couple the train created by the second command onto the rear 
+    /// of the train created by the first command.
+    Couple(usize, usize),
+    /// Split the given length off the rear of the train created by the
+    /// command. The rear part is a new train identified by this command.
+    Split(usize, f64),
+}
+
+impl Command {
+    pub fn route(&self) -> Option<&RouteSpec> {
+        match self {
+            Command::Train(_,r) | Command::Route(r) => Some(r),
+            Command::Couple(_,_) | Command::Split(_,_) => None,
+        }
+    }
+
+    pub fn route_mut(&mut self) -> Option<&mut RouteSpec> {
+        match self {
+            Command::Train(_,r) | Command::Route(r) => Some(r),
+            Command::Couple(_,_) | Command::Split(_,_) => None,
+        }
+    }
 }
 
 pub type Commands = Vec<(usize,(f64,Command))>;
 
 #[derive(Serialize,Deserialize)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Dispatch {
     pub name :String,
     generation :usize,
     pub commands :Vec<(usize,(f64,Command))>,
+    #[serde(default)]
+    pub origin :Option<DispatchOrigin>,
+}
+
+/// Plan candidate that a manual dispatch was copied from.
+#[derive(Serialize,Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DispatchOrigin {
+    pub plan :usize,
+    pub candidate :usize,
 }
 
 impl Dispatch {
@@ -150,6 +183,7 @@ impl Dispatch {
             name: name,
             generation :0,
             commands :Vec::new(),
+            origin: None,
         }
     }
 
@@ -159,6 +193,7 @@ impl Dispatch {
             name: name, 
             generation: l,
             commands: commands,
+            origin: None,
         }
     }
 
@@ -173,12 +208,14 @@ impl Dispatch {
 
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 #[derive(Serialize,Deserialize)]
 pub struct PlanSpec {
     pub name :String,
     pub trains: ImShortGenList<(Option<ListId>, ImShortGenList<Visit>)>,
     pub order :Vec<(VisitRef,VisitRef,Option<f64>)>,
+    #[serde(default)]
+    pub search :PlanSearch,
 }
 
 impl PlanSpec {
@@ -187,13 +224,49 @@ impl PlanSpec {
             name: name,
             trains: Default::default(),
             order: Default::default(),
+            search: Default::default(),
         }
     }
 }
 
+/// Planner search bounds and ranking of the resulting dispatches.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Serialize,Deserialize)]
+pub struct PlanSearch {
+    /// Number of planner states to try before the first candidate is found.
+    pub n_before :usize,
+    /// Number of additional states to try after the first candidate is found.
+    pub n_after :usize,
+    pub max_results :usize,
+    /// Stop looking for more candidates after this many seconds.
+    pub time_budget :f64,
+    pub objective :PlanObjective,
+}
+
+impl Default for PlanSearch {
+    fn default() -> PlanSearch { PlanSearch {
+        n_before: 3,
+        n_after: 3,
+        max_results: 10,
+        time_budget: 30.0,
+        objective: PlanObjective::TotalTime,
+    } }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize,Deserialize)]
+pub enum PlanObjective {
+    /// Time until the last event in the dispatch.
+    TotalTime,
+    /// Sum of the trains' running times.
+    RunningTime,
+    /// Sum of the times at which each train reaches its last visit.
+    LastVisit,
+}
+
 pub type VisitRef = (ListId,ListId);
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 #[derive(Serialize,Deserialize)]
 pub struct Visit {
     pub locs :Vec<PlanLoc>,
@@ -202,10 +275,53 @@ pub struct Visit {
 
 pub type PlanLoc = Result<Ref,PtC>;
 
+/// Timetable-driven dispatch using automatic route setting (ARS).
+/// Each train enters at a given time, and the next route along its
+/// path is requested when the train approaches the route's entry signal.
+#[derive(Clone, Debug, PartialEq)]
+#[derive(Serialize,Deserialize)]
+pub struct Timetable {
+    pub name :String,
+    /// Time before sighting the entry signal that the route is requested.
+    pub look_ahead :f64,
+    pub policy :ConflictPolicy,
+    pub trains :ImShortGenList<TimetableTrain>,
+}
+
+impl Timetable {
+    pub fn new_empty(name :String) -> Self {
+        Timetable {
+            name: name,
+            look_ahead: 30.0,
+            policy: ConflictPolicy::FirstComeFirstServed,
+            trains: Default::default(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize,Deserialize)]
+pub enum ConflictPolicy {
+    FirstComeFirstServed,
+    /// Hold back a route request if a train with higher priority 
+    /// requests a conflicting route within the look-ahead time.
+    Priority,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[derive(Serialize,Deserialize)]
+pub struct TimetableTrain {
+    pub vehicle :Option<ListId>,
+    pub entry_time :f64,
+    pub priority :i32,
+    /// Entry boundary followed by the signals and the exit boundary to pass.
+    pub path :Vec<Ref>,
+}
+
 pub type ListId = usize;
 
 #[derive(Clone)]
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 #[derive(Serialize,Deserialize)]
 pub struct ShortGenList<T> {
     generation :ListId,
@@ -216,7 +332,7 @@ pub struct ShortGenList<T> {
 /// always copies the whole Vec when editing after sharing. 
 /// And iterates over the whole Vec to look up by usize-id.
 #[derive(Clone)]
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 #[derive(Serialize,Deserialize)]
 pub struct ImShortGenList<T>(Arc<ShortGenList<T>>);
 
@@ -289,6 +405,57 @@ pub struct Model {
     pub vehicles :ImShortGenList<Vehicle>, 
     pub dispatches :ImShortGenList<Dispatch>,
     pub plans :ImShortGenList<PlanSpec>,
+    #[serde(default)]
+    pub timetables :ImShortGenList<Timetable>,
+    #[serde(default)]
+    pub mileages :im::HashMap<Ref, MileageSpec>,
+    /// Length in metres of single line segments, overriding the default grid length.
+    #[serde(default)]
+    pub segment_lengths :im::HashMap<(Pt,Pt), f64>,
+    /// Length in metres of whole tracks, keyed by any line segment on the track.
+    #[serde(default)]
+    pub track_lengths :im::HashMap<(Pt,Pt), f64>,
+    #[serde(default)]
+    pub synthesis_constraints :im::HashMap<PtA, SynthesisConstraint>,
+    /// Paused signal designer run.
+    #[serde(default)]
+    pub synthesis_checkpoint :Option<Arc<SynthesisCheckpoint>>,
+}
+
+/// Progress of a signal designer run, sufficient to resume it later.
+#[derive(Debug, Clone)]
+#[derive(Serialize,Deserialize)]
+pub struct SynthesisCheckpoint {
+    /// Hash of the synthesis inputs, see `cache::synthesis_key`.
+    pub key :u64,
+    /// Main signals of the reduced signal sets which have been fully explored.
+    pub explored :Vec<Vec<(usize, f64, Function, Option<AB>)>>,
+    /// Designs found so far, with name and score (`None` for the unmeasured maximal design).
+    pub designs :Vec<(String, Option<f64>, Vec<(usize, f64, Function, Option<AB>)>)>,
+}
+
+/// Role of an existing object when designing the signalling.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Serialize,Deserialize)]
+pub enum SynthesisConstraint {
+    /// The object is kept, unmoved, in every design.
+    Fixed,
+    /// No objects are placed within the given distance (m) of this object.
+    ForbiddenZone(f64),
+}
+
+/// User-given mileage information at a node or object.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Serialize,Deserialize)]
+pub enum MileageSpec {
+    /// Fixed mileage value.
+    Fixed(f64),
+    /// The mileage direction changes here, so that the mileage
+    /// increases (or decreases) in both directions away from this point.
+    Reversal,
+    /// Chainage discontinuity: the mileage jumps from the first value to the 
+    /// second value when passing in the direction of increasing mileage.
+    Jump(f64,f64),
 }
 
 
@@ -383,10 +550,18 @@ impl Model {
 
     pub fn delete(&mut self, x :Ref) {
         match x {
-            Ref::LineSeg(a,b) => { self.linesegs.remove(&(a,b)); },
+            Ref::LineSeg(a,b) => {
+                self.linesegs.remove(&(a,b));
+                self.segment_lengths.remove(&(a,b));
+                self.track_lengths.remove(&(a,b));
+            },
             Ref::Node(a) => { self.node_data.remove(&a); },
-            Ref::Object(p) => { self.objects.remove(&p); },
+            Ref::Object(p) => {
+                self.objects.remove(&p);
+                self.synthesis_constraints.remove(&p);
+            },
         }
+        self.mileages.remove(&x);
     }
 
 
@@ -405,6 +580,15 @@ pub enum EditClass {
 
     DispatchName(usize),
     PlanName(usize),
+    PlanOrderTime(usize,usize),
+    PlanSearch(usize),
+    TimetableName(usize),
+    TimetableLookAhead(usize),
+    TimetableTrainTime(usize,usize),
+    TimetableTrainPriority(usize,usize),
+    Mileage(Ref),
+    Length(Pt,Pt),
+    SynthesisCheckpoint,
 }
 
 
@@ -11,7 +11,7 @@ use nalgebra_glm as glm;
 
 
 #[derive(Clone)]
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 #[derive(Serialize,Deserialize)]
 pub struct Object {
     pub loc :PtC,
@@ -19,7 +19,7 @@ pub struct Object {
     pub functions :Vec<Function>,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 #[derive(Serialize,Deserialize)]
 pub enum Function { MainSignal { has_distant :bool }, Detector }
 
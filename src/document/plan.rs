@@ -3,115 +3,294 @@ use crate::document::interlocking::*;
 use rolling::input::staticinfrastructure as rolling_inf;
 use crate::document::model::*;
 use crate::document::history;
-use crate::document::dgraph::DGraph;
+use crate::document::dispatch;
+use ordered_float::OrderedFloat;
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::document::dgraph::{DGraph, edge_length};
+use crate::util::dist_to_line_sqr;
 use rolling::output::history::*;
+use rolling::railway::dynamics::DistanceVelocity;
+use nalgebra_glm as glm;
+use serde::{Serialize,Deserialize};
 
 #[derive(Debug)]
 pub enum ConvertPlanErr {
     VehicleRefMissing,
     VehicleMissing,
+    /// The first visit of the train is not on any route from a model boundary.
+    NoEntryRoute(ListId),
 }
 
+impl ConvertPlanErr {
+    pub fn message(&self) -> String {
+        match self {
+            ConvertPlanErr::VehicleRefMissing => format!("A train has no vehicle"),
+            ConvertPlanErr::VehicleMissing => format!("A train's vehicle does not exist"),
+            ConvertPlanErr::NoEntryRoute(t) =>
+                format!("The first visit of \u{f239} ({}) must be at a boundary or on a route from a boundary", t),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize,Deserialize)]
 pub enum TestPlanErr {
-    MissingVisits,
-    MissingTrain,
-    VisitOrderError,
-    TimingError,
+    /// The train did not reach this visit.
+    MissingVisits(VisitRef),
+    MissingTrain(ListId),
+    /// Ordering constraint (index into `PlanSpec::order`) was violated.
+    VisitOrderError(usize),
+    /// Maximum time difference of ordering constraint was exceeded.
+    TimingError(usize),
+}
+
+impl TestPlanErr {
+    pub fn message(&self, plan :&PlanSpec) -> String {
+        match self {
+            TestPlanErr::MissingVisits(v) => format!("{} is not reached", visit_name(plan, v)),
+            TestPlanErr::MissingTrain(t) => format!("\u{f239} ({}) does not appear", t),
+            TestPlanErr::VisitOrderError(i) => format!("Visits happen in the wrong order: {}", order_name(plan, *i)),
+            TestPlanErr::TimingError(i) => format!("Time limit exceeded: {}", order_name(plan, *i)),
+        }
+    }
+}
+
+pub fn visit_name(plan :&PlanSpec, (train,visit) :&VisitRef) -> String {
+    let idx = plan.trains.get(*train)
+        .and_then(|(_,vs)| vs.iter().position(|(v,_)| v == visit))
+        .map(|i| format!("{}", i+1)).unwrap_or(format!("?"));
+    format!("\u{f239} ({}) visit {}", train, idx)
+}
+
+pub fn order_name(plan :&PlanSpec, idx :usize) -> String {
+    match plan.order.get(idx) {
+        Some((a,b,_)) => format!("{} \u{f061} {}", visit_name(plan, a), visit_name(plan, b)),
+        None => format!("?"),
+    }
+}
+
+/// Simulated candidates rejected by `eval_plan`, counted by cause.
+#[derive(Debug, Clone, Default)]
+#[derive(Serialize,Deserialize)]
+pub struct Rejections {
+    pub candidates :usize,
+    pub causes :HashMap<TestPlanErr, usize>,
+}
+
+/// Minimal set of plan requirements which the planner cannot satisfy together.
+#[derive(Debug, Clone, Default)]
+pub struct PlanConflict {
+    pub trains :Vec<ListId>,
+    pub visits :Vec<VisitRef>,
+    /// Indices into `PlanSpec::order`.
+    pub order :Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PlanExplanation {
+    pub conflict :Option<PlanConflict>,
+    pub rejections :Rejections,
+    /// Why planning did not finish.
+    pub error :Option<String>,
 }
 
 pub fn eval_plan(dgraph :&DGraph, plan_spec :&PlanSpec, history :&History) -> Result<(), TestPlanErr> {
+    let visit_times = get_visit_times(dgraph, plan_spec, history)?;
+
+    // check ordering constraints and time diff
+    for (i,(ra,rb,dt)) in plan_spec.order.iter().enumerate() {
+        let t1 = visit_times.get(ra).ok_or(TestPlanErr::VisitOrderError(i))?;
+        let t2 = visit_times.get(rb).ok_or(TestPlanErr::VisitOrderError(i))?;
+        // Visits happen in order
+        if !(t1 <= t2) {
+            return Err(TestPlanErr::VisitOrderError(i));
+        }
+        // Visits happen within time limit
+        if let Some(dt) = dt {
+            if !(t1 + dt >= *t2) {
+                return Err(TestPlanErr::TimingError(i));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Time of each visit, checking that every train reaches all of its visits.
+pub fn get_visit_times(dgraph :&DGraph, plan_spec :&PlanSpec, history :&History) -> Result<HashMap<VisitRef,f64>, TestPlanErr> {
 
     // Record each visit's time for checking the ordering constraints.
     let mut visit_times : HashMap<VisitRef, f64> = HashMap::new();
 
-    //
-    // 1. check train's visits
     for (train_idx, (train_id, (veh, visits))) in plan_spec.trains.iter().enumerate() {
         let mut t = 0.0;
         let mut current_visit = 0;
-        let (train_name, train_params, train_log) = history.trains.get(train_idx).ok_or(TestPlanErr::MissingTrain)?;
+        let (train_name, train_params, train_log) = history.trains.get(train_idx).ok_or(TestPlanErr::MissingTrain(*train_id))?;
+        let targets = visits.iter().map(|(_,v)| {
+            v.locs.iter().map(|l| loc_target(dgraph, l)).collect::<Vec<_>>()
+        }).collect::<Vec<_>>();
+        let mut pos = None;
         for ev in train_log.iter() {
 
             if let TrainLogEvent::Wait(dt) = ev { 
                 t += dt;
             }
-            if let TrainLogEvent::Move(dt,_,_) = ev { 
+            if let TrainLogEvent::Move(dt,_,DistanceVelocity { dx, .. }) = ev { 
                 t += dt;
+                if let Some((_,d)) = &mut pos { *d += dx; }
+            }
+            if let TrainLogEvent::Edge(a,b) = ev {
+                pos = b.map(|b| ((*a,b), 0.0));
             }
 
             if !(current_visit < visits.data().len()) { 
                 break;
             }
 
-            if event_matches_spec(dgraph, &visits.data()[current_visit].1, ev) {
+            if event_matches_spec(dgraph, &targets[current_visit], ev, pos) {
                 visit_times.insert((*train_id, visits.data()[current_visit].0), t);
                 current_visit += 1;
             }
         }
         if current_visit < visits.data().len() {
             // train train_idx failed to reach its planned locations
-            return Err(TestPlanErr::MissingVisits); 
+            return Err(TestPlanErr::MissingVisits((*train_id, visits.data()[current_visit].0))); 
         }
     }
 
-    // 2. check ordering constraints and time diff
-    for (ra,rb,dt) in plan_spec.order.iter() {
-        let t1 = visit_times.get(ra).ok_or(TestPlanErr::VisitOrderError)?;
-        let t2 = visit_times.get(rb).ok_or(TestPlanErr::VisitOrderError)?;
-        // Visits happen in order
-        if !(t1 <= t2) {
-            return Err(TestPlanErr::TimingError);
-        }
-        // Visits happen within time limit
-        if let Some(dt) = dt {
-            if !(t1 + dt >= *t2) {
-                return Err(TestPlanErr::TimingError);
-            }
-        }
-    }
-
-    Ok(())
+    Ok(visit_times)
 }
 
 pub fn test_plan(dgraph :&DGraph,
                  il :&Interlocking, 
                  vehicles :&[(usize,Vehicle)],
                  plan_spec :&PlanSpec,
-                 candidate :&planner::input::RoutePlan) 
+                 candidate :&planner::input::RoutePlan,
+                 cancel :Option<&AtomicBool>) 
     -> Result<Result<(Commands, History),TestPlanErr>,String> {
     let commands = convert_dispatch_commands(candidate, il, plan_spec)?;
 
     // simulate the dispatch
     let (history,route_refs) =
-         history::get_history(vehicles, &dgraph.rolling_inf, il, &commands)?;
+         history::get_history_cancellable(vehicles, &dgraph.rolling_inf, il, &commands, cancel)?;
 
     // then check that the plan is satisfied
     if let Err(e) = eval_plan(dgraph, plan_spec, &history) { return Ok(Err(e)); }
     return Ok(Ok((commands,history)));
 }
 
-fn event_matches_spec(dgraph :&DGraph, visit :&Visit, event :&TrainLogEvent) -> bool {
-    if let TrainLogEvent::Node(n) = event {
-        for l in visit.locs.iter() {
-            match l {
-                Ok(Ref::Node(pt)) => {
-                    // have to check both boundaries and switch nodes in the dgraph
-                    if dgraph.node_ids.get_by_left(n) == Some(pt) { return true; }
-                    if dgraph.switch_ids.get_by_left(n) == Some(pt) { return true; }
-                },
-                _ => unimplemented!(), // TODO
-            };
+/// Where a plan location is found in the dgraph.
+#[derive(Debug)]
+pub enum LocTarget {
+    /// Model node (boundary, switch or crossing).
+    Node(Pt),
+    /// Dgraph nodes holding a signal or detector.
+    Nodes(HashSet<rolling_inf::NodeId>),
+    /// Distances (in meters) along directed edges.
+    Positions(Vec<((rolling_inf::NodeId, rolling_inf::NodeId), f64)>),
+}
+
+pub fn loc_target(dgraph :&DGraph, loc :&PlanLoc) -> LocTarget {
+    match loc {
+        Ok(Ref::Node(pt)) => LocTarget::Node(*pt),
+        Ok(Ref::Object(pta)) => {
+            let mut nodes = HashSet::new();
+            if let Some(obj) = dgraph.object_ids.get_by_right(pta) {
+                for (node_id, node) in dgraph.rolling_inf.nodes.iter().enumerate() {
+                    if node.objects.contains(obj) { nodes.insert(node_id); }
+                }
+            }
+            if let Some(node_id) = dgraph.detector_ids.get_by_right(pta) {
+                nodes.insert(*node_id);
+            }
+            LocTarget::Nodes(nodes)
+        },
+        Ok(Ref::LineSeg(a,b)) => {
+            let mid = (glm::vec2(a.x as f32, a.y as f32) + glm::vec2(b.x as f32, b.y as f32)) * 0.5;
+            LocTarget::Positions(track_positions(dgraph, mid))
+        },
+        Err(pt) => LocTarget::Positions(track_positions(dgraph, *pt)),
+    }
+}
+
+/// Edges passing close to the point, and the distance along each edge.
+fn track_positions(dgraph :&DGraph, pt :PtC) -> Vec<((rolling_inf::NodeId, rolling_inf::NodeId), f64)> {
+    let mut output = Vec::new();
+    for ((a,b),line) in dgraph.edge_lines.iter() {
+        let length = match edge_length(&dgraph.rolling_inf, *a, *b) { Some(l) => l, None => continue };
+        let mut along = 0.0;
+        let mut closest :Option<(f32,f32)> = None;
+        for w in line.windows(2) {
+            let (dist_sqr, param) = dist_to_line_sqr(pt, w[0], w[1]);
+            let seg_length = glm::distance(&w[0], &w[1]);
+            if dist_sqr < 0.25*0.25 && closest.map(|(d,_)| dist_sqr < d).unwrap_or(true) {
+                closest = Some((dist_sqr, along + param*seg_length));
+            }
+            along += seg_length;
+        }
+        if let Some((_,x)) = closest {
+            if along > 0.0 { output.push(((*a,*b), length * (x / along) as f64)); }
+        }
+    }
+    output
+}
+
+fn event_matches_spec(dgraph :&DGraph, targets :&[LocTarget], event :&TrainLogEvent,
+                      pos :Option<((rolling_inf::NodeId, rolling_inf::NodeId), f64)>) -> bool {
+    for target in targets.iter() {
+        match (target, event) {
+            (LocTarget::Node(pt), TrainLogEvent::Node(n)) => {
+                // have to check both boundaries and switch nodes in the dgraph
+                if dgraph.node_ids.get_by_left(n) == Some(pt) { return true; }
+                if dgraph.switch_ids.get_by_left(n) == Some(pt) { return true; }
+            },
+            (LocTarget::Nodes(nodes), TrainLogEvent::Node(n)) => {
+                if nodes.contains(n) { return true; }
+            },
+            (LocTarget::Positions(ps), TrainLogEvent::Move(_,_,_)) => {
+                if let Some((edge,dist)) = pos {
+                    if ps.iter().any(|(e,x)| *e == edge && dist >= *x) { return true; }
+                }
+            },
+            _ => {},
         }
     }
     false
 }
 
+/// Routes which the train can use when visiting the location.
+fn route_set(dgraph :&DGraph, il :&Interlocking, loc :&PlanLoc, first_visit :bool) -> HashSet<usize> {
+    let through = |f :&dyn Fn(rolling_inf::NodeId, rolling_inf::NodeId) -> bool| {
+        il.routes.iter().enumerate()
+            .filter(|(_,r)| r.path.iter().any(|(a,b)| f(*a,*b)))
+            .map(|(i,_)| i).collect::<HashSet<usize>>()
+    };
+    let routes = match loc_target(dgraph, loc) {
+        LocTarget::Node(pt) => {
+            let bdry = if first_visit { &il.boundary_routes } else { &il.boundary_out_routes };
+            if let Some(routes) = bdry.get(&pt) { return routes.iter().cloned().collect(); }
+            // Switches and crossings
+            let is_pt = |n :rolling_inf::NodeId| dgraph.node_ids.get_by_left(&n) == Some(&pt) || 
+                            dgraph.switch_ids.get_by_left(&n) == Some(&pt);
+            through(&|a,b| is_pt(a) || is_pt(b))
+        },
+        LocTarget::Nodes(nodes) => through(&|a,b| nodes.contains(&a) || nodes.contains(&b)),
+        LocTarget::Positions(ps) => through(&|a,b| ps.iter().any(|(e,_)| *e == (a,b))),
+    };
+    if !first_visit { return routes; }
+    // Trains enter the model on a boundary route, so a first visit away from 
+    // the boundary is made on the boundary routes leading to the location.
+    il.boundary_routes.values().flat_map(|rs| rs.iter())
+        .filter(|r| routes.contains(r)).cloned().collect()
+}
+
 pub fn get_dispatches(
       dgraph :&DGraph,
       il :&Interlocking, 
       vehicles :&[(usize,Vehicle)],
       plan :&PlanSpec,
-      ) -> Result<Vec<(Dispatch, History)>, String> {
+      cancel :Option<&AtomicBool>,
+      ) -> Result<(Vec<(Dispatch, History)>, Rejections), String> {
 
     let routes : HashMap<usize,rolling_inf::Route> = 
         il.routes.iter().map(|r| r.route.clone()).enumerate().collect();
@@ -119,27 +298,187 @@ pub fn get_dispatches(
         il.routes.iter().map(|r| r.id.clone()).enumerate().collect();
 
     let plan_inf = convert_inf(&routes);
-    let plan_usage = convert_plan(il, vehicles, plan).
-        map_err(|e| format!("{:?}", e))?;
-    let config = planner::input::Config {
-        n_before: 3, n_after: 3, exact_n: None, optimize_signals: false,
-    };
+    let plan_usage = convert_plan(dgraph, il, vehicles, plan).
+        map_err(|e| e.message())?;
+    let config = planner_config(&plan.search);
 
     //println!(" STARTIN GPLANNIGN");
     //println!("infrastructure {:#?}", plan_inf);
     //println!("usage {:#?}", plan_usage);
 
+    let budget = std::time::Duration::from_millis((plan.search.time_budget.max(0.0) * 1000.0) as u64);
+    let deadline = std::time::Instant::now().checked_add(budget);
     let mut output = Vec::new();
-    planner::solver::plan(&config, &plan_inf, &plan_usage, |candidate| {
+    let mut signatures = HashSet::new();
+    let mut rejections = Rejections::default();
+    let mut error = None;
+    planner::solver::plan_until(&config, &plan_inf, &plan_usage, cancel, deadline, |candidate| {
         //println!("got one plan");
-        if let Ok((cmds,p)) = test_plan(dgraph, il, vehicles, plan, candidate).unwrap() {
-            let name = format!("Dispatch {}", output.len()+1);
-            output.push((Dispatch::from_vec(name,cmds),p));
+        match test_plan(dgraph, il, vehicles, plan, candidate, cancel) {
+            Err(e) => {
+                // Simulation failed or was cancelled, stop searching.
+                error = Some(e);
+                return true;
+            },
+            Ok(Ok((cmds,p))) => {
+                // Skip candidates which are equivalent to one we already have.
+                if output.len() < plan.search.max_results && signatures.insert(history_signature(&p)) {
+                    output.push((cmds,p));
+                }
+            },
+            Ok(Err(e)) => {
+                rejections.candidates += 1;
+                *rejections.causes.entry(e).or_insert(0) += 1;
+            },
         }
-        false
+        // Returning true stops the search. The time budget is checked by the solver loop.
+        output.len() >= plan.search.max_results
     });
     //println!("planner finished");
-    Ok(output)
+    if let Some(e) = error { return Err(e); }
+    if cancel.map(|c| c.load(Ordering::Relaxed)).unwrap_or(false) {
+        return Err(format!("Planning cancelled"));
+    }
+
+    // Rank the results, so that the first dispatch is the best one.
+    let mut ranked = output.into_iter().map(|(cmds,h)| {
+        (objective_value(dgraph, plan, &h), cmds, h)
+    }).collect::<Vec<_>>();
+    ranked.sort_by_key(|(v,_,_)| OrderedFloat(*v));
+    let output = ranked.into_iter().enumerate().map(|(i,(_,cmds,h))| {
+        (Dispatch::from_vec(format!("Dispatch {}", i+1), cmds), h)
+    }).collect();
+
+    Ok((output, rejections))
+}
+
+pub fn planner_config(search :&PlanSearch) -> planner::input::Config {
+    planner::input::Config {
+        n_before: search.n_before, n_after: search.n_after, 
+        exact_n: None, optimize_signals: false,
+    }
+}
+
+/// Node passing times (rounded to 0.1 s) for each train, used 
+/// for detecting candidates giving equivalent histories.
+fn history_signature(history :&History) -> Vec<(String, Vec<(usize, i64)>)> {
+    history.trains.iter().map(|(name,_,events)| {
+        let mut t = 0.0;
+        let mut nodes = Vec::new();
+        for ev in events.iter() {
+            match ev {
+                TrainLogEvent::Wait(dt) | TrainLogEvent::Move(dt,_,_) => { t += dt; },
+                TrainLogEvent::Node(n) => { nodes.push((*n, (t*10.0).round() as i64)); },
+                _ => {},
+            }
+        }
+        (name.clone(), nodes)
+    }).collect()
+}
+
+/// Value of the plan's ranking objective (lower is better).
+pub fn objective_value(dgraph :&DGraph, plan :&PlanSpec, history :&History) -> f64 {
+    match plan.search.objective {
+        PlanObjective::TotalTime => dispatch::max_time(history),
+        PlanObjective::RunningTime => {
+            history.trains.iter().map(|(_,_,events)| {
+                let mut t = 0.0;
+                let mut entered = None;
+                for ev in events.iter() {
+                    match ev {
+                        TrainLogEvent::Wait(dt) | TrainLogEvent::Move(dt,_,_) => { t += dt; },
+                        TrainLogEvent::Node(_) => { entered.get_or_insert(t); },
+                        _ => {},
+                    }
+                }
+                t - entered.unwrap_or(t)
+            }).sum()
+        },
+        PlanObjective::LastVisit => {
+            let times = match get_visit_times(dgraph, plan, history) {
+                Ok(t) => t,
+                Err(_) => return std::f64::INFINITY,
+            };
+            plan.trains.iter().filter_map(|(train_id,(_,visits))| {
+                let (visit_id,_) = visits.iter().last()?;
+                times.get(&(*train_id, *visit_id))
+            }).sum()
+        },
+    }
+}
+
+/// Find which trains, visits and ordering constraints cannot be planned together.
+/// Returns `None` when the planner finds candidates, which may still be rejected
+/// by the simulation.
+pub fn plan_conflict(
+      dgraph :&DGraph,
+      il :&Interlocking, 
+      vehicles :&[(usize,Vehicle)],
+      plan :&PlanSpec,
+      ) -> Result<Option<PlanConflict>, String> {
+
+    let routes : HashMap<usize,rolling_inf::Route> = 
+        il.routes.iter().map(|r| r.route.clone()).enumerate().collect();
+    let plan_inf = convert_inf(&routes);
+    let plan_usage = convert_plan(dgraph, il, vehicles, plan).
+        map_err(|e| e.message())?;
+    let config = planner_config(&plan.search);
+
+    let conflict = match planner::solver::explain(&config, &plan_inf, &plan_usage) {
+        Some(c) => c,
+        None => return Ok(None),
+    };
+    let visit_id = |(train,idx) :(usize,usize)| plan.trains.get(train)
+        .and_then(|(_,vs)| vs.iter().nth(idx)).map(|(v,_)| (train,*v));
+    Ok(Some(PlanConflict {
+        trains: conflict.trains,
+        visits: conflict.visits.into_iter().filter_map(visit_id).collect(),
+        order: conflict.train_ord,
+    }))
+}
+
+/// For each ordering constraint with a maximum time difference, the smallest
+/// time difference found when planning without the time limits.
+pub fn tightest_time_differences(
+      dgraph :&DGraph,
+      il :&Interlocking, 
+      vehicles :&[(usize,Vehicle)],
+      plan :&PlanSpec,
+      cancel :Option<&AtomicBool>,
+      ) -> Result<Vec<(usize, Option<f64>)>, String> {
+
+    let mut relaxed = plan.clone();
+    for (_,_,dt) in relaxed.order.iter_mut() { *dt = None; }
+
+    let routes : HashMap<usize,rolling_inf::Route> = 
+        il.routes.iter().map(|r| r.route.clone()).enumerate().collect();
+    let plan_inf = convert_inf(&routes);
+    let plan_usage = convert_plan(dgraph, il, vehicles, &relaxed).
+        map_err(|e| e.message())?;
+    let config = planner_config(&plan.search);
+
+    let mut tightest :Vec<(usize,Option<f64>)> = plan.order.iter().enumerate()
+        .filter(|(_,(_,_,dt))| dt.is_some()).map(|(i,_)| (i,None)).collect();
+    planner::solver::plan_cancellable(&config, &plan_inf, &plan_usage, cancel, |candidate| {
+        let commands = match convert_dispatch_commands(candidate, il, &relaxed) { Ok(c) => c, _ => return false };
+        let history = match history::get_history_cancellable(vehicles, &dgraph.rolling_inf, il, &commands, cancel) {
+            Ok((h,_)) => h, _ => return false };
+        if eval_plan(dgraph, &relaxed, &history).is_err() { return false; }
+        if let Ok(times) = get_visit_times(dgraph, &relaxed, &history) {
+            for (i,best) in tightest.iter_mut() {
+                let (a,b,_) = &plan.order[*i];
+                if let (Some(ta),Some(tb)) = (times.get(a),times.get(b)) {
+                    let diff = tb - ta;
+                    if best.map(|x| diff < x).unwrap_or(true) { *best = Some(diff); }
+                }
+            }
+        }
+        false
+    });
+    if cancel.map(|c| c.load(Ordering::Relaxed)).unwrap_or(false) {
+        return Err(format!("Planning cancelled"));
+    }
+    Ok(tightest)
 }
 
 
@@ -290,7 +629,8 @@ pub fn convert_inf(routes :&rolling_inf::Routes<usize>) -> planner::input::Infra
 }
 
 
-pub fn convert_plan(il :&Interlocking, 
+pub fn convert_plan(dgraph :&DGraph,
+                    il :&Interlocking, 
                     vehicles :&[(usize,Vehicle)], 
                     plan :&PlanSpec) -> Result<planner::input::Usage, ConvertPlanErr> {
 
@@ -302,25 +642,21 @@ pub fn convert_plan(il :&Interlocking,
         let mut planner_visits :Vec<HashSet<usize>> = Vec::new();
         for (visit_i, (visit_id, Visit { locs, dwell})) in visits.iter().enumerate() {
             let mut set = HashSet::new();
-            let bdry = if visit_i == 0 { &il.boundary_routes } else { &il.boundary_out_routes };
-            for (loc_i, loc) in locs.iter().enumerate() {
-                if let Ok(Ref::Node(pt)) = loc {
-                    set.extend(bdry.get(pt).into_iter().flat_map(move |rs| rs.iter()));
-                }  else {
-                    unimplemented!(); // TODO  other types of infrastructure references
-                }
+            for loc in locs.iter() {
+                set.extend(route_set(dgraph, il, loc, visit_i == 0));
             }
+            if visit_i == 0 && set.is_empty() { return Err(ConvertPlanErr::NoEntryRoute(*t_id)); }
             planner_visits.push(set);
         }
         trains.insert(*t_id, planner::input::Train {
             length: vehicle.length,
+            max_velocity: vehicle.max_vel,
             visits: planner_visits,
         });
     }
 
     let mut train_ord = Vec::new();
-    for ((train_a,visit_a),(train_b,visit_b), _max_time) in &plan.order {
-        // TODO max_time between visits
+    for ((train_a,visit_a),(train_b,visit_b), max_time) in &plan.order {
         let visit_idx = |train_id, visit_id| plan.trains.get(train_id).unwrap()
             .1.iter().position(|(v,_)| v == visit_id).unwrap(); 
         // TODO unwrap crashes if visit_id is missing
@@ -328,6 +664,7 @@ pub fn convert_plan(il :&Interlocking,
         train_ord.push(planner::input::TrainOrd {
             a: (*train_a, visit_idx(*train_a, visit_a)),
             b: (*train_b, visit_idx(*train_b, visit_b)),
+            max_time: *max_time,
         });
 
     }
@@ -15,6 +15,7 @@ pub struct Topology {
     pub locations : HashMap<Pt,(NDType,Vc)>,
     pub trackobjects : Vec<Vec<(f64,PtA, Function,Option<AB>)>>,
     pub interval_lines :Vec<Vec<(OrderedFloat<f64>,PtC)>>,
+    pub mileages :HashMap<Ref, MileageSpec>,
 }
 
 impl Topology {
@@ -51,6 +52,26 @@ impl Topology {
 
         output
     }
+
+    /// Line segments of a track, each ordered by `order_ivec`.
+    pub fn track_segments(&self, track_idx :usize) -> Vec<(Pt,Pt)> {
+        let pts = self.interval_lines[track_idx].iter()
+            .map(|(_,p)| glm::vec2(p.x.round() as i32, p.y.round() as i32)).collect::<Vec<Pt>>();
+        pts.iter().zip(pts.iter().skip(1)).map(|(a,b)| order_ivec(*a,*b)).collect()
+    }
+
+    /// Track containing a line segment, and the length of the segment.
+    pub fn segment_track(&self, seg :(Pt,Pt)) -> Option<(usize, f64)> {
+        let seg = order_ivec(seg.0, seg.1);
+        for (track_idx, lines) in self.interval_lines.iter().enumerate() {
+            for ((OrderedFloat(l1),p1),(OrderedFloat(l2),p2)) in lines.iter().zip(lines.iter().skip(1)) {
+                let p1 = glm::vec2(p1.x.round() as i32, p1.y.round() as i32);
+                let p2 = glm::vec2(p2.x.round() as i32, p2.y.round() as i32);
+                if order_ivec(p1,p2) == seg { return Some((track_idx, l2 - l1)); }
+            }
+        }
+        None
+    }
 }
 
 
@@ -74,7 +95,6 @@ pub fn convert(model :&Model, def_len :f64) -> Result<Topology, ()>{
         let mut list = VecDeque::new();
         list.push_back((p1,p2));
 
-        let mut length = def_len;
         let (mut a, mut b) = ((p1,p2),(p2,p1));
         drop(p1);drop(p2);
 
@@ -93,7 +113,6 @@ pub fn convert(model :&Model, def_len :f64) -> Result<Topology, ()>{
                     } else { panic!(); }
 
                     *p = (n,p.0);
-                    length += def_len;
 
                 } else {
                     break;
@@ -103,6 +122,13 @@ pub fn convert(model :&Model, def_len :f64) -> Result<Topology, ()>{
 
         extend(&mut a, b.0);
         extend(&mut b, a.0);
+
+        let key = |(p,q) :((i32,i32),(i32,i32))| order_ivec(to_vec(p), to_vec(q));
+        let given = list.iter().map(|x| model.segment_lengths.get(&key(*x)).cloned()).collect::<Vec<_>>();
+        let track_length = list.iter().filter_map(|x| model.track_lengths.get(&key(*x))).next().cloned();
+        let seg_lengths = segment_lengths(&given, track_length, def_len);
+        let length = seg_lengths.iter().sum::<f64>();
+
         let track_idx = tracks.len();
         tracks.push((to_vec(a.0),to_vec(b.0),length));
         locs.entry(a.0).or_insert(Vec::new()).push(((track_idx, AB::A), to_vec(a.1)));
@@ -112,10 +138,10 @@ pub fn convert(model :&Model, def_len :f64) -> Result<Topology, ()>{
         //println!("List {:?}", list);
         let mut l = 0.0;
         let mut interval_map = Vec::new();
-        for (a,b) in list.iter().cloned() {
-            piece_map.insert((a,b), (tracks.len()-1, l, def_len));
+        for ((a,b),seg_len) in list.iter().cloned().zip(seg_lengths.iter().cloned()) {
+            piece_map.insert((a,b), (tracks.len()-1, l, seg_len));
             interval_map.push((OrderedFloat(l),glm::vec2(a.0 as f32 ,a.1 as f32)));
-            l += def_len;
+            l += seg_len;
         }
         let last_pt = list[list.len()-1].1;
         interval_map.push((OrderedFloat(l),glm::vec2(last_pt.0 as f32, last_pt.1 as f32)));
@@ -309,10 +335,30 @@ pub fn convert(model :&Model, def_len :f64) -> Result<Topology, ()>{
             locations: locx,
             trackobjects: trackobjects,
             interval_lines: interval_lines, 
+            mileages: model.mileages.iter().map(|(r,m)| (*r,*m)).collect(),
         }
     )
 }
 
+/// Lengths of the line segments of a track. Segments without a given length
+/// get the default length, or share the rest of the track length if it is given.
+fn segment_lengths(given :&[Option<f64>], track_length :Option<f64>, def_len :f64) -> Vec<f64> {
+    let fixed = given.iter().filter_map(|l| *l).sum::<f64>();
+    let n_free = given.iter().filter(|l| l.is_none()).count();
+    match track_length {
+        Some(total) if n_free > 0 && total > fixed => {
+            let free = (total - fixed) / n_free as f64;
+            given.iter().map(|l| l.unwrap_or(free)).collect()
+        },
+        Some(total) if total > 0.0 => {
+            // The given segment lengths do not fit, so scale everything.
+            let scale = total / (fixed + n_free as f64 * def_len);
+            given.iter().map(|l| scale * l.unwrap_or(def_len)).collect()
+        },
+        _ => given.iter().map(|l| l.unwrap_or(def_len)).collect(),
+    }
+}
+
 fn modu(a :i8, b:i8) -> i8 { (a % b + b ) % b }
 
 fn v_angle(v :Vc) -> i8 {
@@ -0,0 +1,140 @@
+use rolling::input::staticinfrastructure as rolling_inf;
+
+use crate::config::*;
+use crate::document::dgraph::*;
+use crate::document::dispatch::*;
+use crate::export::*;
+
+const PAGE :(f32,f32) = (595.0, 842.0);
+const MARGIN_LEFT :f32 = 60.0;
+const MARGIN_RIGHT :f32 = 20.0;
+const MARGIN_TOP :f32 = 120.0;
+const MARGIN_BOTTOM :f32 = 30.0;
+
+struct Axes { pos :(f64,f64), time :(f64,f64) }
+
+impl Axes {
+    fn x(&self, km :f64) -> f32 {
+        let w = PAGE.0 - MARGIN_LEFT - MARGIN_RIGHT;
+        let x = MARGIN_LEFT + w*((km - self.pos.0)/(self.pos.1 - self.pos.0)) as f32;
+        x.max(MARGIN_LEFT).min(PAGE.0 - MARGIN_RIGHT)
+    }
+
+    fn y(&self, t :f64) -> f32 {
+        let h = PAGE.1 - MARGIN_TOP - MARGIN_BOTTOM;
+        MARGIN_TOP + h*((t - self.time.0)/(self.time.1 - self.time.0)) as f32
+    }
+
+    fn pt(&self, t :f64, km :f64) -> (f32,f32) { (self.x(km), self.y(t)) }
+}
+
+/// Signals, detectors and model boundaries with their mileage.
+fn object_labels(dgraph :&DGraph) -> Vec<(f64, &'static str)> {
+    let mut labels = Vec::new();
+    for (node_idx, node) in dgraph.rolling_inf.nodes.iter().enumerate() {
+        let km = match dgraph.mileage.get(&node_idx) { Some(km) => *km, None => continue };
+        for obj in node.objects.iter() {
+            if let Some(rolling_inf::StaticObject::Signal { .. }) = dgraph.rolling_inf.objects.get(*obj) {
+                labels.push((km, "Signal"));
+            }
+        }
+        if let rolling_inf::Edges::ModelBoundary = node.edges {
+            labels.push((km, "Boundary"));
+        }
+    }
+    for (node_idx,_) in dgraph.detector_ids.iter() {
+        if let Some(km) = dgraph.mileage.get(node_idx) {
+            labels.push((*km, "Detector"));
+        }
+    }
+    labels.sort_by(|a,b| a.0.partial_cmp(&b.0).unwrap());
+    labels.dedup_by(|a,b| (a.0 - b.0).abs() < 1e-3 && a.1 == b.1);
+    labels
+}
+
+/// Time-distance diagram of a dispatch, with block occupation and reservation,
+/// train front and rear curves, a mileage axis on top and a time axis on the left.
+pub fn diagram(config :&Config, dgraph :&DGraph, graph :&DispatchOutput) -> Canvas {
+    let color = |name| Color::from_config(config, name);
+    let col_res = color(RailUIColorName::GraphBlockReserved);
+    let col_occ = color(RailUIColorName::GraphBlockOccupied);
+    let col_box = color(RailUIColorName::GraphBlockBorder);
+    let col_front = color(RailUIColorName::GraphTrainFront);
+    let col_rear = color(RailUIColorName::GraphTrainRear);
+    let col_text = color(RailUIColorName::GraphTimeSliderText);
+    let col_grid = Color(col_box.0, col_box.1, col_box.2, 0.3*col_box.3);
+
+    let (p1,p2) = (graph.pos_interval.0 as f64, graph.pos_interval.1 as f64);
+    let (t1,t2) = (graph.time_interval.0 as f64, graph.time_interval.1 as f64);
+    let (dp,dt) = ((p2-p1).max(1.0), (t2-t1).max(1.0));
+    let axes = Axes { pos: (p1 - 0.05*dp, p2 + 0.05*dp), time: (t1, t2 + 0.05*dt) };
+
+    let mut c = Canvas::new(PAGE, color(RailUIColorName::GraphBackground));
+    c.text((MARGIN_LEFT, 20.0), 12.0, col_text, &graph.dispatch.name, Anchor::Start);
+
+    // Mileage axis with object labels
+    let mut last_label_x = -std::f32::INFINITY;
+    for (km, label) in object_labels(dgraph) {
+        if km < axes.pos.0 || km > axes.pos.1 { continue; }
+        let x = axes.x(km);
+        c.line((x, MARGIN_TOP), (x, PAGE.1 - MARGIN_BOTTOM), col_grid, 0.5);
+        if x - last_label_x < 8.0 { continue; }
+        c.vertical_text((x + 3.0, MARGIN_TOP - 22.0), 7.0, col_text, label, Anchor::Start);
+        last_label_x = x;
+    }
+    let step = tick_step(axes.pos.1 - axes.pos.0, 6);
+    let mut km = (axes.pos.0 / step).ceil() * step;
+    while km <= axes.pos.1 {
+        let x = axes.x(km);
+        c.line((x, MARGIN_TOP - 4.0), (x, MARGIN_TOP), col_box, 1.0);
+        c.text((x, MARGIN_TOP - 8.0), 7.0, col_text, &format!("{:.0}", km), Anchor::Middle);
+        km += step;
+    }
+    c.line((MARGIN_LEFT, MARGIN_TOP), (PAGE.0 - MARGIN_RIGHT, MARGIN_TOP), col_box, 1.0);
+
+    // Time axis
+    let step = tick_step(axes.time.1 - axes.time.0, 10);
+    let mut t = (axes.time.0 / step).ceil() * step;
+    while t <= axes.time.1 {
+        let y = axes.y(t);
+        c.line((MARGIN_LEFT - 4.0, y), (MARGIN_LEFT, y), col_box, 1.0);
+        c.text((MARGIN_LEFT - 6.0, y + 2.5), 7.0, col_text, &format!("{:.0} s", t), Anchor::End);
+        t += step;
+    }
+    c.line((MARGIN_LEFT, MARGIN_TOP), (MARGIN_LEFT, PAGE.1 - MARGIN_BOTTOM), col_box, 1.0);
+
+    for block in &graph.diagram.blocks {
+        if block.reserved.0 < block.occupied.0 {
+            c.rect(axes.pt(block.reserved.0, block.pos.0), axes.pt(block.occupied.0, block.pos.1),
+                   Some(col_res), None);
+        }
+        c.rect(axes.pt(block.occupied.0, block.pos.0), axes.pt(block.occupied.1, block.pos.1),
+               Some(col_occ), None);
+        if block.reserved.1 > block.occupied.1 {
+            c.rect(axes.pt(block.occupied.1, block.pos.0), axes.pt(block.reserved.1, block.pos.1),
+                   Some(col_res), None);
+        }
+        c.rect(axes.pt(block.reserved.0, block.pos.0), axes.pt(block.reserved.1, block.pos.1),
+               None, Some((col_box, 0.5)));
+    }
+
+    for train in &graph.diagram.trains {
+        for (rear, col) in &[(false, col_front), (true, col_rear)] {
+            let mut segments = Vec::new();
+            for s in &train.segments {
+                let kms = if *rear { &s.end_kms } else { &s.kms };
+                let y = (0..4).map(|i| axes.pt(s.start_time + (i as f64)/3.0*s.dt, kms[i]))
+                    .collect::<Vec<_>>();
+                // Control points of the cubic through the four interpolation points.
+                let ctrl = |p0 :(f32,f32), p1 :(f32,f32), p2 :(f32,f32), p3 :(f32,f32)|
+                    ((-5.0*p0.0 + 18.0*p1.0 - 9.0*p2.0 + 2.0*p3.0)/6.0, 
+                     (-5.0*p0.1 + 18.0*p1.1 - 9.0*p2.1 + 2.0*p3.1)/6.0);
+                segments.push(Segment::MoveTo(y[0]));
+                segments.push(Segment::CubicTo(ctrl(y[0],y[1],y[2],y[3]), ctrl(y[3],y[2],y[1],y[0]), y[3]));
+            }
+            c.shapes.push(Shape::Path { segments, closed: false, fill: None, stroke: Some((*col, 1.5)) });
+        }
+    }
+
+    c
+}
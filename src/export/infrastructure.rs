@@ -0,0 +1,241 @@
+use matches::matches;
+
+use crate::config::*;
+use crate::document::model::*;
+use crate::document::objects::*;
+use crate::document::topology::Topology;
+use crate::document::dgraph::*;
+use crate::document::dispatch::*;
+use crate::document::infview::unround_coord;
+use crate::export::*;
+
+/// Grid spacing in points, the same as the canvas' default zoom level.
+const SCALE :f32 = 35.0;
+const MARGIN :f32 = 40.0;
+
+#[derive(Debug, Copy, Clone)]
+pub struct SchematicOptions {
+    /// Name signals and detectors.
+    pub labels :bool,
+    pub mileages :bool,
+    /// Overlay section states and trains from the current dispatch.
+    pub instant :bool,
+}
+
+impl Default for SchematicOptions {
+    fn default() -> Self {
+        SchematicOptions { labels: true, mileages: false, instant: false }
+    }
+}
+
+type P = (f32,f32);
+
+fn add(a :P, b :P) -> P { (a.0+b.0, a.1+b.1) }
+fn sub(a :P, b :P) -> P { (a.0-b.0, a.1-b.1) }
+fn mul(f :f32, a :P) -> P { (f*a.0, f*a.1) }
+fn normalize(a :P) -> P { let l = (a.0*a.0 + a.1*a.1).sqrt().max(1e-6); (a.0/l, a.1/l) }
+fn rotate(a :P, deg :f32) -> P {
+    let (s,c) = deg.to_radians().sin_cos();
+    (c*a.0 - s*a.1, s*a.0 + c*a.1)
+}
+
+struct Transform { min :P, max :P }
+
+impl Transform {
+    fn pt(&self, p :PtC) -> P {
+        (MARGIN + SCALE*(p.x - self.min.0), MARGIN + SCALE*(self.max.1 - p.y))
+    }
+    fn size(&self) -> P {
+        (2.0*MARGIN + SCALE*(self.max.0 - self.min.0), 2.0*MARGIN + SCALE*(self.max.1 - self.min.1))
+    }
+}
+
+fn bounds(model :&Model) -> Transform {
+    let mut min = (std::f32::INFINITY, std::f32::INFINITY);
+    let mut max = (-std::f32::INFINITY, -std::f32::INFINITY);
+    let mut include = |p :PtC| {
+        min = (min.0.min(p.x), min.1.min(p.y));
+        max = (max.0.max(p.x), max.1.max(p.y));
+    };
+    for (a,b) in model.linesegs.iter() {
+        include(glm_ptc(*a));
+        include(glm_ptc(*b));
+    }
+    for (pta,_) in model.objects.iter() { include(unround_coord(*pta)); }
+    if !(min.0 <= max.0) { return Transform { min: (0.0,0.0), max: (1.0,1.0) }; }
+    Transform { min, max }
+}
+
+fn glm_ptc(p :Pt) -> PtC { nalgebra_glm::vec2(p.x as f32, p.y as f32) }
+
+fn node_symbol(c :&mut Canvas, p :P, t :&NDType, tangent :P, col :Color, col_err :Color) {
+    let tangent = normalize(tangent);
+    match t {
+        NDType::OpenEnd => {
+            for angle in &[-45.0,45.0] {
+                c.line(p, add(p, mul(8.0, rotate(tangent, *angle))), col, 2.5);
+            }
+        },
+        NDType::Cont => { c.circle(p, 4.0, Some(col), None); },
+        NDType::Sw(side) => {
+            let angle = if matches!(side, Side::Left) { 45.0 } else { -45.0 };
+            let p2 = add(p, mul(15.0, tangent));
+            let p3 = add(p, mul(15.0*1.41, rotate(tangent, angle)));
+            c.polygon(&[p,p2,p3], Some(col), None);
+        },
+        NDType::Err => {
+            c.rect(sub(p,(4.0,4.0)), add(p,(4.0,4.0)), None, Some((col_err, 4.0)));
+        },
+        NDType::BufferStop => {
+            let normal = (-tangent.1, tangent.0);
+            c.polyline(&[add(add(p, mul(8.0,normal)), mul(8.0,tangent)),
+                         add(p, mul(8.0,normal)),
+                         sub(p, mul(8.0,normal)),
+                         add(sub(p, mul(8.0,normal)), mul(8.0,tangent))], col, 2.5);
+        },
+        NDType::Crossing(type_) => {
+            let left_conn  = matches!(type_, CrossingType::DoubleSlip | CrossingType::SingleSlip(Side::Left));
+            let right_conn = matches!(type_, CrossingType::DoubleSlip | CrossingType::SingleSlip(Side::Right));
+            let normal = (tangent.1, tangent.0);
+            let diag = rotate(tangent, 45.0);
+            if right_conn {
+                let base = sub(sub(p, mul(4.0,normal)), mul(2.0f32.sqrt()*2.0, tangent));
+                c.polyline(&[sub(base, mul(8.0,tangent)), base, add(base, mul(8.0,diag))], col, 2.5);
+            }
+            if left_conn {
+                let base = add(add(p, mul(4.0,normal)), mul(2.0f32.sqrt()*2.0, tangent));
+                c.polyline(&[add(base, mul(8.0,tangent)), base, sub(base, mul(8.0,diag))], col, 2.5);
+            }
+            if left_conn || right_conn {
+                let (pa,pb) = (mul(15.0,tangent), mul(15.0,diag));
+                c.polygon(&[p, add(p,pa), add(p,pb)], Some(col), None);
+                c.polygon(&[p, sub(p,pa), sub(p,pb)], Some(col), None);
+            } else {
+                c.circle(p, 4.0, Some(col), None);
+            }
+        },
+    }
+}
+
+fn object_symbol(config :&Config, c :&mut Canvas, p :P, obj :&Object, state :&[ObjectState], col :Color) {
+    let scale = 5.0;
+    let tangent = (scale * obj.tangent.x as f32, scale * -obj.tangent.y as f32);
+    let normal = (scale * -obj.tangent.y as f32, scale * -obj.tangent.x as f32);
+    let col_stop = Color::from_config(config, RailUIColorName::CanvasSignalStop);
+    let col_proceed = Color::from_config(config, RailUIColorName::CanvasSignalProceed);
+    for f in obj.functions.iter() {
+        match f {
+            Function::Detector => { c.line(sub(p,normal), add(p,normal), col, 2.0); },
+            Function::MainSignal { has_distant } => {
+                c.line(add(p,normal), sub(p,normal), col, 2.0);
+                let stem = if *has_distant { 2.0 } else { 1.0 };
+                c.line(p, add(p, mul(stem,tangent)), col, 2.0);
+                let main = add(add(p, mul(stem,tangent)), tangent);
+                let distant = add(add(p, mul(1.5,tangent)), normal);
+                for s in state.iter() {
+                    match s {
+                        ObjectState::SignalStop => c.circle(main, scale, Some(col_stop), None),
+                        ObjectState::SignalProceed => c.circle(main, scale, Some(col_proceed), None),
+                        ObjectState::DistantStop if *has_distant => c.circle(distant, 0.8*scale, Some(col_stop), None),
+                        ObjectState::DistantProceed => c.circle(distant, 0.8*scale, Some(col_proceed), None),
+                        _ => {},
+                    }
+                }
+                if *has_distant { c.circle(distant, 0.8*scale, None, Some((col, 2.0))); }
+                c.circle(main, scale, None, Some((col, 2.0)));
+            },
+        }
+    }
+}
+
+/// Mileage of a signal or detector, from the node where it is placed.
+fn object_mileage(dgraph :&DGraph, pta :PtA) -> Option<f64> {
+    if let Some(node) = dgraph.detector_ids.get_by_right(&pta) {
+        return dgraph.mileage.get(node).cloned();
+    }
+    let obj = dgraph.object_ids.get_by_right(&pta)?;
+    let node = dgraph.rolling_inf.nodes.iter().position(|n| n.objects.contains(obj))?;
+    dgraph.mileage.get(&node).cloned()
+}
+
+/// Drawing of the infrastructure as shown in the canvas, optionally with
+/// labels, mileages and the state of the infrastructure at an instant.
+pub fn schematic(config :&Config, model :&Model, topology :Option<&Topology>, dgraph :Option<&DGraph>,
+                 instant :Option<&Instant>, opts :&SchematicOptions) -> Canvas {
+    let color = |name| Color::from_config(config, name);
+    let col_track = color(RailUIColorName::CanvasTrack);
+    let col_node = color(RailUIColorName::CanvasNode);
+    let col_symbol = color(RailUIColorName::CanvasSymbol);
+    let col_text = color(RailUIColorName::CanvasSymbol);
+
+    let tf = bounds(model);
+    let mut c = Canvas::new(tf.size(), color(RailUIColorName::CanvasBackground));
+
+    let mut lines = model.linesegs.iter().collect::<Vec<_>>();
+    lines.sort_by_key(|(a,b)| (a.x,a.y,b.x,b.y));
+    for (a,b) in lines {
+        c.line(tf.pt(glm_ptc(*a)), tf.pt(glm_ptc(*b)), col_track, 2.5);
+    }
+
+    let instant = instant.filter(|_| opts.instant);
+    if let Some(instant) = instant {
+        for (_tvd, status, lines) in instant.infrastructure.sections.iter() {
+            let col = match status {
+                SectionStatus::Occupied => color(RailUIColorName::CanvasTVDOccupied),
+                SectionStatus::Reserved => color(RailUIColorName::CanvasTVDReserved),
+                _ => color(RailUIColorName::CanvasTVDFree),
+            };
+            for (p1,p2) in lines.iter() { c.line(tf.pt(*p1), tf.pt(*p2), col, 8.0); }
+        }
+        let col_train = color(RailUIColorName::CanvasTrain);
+        for t in instant.trains.iter() {
+            for (p1,p2) in t.lines.iter() { c.line(tf.pt(*p1), tf.pt(*p2), col_train, 20.0); }
+        }
+    }
+
+    if let Some(topo) = topology {
+        let mut nodes = topo.locations.iter().collect::<Vec<_>>();
+        nodes.sort_by_key(|(p,_)| (p.x,p.y));
+        for (pt,(t,vc)) in nodes {
+            let p = tf.pt(glm_ptc(*pt));
+            node_symbol(&mut c, p, t, (vc.x as f32, vc.y as f32), col_node,
+                        color(RailUIColorName::CanvasNodeError));
+            let km = dgraph.and_then(|d| d.node_ids.get_by_right(pt).and_then(|n| d.mileage.get(n)));
+            if let (true, Some(km)) = (opts.mileages, km) {
+                c.text(add(p, (0.0, 18.0)), 7.0, col_text, &format!("{:.0}", km), Anchor::Middle);
+            }
+        }
+    }
+
+    // Number signals and detectors from left to right.
+    let mut objects = model.objects.iter().collect::<Vec<_>>();
+    objects.sort_by_key(|(p,_)| (p.x,-p.y));
+    let (mut n_signals, mut n_detectors) = (0,0);
+    for (pta,obj) in objects {
+        let p = tf.pt(unround_coord(*pta));
+        let state = instant.and_then(|i| i.infrastructure.object_state.get(pta))
+            .map(|s| s.as_slice()).unwrap_or(&[]);
+        object_symbol(config, &mut c, p, obj, state, col_symbol);
+
+        let mut label = Vec::new();
+        if opts.labels {
+            if obj.functions.iter().any(|f| matches!(f, Function::MainSignal { .. })) {
+                n_signals += 1;
+                label.push(format!("S{}", n_signals));
+            } else if obj.functions.iter().any(|f| matches!(f, Function::Detector)) {
+                n_detectors += 1;
+                label.push(format!("D{}", n_detectors));
+            }
+        }
+        if opts.mileages {
+            if let Some(km) = dgraph.and_then(|d| object_mileage(d, *pta)) {
+                label.push(format!("{:.0}", km));
+            }
+        }
+        if !label.is_empty() {
+            c.text(add(p, (0.0, -12.0)), 7.0, col_text, &label.join(" "), Anchor::Middle);
+        }
+    }
+
+    c
+}
@@ -0,0 +1,259 @@
+//! Vector graphics export of diagrams and infrastructure drawings.
+//! Drawings are built as a list of shapes on a `Canvas`, which
+//! can then be written as SVG or PDF.
+
+use log::*;
+use std::fmt::Write;
+use crate::config::*;
+
+pub mod diagram;
+pub mod infrastructure;
+
+/// RGBA color, components in 0..1.
+#[derive(Debug, Copy, Clone)]
+pub struct Color(pub f32, pub f32, pub f32, pub f32);
+
+impl Color {
+    pub fn from_config(config :&Config, name :RailUIColorName) -> Color {
+        let c = config.colors[name];
+        Color(c.color.red, c.color.green, c.color.blue, c.alpha)
+    }
+
+    fn svg(&self) -> String {
+        format!("rgb({},{},{})", (255.0*self.0) as u8, (255.0*self.1) as u8, (255.0*self.2) as u8)
+    }
+
+    /// PDF has no transparency without extended graphics states,
+    /// so the color is blended onto the background instead.
+    fn pdf(&self, background :Color) -> (f32,f32,f32) {
+        let a = self.3;
+        (a*self.0 + (1.0-a)*background.0,
+         a*self.1 + (1.0-a)*background.1,
+         a*self.2 + (1.0-a)*background.2)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Segment {
+    MoveTo((f32,f32)),
+    LineTo((f32,f32)),
+    CubicTo((f32,f32),(f32,f32),(f32,f32)),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Anchor { Start, Middle, End }
+
+#[derive(Debug, Clone)]
+pub enum Shape {
+    Path { segments :Vec<Segment>, closed :bool, fill :Option<Color>, stroke :Option<(Color,f32)> },
+    Text { pos :(f32,f32), size :f32, color :Color, text :String, anchor :Anchor, vertical :bool },
+}
+
+/// Drawing in points, with the origin in the upper left corner.
+#[derive(Debug, Clone)]
+pub struct Canvas {
+    pub size :(f32,f32),
+    pub background :Color,
+    pub shapes :Vec<Shape>,
+}
+
+impl Canvas {
+    pub fn new(size :(f32,f32), background :Color) -> Canvas {
+        Canvas { size, background, shapes: Vec::new() }
+    }
+
+    pub fn rect(&mut self, a :(f32,f32), b :(f32,f32), fill :Option<Color>, stroke :Option<(Color,f32)>) {
+        self.shapes.push(Shape::Path {
+            segments: vec![Segment::MoveTo(a), Segment::LineTo((b.0,a.1)),
+                           Segment::LineTo(b), Segment::LineTo((a.0,b.1))],
+            closed: true, fill, stroke });
+    }
+
+    pub fn line(&mut self, a :(f32,f32), b :(f32,f32), color :Color, width :f32) {
+        self.polyline(&[a,b], color, width);
+    }
+
+    pub fn polyline(&mut self, pts :&[(f32,f32)], color :Color, width :f32) {
+        if pts.len() < 2 { return; }
+        let mut segments = vec![Segment::MoveTo(pts[0])];
+        segments.extend(pts[1..].iter().map(|p| Segment::LineTo(*p)));
+        self.shapes.push(Shape::Path { segments, closed: false, fill: None, stroke: Some((color, width)) });
+    }
+
+    pub fn polygon(&mut self, pts :&[(f32,f32)], fill :Option<Color>, stroke :Option<(Color,f32)>) {
+        if pts.len() < 2 { return; }
+        let mut segments = vec![Segment::MoveTo(pts[0])];
+        segments.extend(pts[1..].iter().map(|p| Segment::LineTo(*p)));
+        self.shapes.push(Shape::Path { segments, closed: true, fill, stroke });
+    }
+
+    pub fn circle(&mut self, c :(f32,f32), r :f32, fill :Option<Color>, stroke :Option<(Color,f32)>) {
+        // Four cubic arcs
+        let k = 0.5523*r;
+        let segments = vec![
+            Segment::MoveTo((c.0+r, c.1)),
+            Segment::CubicTo((c.0+r, c.1+k), (c.0+k, c.1+r), (c.0, c.1+r)),
+            Segment::CubicTo((c.0-k, c.1+r), (c.0-r, c.1+k), (c.0-r, c.1)),
+            Segment::CubicTo((c.0-r, c.1-k), (c.0-k, c.1-r), (c.0, c.1-r)),
+            Segment::CubicTo((c.0+k, c.1-r), (c.0+r, c.1-k), (c.0+r, c.1)),
+        ];
+        self.shapes.push(Shape::Path { segments, closed: true, fill, stroke });
+    }
+
+    pub fn text(&mut self, pos :(f32,f32), size :f32, color :Color, text :&str, anchor :Anchor) {
+        self.shapes.push(Shape::Text { pos, size, color, text: text.to_string(), anchor, vertical: false });
+    }
+
+    pub fn vertical_text(&mut self, pos :(f32,f32), size :f32, color :Color, text :&str, anchor :Anchor) {
+        self.shapes.push(Shape::Text { pos, size, color, text: text.to_string(), anchor, vertical: true });
+    }
+
+    pub fn to_svg(&self) -> String {
+        let mut s = String::new();
+        let (w,h) = self.size;
+        writeln!(s, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+        writeln!(s, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}pt" height="{}pt" viewBox="0 0 {} {}">"#,
+                 w, h, w, h).unwrap();
+        writeln!(s, r#"<rect x="0" y="0" width="{}" height="{}" fill="{}" fill-opacity="{}"/>"#,
+                 w, h, self.background.svg(), self.background.3).unwrap();
+        for shape in &self.shapes {
+            match shape {
+                Shape::Path { segments, closed, fill, stroke } => {
+                    let mut d = String::new();
+                    for seg in segments {
+                        match seg {
+                            Segment::MoveTo(p) => write!(d, "M{:.2},{:.2} ", p.0, p.1),
+                            Segment::LineTo(p) => write!(d, "L{:.2},{:.2} ", p.0, p.1),
+                            Segment::CubicTo(a,b,c) => write!(d, "C{:.2},{:.2} {:.2},{:.2} {:.2},{:.2} ",
+                                                              a.0, a.1, b.0, b.1, c.0, c.1),
+                        }.unwrap();
+                    }
+                    if *closed { d.push('Z'); }
+                    let fill = match fill {
+                        Some(c) => format!(r#"fill="{}" fill-opacity="{}""#, c.svg(), c.3),
+                        None => format!(r#"fill="none""#),
+                    };
+                    let stroke = match stroke {
+                        Some((c,w)) => format!(r#"stroke="{}" stroke-opacity="{}" stroke-width="{}""#, c.svg(), c.3, w),
+                        None => format!(r#"stroke="none""#),
+                    };
+                    writeln!(s, r#"<path d="{}" {} {}/>"#, d.trim(), fill, stroke).unwrap();
+                },
+                Shape::Text { pos, size, color, text, anchor, vertical } => {
+                    let anchor = match anchor { Anchor::Start => "start", Anchor::Middle => "middle", Anchor::End => "end" };
+                    let transform = if *vertical { format!(r#" transform="rotate(-90 {} {})""#, pos.0, pos.1) }
+                                    else { String::new() };
+                    writeln!(s, r#"<text x="{}" y="{}" font-family="sans-serif" font-size="{}" fill="{}" fill-opacity="{}" text-anchor="{}"{}>{}</text>"#,
+                             pos.0, pos.1, size, color.svg(), color.3, anchor, transform, xml_escape(text)).unwrap();
+                },
+            }
+        }
+        writeln!(s, "</svg>").unwrap();
+        s
+    }
+
+    pub fn to_pdf(&self) -> Vec<u8> {
+        let (w,h) = self.size;
+        let bg = Color(1.0,1.0,1.0,1.0);
+        let flip = |p :(f32,f32)| (p.0, h - p.1);
+        let mut c = String::new();
+        let (r,g,b) = self.background.pdf(bg);
+        writeln!(c, "{:.3} {:.3} {:.3} rg 0 0 {} {} re f", r, g, b, w, h).unwrap();
+        for shape in &self.shapes {
+            match shape {
+                Shape::Path { segments, closed, fill, stroke } => {
+                    if let Some(col) = fill {
+                        let (r,g,b) = col.pdf(self.background);
+                        writeln!(c, "{:.3} {:.3} {:.3} rg", r, g, b).unwrap();
+                    }
+                    if let Some((col,lw)) = stroke {
+                        let (r,g,b) = col.pdf(self.background);
+                        writeln!(c, "{:.3} {:.3} {:.3} RG {} w", r, g, b, lw).unwrap();
+                    }
+                    for seg in segments {
+                        match seg {
+                            Segment::MoveTo(p) => { let p = flip(*p); writeln!(c, "{:.2} {:.2} m", p.0, p.1) },
+                            Segment::LineTo(p) => { let p = flip(*p); writeln!(c, "{:.2} {:.2} l", p.0, p.1) },
+                            Segment::CubicTo(p1,p2,p3) => {
+                                let (p1,p2,p3) = (flip(*p1), flip(*p2), flip(*p3));
+                                writeln!(c, "{:.2} {:.2} {:.2} {:.2} {:.2} {:.2} c", p1.0, p1.1, p2.0, p2.1, p3.0, p3.1)
+                            },
+                        }.unwrap();
+                    }
+                    if *closed { writeln!(c, "h").unwrap(); }
+                    let op = match (fill.is_some(), stroke.is_some()) {
+                        (true, true) => "B", (true, false) => "f", (false, true) => "S", (false, false) => "n",
+                    };
+                    writeln!(c, "{}", op).unwrap();
+                },
+                Shape::Text { pos, size, color, text, anchor, vertical } => {
+                    // Approximate Helvetica text width for alignment.
+                    let width = 0.5 * size * text.chars().count() as f32;
+                    let offset = match anchor { Anchor::Start => 0.0, Anchor::Middle => -0.5*width, Anchor::End => -width };
+                    let p = flip(*pos);
+                    let (r,g,b) = color.pdf(self.background);
+                    let matrix = if *vertical { format!("0 1 -1 0 {:.2} {:.2}", p.0, p.1 + offset) }
+                                 else { format!("1 0 0 1 {:.2} {:.2}", p.0 + offset, p.1) };
+                    writeln!(c, "BT {:.3} {:.3} {:.3} rg /F1 {} Tf {} Tm ({}) Tj ET",
+                             r, g, b, size, matrix, pdf_escape(text)).unwrap();
+                },
+            }
+        }
+
+        let objects = vec![
+            format!("<< /Type /Catalog /Pages 2 0 R >>"),
+            format!("<< /Type /Pages /Kids [3 0 R] /Count 1 >>"),
+            format!("<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Contents 4 0 R /Resources << /Font << /F1 5 0 R >> >> >>", w, h),
+            format!("<< /Length {} >>\nstream\n{}endstream", c.len(), c),
+            format!("<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"),
+        ];
+        let mut out = String::from("%PDF-1.4\n");
+        let mut offsets = Vec::new();
+        for (i,o) in objects.iter().enumerate() {
+            offsets.push(out.len());
+            write!(out, "{} 0 obj\n{}\nendobj\n", i+1, o).unwrap();
+        }
+        let xref = out.len();
+        write!(out, "xref\n0 {}\n0000000000 65535 f \n", objects.len()+1).unwrap();
+        for o in offsets { write!(out, "{:010} 00000 n \n", o).unwrap(); }
+        write!(out, "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", objects.len()+1, xref).unwrap();
+        out.into_bytes()
+    }
+
+    /// Write SVG or PDF depending on the file name extension.
+    pub fn save(&self, filename :&str) -> Result<(), String> {
+        let data = if filename.to_lowercase().ends_with(".pdf") { self.to_pdf() }
+                   else { self.to_svg().into_bytes() };
+        std::fs::write(filename, data).map_err(|e| format!("{}", e))
+    }
+}
+
+fn xml_escape(s :&str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn pdf_escape(s :&str) -> String {
+    s.chars().map(|c| match c {
+        '(' | ')' | '\\' => format!("\\{}", c),
+        c if c.is_ascii() && !c.is_ascii_control() => c.to_string(),
+        _ => "?".to_string(),
+    }).collect()
+}
+
+/// Round step size for axis ticks, giving around `n` ticks over the range.
+pub fn tick_step(range :f64, n :usize) -> f64 {
+    let raw = range.abs().max(1e-9) / n.max(1) as f64;
+    let mag = 10f64.powf(raw.log10().floor());
+    let m = raw / mag;
+    mag * if m < 1.5 { 1.0 } else if m < 3.5 { 2.0 } else if m < 7.5 { 5.0 } else { 10.0 }
+}
+
+/// Ask for a file name and save the drawing there.
+pub fn save_interactive(title :&str, default :&str, canvas :&Canvas) {
+    if let Some(filename) = tinyfiledialogs::save_file_dialog(title, default) {
+        match canvas.save(&filename) {
+            Ok(()) => info!("Drawing written to {:?}", filename),
+            Err(e) => error!("Could not write drawing: {}", e),
+        }
+    }
+}
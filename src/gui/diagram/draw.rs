@@ -10,6 +10,8 @@ use crate::document::dispatch::*;
 use crate::document::model::*;
 use crate::document::analysis::*;
 use crate::document::*;
+use crate::document::history;
+use crate::document::mileage;
 use crate::gui::diagram::DiagramViewAction;
 use crate::gui::infrastructure::draw::highlight_node;
 use crate::document::infview::InfView;
@@ -171,16 +173,28 @@ pub fn command_icons(config :&Config,
 
     let mut prev_y = -std::f32::INFINITY;
     for (cmd_idx,(cmd_id,(cmd_t,cmd))) in dispatch.commands.iter().enumerate() {
-        let route_idx = match cmd { Command::Route(routespec) | Command::Train(_,routespec) => {
-            il.find_route(routespec) }};
+        let route_idx = cmd.route().and_then(|routespec| il.find_route(routespec));
+
+        // Coupling and splitting is placed at the position of the train
+        let train_idx = match cmd {
+            Command::Couple(train,_) | Command::Split(train,_) => {
+                let name = history::train_name(*train);
+                graphics.history.trains.iter().position(|(n,_,_)| n == &name)
+            },
+            _ => None,
+        };
 
-        let fill_color = match (cmd,route_idx) {
-            (_,None) =>                 config.color_u32(RailUIColorName::GraphCommandError),
-            (Command::Route(_),_) =>    config.color_u32(RailUIColorName::GraphCommandRoute),
-            (Command::Train(_,_),_) =>  config.color_u32(RailUIColorName::GraphCommandTrain),
+        let fill_color = match (cmd,route_idx,train_idx) {
+            (Command::Route(_),Some(_),_) =>    config.color_u32(RailUIColorName::GraphCommandRoute),
+            (Command::Train(_,_),Some(_),_) =>  config.color_u32(RailUIColorName::GraphCommandTrain),
+            (Command::Couple(_,_),_,Some(_)) |
+            (Command::Split(_,_),_,Some(_)) =>  config.color_u32(RailUIColorName::GraphCommandTrain),
+            _ =>                                config.color_u32(RailUIColorName::GraphCommandError),
         };
 
-        let km = route_idx.and_then(|r| dgraph.mileage.get(&il.routes[*r].start_node())).cloned().unwrap_or(0.0);
+        let km = route_idx.and_then(|r| dgraph.mileage.get(&il.routes[*r].start_node())).cloned()
+            .or_else(|| train_idx.and_then(|i| graphics.diagram.trains.get(i)?.km_at(*cmd_t)))
+            .unwrap_or(0.0);
 
         unsafe {
             let half_icon_size = ImVec2 { x: 8.0, y: 8.0 };
@@ -193,6 +207,19 @@ pub fn command_icons(config :&Config,
                                p - half_icon_size, 
                                p + half_icon_size, border_col, 0.0, 0, 1.0);
 
+            // Mark commands with diagnostics
+            let issues = graphics.diagnostics.iter().filter(|d| d.cmd_id == *cmd_id).collect::<Vec<_>>();
+            if issues.len() > 0 {
+                let warn_col = config.color_u32(RailUIColorName::GraphCommandError);
+                let marker_pos = p + ImVec2 { x: half_icon_size.x + 2.0, y: -2.0*half_icon_size.y };
+                let text = "!";
+                ImDrawList_AddText(draw.draw_list, marker_pos, warn_col,
+                                   text.as_ptr() as _, text.as_ptr().offset(text.len() as isize) as _);
+                ImDrawList_AddRect(draw.draw_list,
+                                   p - half_icon_size - ImVec2 { x: 2.0, y: 2.0 },
+                                   p + half_icon_size + ImVec2 { x: 2.0, y: 2.0 }, warn_col, 0.0, 0, 2.0);
+            }
+
             if igIsItemHovered(0) && (p-draw.pos-draw.mouse).length_sq() < 5.0*5.0 {
 
                 if let Some(inf) = inf_canvas {
@@ -205,6 +232,14 @@ pub fn command_icons(config :&Config,
 
                 igBeginTooltip();
                 match (cmd, route_idx) {
+                    (Command::Couple(front,rear),_) => {
+                        widgets::show_text(&format!("Couple {} onto {} t={:.1}", 
+                                           history::train_name(*rear), history::train_name(*front), cmd_t));
+                    },
+                    (Command::Split(train,length),_) => {
+                        widgets::show_text(&format!("Split {:.0} m off {} t={:.1}", 
+                                           length, history::train_name(*train), cmd_t));
+                    },
                     (_,None) => {
                         widgets::show_text(&format!("Invalid route start/end points."));
                     }
@@ -217,6 +252,9 @@ pub fn command_icons(config :&Config,
                         widgets::show_text(&format!("{} entering t={:.1}", v, cmd_t));
                     },
                 }
+                for d in issues.iter() {
+                    widgets::show_text(&format!("\u{f071} {}", d.issue.message()));
+                }
                 igEndTooltip();
 
                 if igIsMouseClicked(0,false) && matches!(dv.action, ManualDispatchViewAction::None) {
@@ -253,6 +291,29 @@ pub fn time_slider(config :&Config, draw :&Draw, viewport :&DiagramViewport, t :
 	}
 }
 
+/// Mileage ticks along the top edge of the diagram.
+pub fn mileage_axis(config :&Config, draw :&Draw, view :&DiagramViewport) {
+    let (x0,x1) = view.pos;
+    if !(x1 > x0) { return; }
+    let step = {
+        let raw = (x1 - x0) / 5.0;
+        let mag = 10f64.powf(raw.log10().floor());
+        if raw / mag >= 5.0 { 5.0*mag } else if raw / mag >= 2.0 { 2.0*mag } else { mag }
+    };
+    let col = config.color_u32(RailUIColorName::GraphTimeSliderText);
+    let mut x = (x0 / step).ceil() * step;
+    while x <= x1 {
+        let p = to_screen(draw, view, view.time.0, x);
+        let text = mileage::format_mileage(x);
+        unsafe {
+            ImDrawList_AddLine(draw.draw_list, p, p + ImVec2 { x: 0.0, y: 6.0 }, col, 1.0);
+            ImDrawList_AddText(draw.draw_list, p + ImVec2 { x: 2.0, y: 6.0 }, col,
+                               text.as_ptr() as _, text.as_ptr().offset(text.len() as isize) as _);
+        }
+        x += step;
+    }
+}
+
 pub fn to_screen(draw :&Draw, v :&DiagramViewport, t: f64, x :f64) -> ImVec2 {
     ImVec2 {
         x: draw.pos.x + draw.size.x*(((x - v.pos.0)/(v.pos.1 - v.pos.0)) as f32),
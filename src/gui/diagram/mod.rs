@@ -2,7 +2,12 @@ use nalgebra_glm as glm;
 use const_cstr::*;
 use backend_glfw::imgui::*;
 
+use std::ffi::CString;
+use matches::matches;
+
 use crate::app::*;
+use crate::document::model::*;
+use crate::document::history;
 use crate::document::dispatch::*;
 use crate::document::analysis::*;
 use crate::gui::widgets;
@@ -11,6 +16,7 @@ use crate::app::*;
 use crate::document::*;
 use crate::gui::widgets::Draw;
 use crate::document::infview::InfView;
+use crate::export;
 
 mod draw;
 
@@ -18,6 +24,7 @@ mod draw;
 pub enum DiagramViewAction {
     DeleteCommand { id :usize },
     MoveCommand { idx :usize, id :usize, t :f64 },
+    AddCommand { t :f64, cmd :Command },
 }
 
 pub fn default_viewport(graph :&DispatchOutput) -> DiagramViewport {
@@ -34,7 +41,7 @@ pub fn diagram_view(config :&Config, inf_canvas :Option<&Draw>, inf_view :&InfVi
                     analysis :&Analysis, dv :&mut ManualDispatchView, graph :&DispatchOutput) -> Option<DiagramViewAction> {
     let mut action = None;
     unsafe {
-        diagram_toolbar(dv, graph);
+        diagram_toolbar(config, analysis, dv, graph);
         let size = igGetContentRegionAvail_nonUDT2().into();
         let draw = widgets::canvas(size,
                     config.color_u32(RailUIColorName::GraphBackground),
@@ -57,6 +64,7 @@ pub fn diagram_view(config :&Config, inf_canvas :Option<&Draw>, inf_view :&InfVi
 
         // Need to get a DispatchOutput from analysis.
         draw::diagram(config, graph, &draw, dv.viewport.as_ref().unwrap());
+        draw::mileage_axis(config, &draw, dv.viewport.as_ref().unwrap());
         action = draw::command_icons(config, inf_canvas, inf_view, analysis, graph, &draw, dv).or(action);
         draw::time_slider(config, &draw, dv.viewport.as_ref().unwrap(), dv.time);
 
@@ -79,6 +87,7 @@ pub fn diagram_view(config :&Config, inf_canvas :Option<&Draw>, inf_view :&InfVi
                 if igSelectable(const_cstr!("Delete").as_ptr(), false, 0 as _, ImVec2::zero()) {
                     action = Some(DiagramViewAction::DeleteCommand { id: selection });
                 }
+                action = train_command_menu(analysis, graph, dv.time, selection).or(action);
             }
             igEndPopup();
         }
@@ -89,6 +98,48 @@ pub fn diagram_view(config :&Config, inf_canvas :Option<&Draw>, inf_view :&InfVi
     action
 }
 
+/// Couple and split commands for the train created by the selected command.
+fn train_command_menu(analysis :&Analysis, graph :&DispatchOutput, t :f64, 
+                      selection :usize) -> Option<DiagramViewAction> {
+    let creates_train = |cmd :&Command| matches!(cmd, Command::Train(_,_) | Command::Split(_,_));
+    let is_train = graph.dispatch.commands.iter()
+        .any(|(id,(_,cmd))| *id == selection && creates_train(cmd));
+    if !is_train { return None; }
+
+    let mut action = None;
+    unsafe {
+        let couple_label = CString::new(format!("Couple onto (t={:.1})", t)).unwrap();
+        if igBeginMenu(couple_label.as_ptr(), true) {
+            let mut any = false;
+            for (id,(_,cmd)) in graph.dispatch.commands.iter() {
+                if *id == selection || !creates_train(cmd) { continue; }
+                any = true;
+                let name = CString::new(history::train_name(*id)).unwrap();
+                if igSelectable(name.as_ptr(), false, 0 as _, ImVec2::zero()) {
+                    action = Some(DiagramViewAction::AddCommand { t, cmd: Command::Couple(*id, selection) });
+                }
+            }
+            if !any { widgets::show_text("No other trains."); }
+            igEndMenu();
+        }
+
+        let split_label = CString::new(format!("Split off rear (t={:.1})", t)).unwrap();
+        if igBeginMenu(split_label.as_ptr(), true) {
+            let mut any = false;
+            for (_,v) in analysis.model().vehicles.iter() {
+                any = true;
+                let name = CString::new(format!("{} ({:.0} m)", v.name, v.length)).unwrap();
+                if igSelectable(name.as_ptr(), false, 0 as _, ImVec2::zero()) {
+                    action = Some(DiagramViewAction::AddCommand { t, cmd: Command::Split(selection, v.length as _) });
+                }
+            }
+            if !any { widgets::show_text("No vehicles."); }
+            igEndMenu();
+        }
+    }
+    action
+}
+
 fn scroll(draw :&Draw, viewport :&mut DiagramViewport) {
     fn translate((a,b) :(f64,f64), d:f64) -> (f64,f64) { (a+d,b+d) }
     fn dilate((a,b) :(f64,f64), f :f64) -> (f64,f64) {
@@ -121,7 +172,7 @@ fn scroll(draw :&Draw, viewport :&mut DiagramViewport) {
 }
 
 
-fn diagram_toolbar(dv :&mut ManualDispatchView, graph :&DispatchOutput) {
+fn diagram_toolbar(config :&Config, analysis :&Analysis, dv :&mut ManualDispatchView, graph :&DispatchOutput) {
     unsafe {
     let label = if dv.play { const_cstr!("\u{f04c}") }
                 else { const_cstr!("\u{f04b}") };
@@ -132,5 +183,26 @@ fn diagram_toolbar(dv :&mut ManualDispatchView, graph :&DispatchOutput) {
     if igButton(const_cstr!("\u{f0b2}").as_ptr(), ImVec2::zero()) {
         dv.viewport = Some(default_viewport(graph));
     }
+    igSameLine(0.0,-1.0);
+    if igButton(const_cstr!("\u{f56e}").as_ptr(), ImVec2::zero()) {
+        igOpenPopup(const_cstr!("diagexport").as_ptr());
+    }
+    if igIsItemHovered(0) {
+        igBeginTooltip();
+        widgets::show_text("Export diagram");
+        igEndTooltip();
+    }
+    if igBeginPopup(const_cstr!("diagexport").as_ptr(), 0 as _) {
+        let dgraph = analysis.data().dgraph.as_ref().map(|(_,d)| d);
+        for (label, default) in &[(const_cstr!("SVG..."), "diagram.svg"), (const_cstr!("PDF..."), "diagram.pdf")] {
+            if igSelectable(label.as_ptr(), false, 0 as _, ImVec2::zero()) {
+                if let Some(dgraph) = dgraph {
+                    let canvas = export::diagram::diagram(config, dgraph, graph);
+                    export::save_interactive("Export diagram", default, &canvas);
+                }
+            }
+        }
+        igEndPopup();
+    }
     }
 }
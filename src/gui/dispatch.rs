@@ -9,6 +9,9 @@ use crate::app::*;
 use crate::config::*;
 use crate::gui::widgets;
 use crate::gui::plan;
+use crate::gui::timetable;
+use crate::document::diagnostics;
+use crate::document::dispatch::DispatchOutput;
 use crate::gui::diagram::diagram_view;
 use crate::util::VecMap;
 use crate::gui::diagram::*;
@@ -24,8 +27,20 @@ pub fn dispatch_view(config :&Config, inf_canvas :Option<&Draw>, inf_view :&InfV
 
     match dv {
         DispatchView::Manual(manual) => {
+            plan_check_icon(config, analysis, manual.dispatch_idx);
             let graph = analysis.data().dispatch.vecmap_get(manual.dispatch_idx);
             if let Some((_gen,graph)) = graph {
+                unsafe { igSameLine(0.0, -1.0); }
+                command_list(config, analysis, graph);
+                if let Some(e) = &graph.error {
+                    unsafe {
+                        igSameLine(0.0, -1.0);
+                        igPushStyleColorU32(ImGuiCol__ImGuiCol_Text as _, 
+                                            config.color_u32(RailUIColorName::CanvasSignalStop));
+                        widgets::show_text(&format!("\u{f071} Simulation failed: {}", e));
+                        igPopStyleColor(1);
+                    }
+                }
                 unsafe { igSameLine(0.0, -1.0); }
                 if let Some(action) = diagram_view(config, inf_canvas, inf_view, analysis, manual, graph) {
                     analysis.edit_model(|m| {
@@ -39,7 +54,10 @@ pub fn dispatch_view(config :&Config, inf_canvas :Option<&Draw>, inf_view :&InfV
                                     if *c_id == id { *c_t = t; }
                                 }
                                 commands.sort_by_key(|(_,(t,_))| OrderedFloat(*t));
-                            }
+                            },
+                            DiagramViewAction::AddCommand { t, cmd } => {
+                                m.dispatches.get_mut(manual.dispatch_idx)?.insert(t, cmd);
+                            },
                         };
                         None
                     });
@@ -69,16 +87,114 @@ pub fn dispatch_view(config :&Config, inf_canvas :Option<&Draw>, inf_view :&InfV
                 }
             }
         },
+        DispatchView::Timetable(tv) => {
+            timetable::edit_timetable(config, analysis, tv);
+            if let Some(Some((_gen,Ok(graph)))) = analysis.data().timetables.get(tv.timetable_idx) {
+                command_list(config, analysis, graph);
+                unsafe { igSameLine(0.0, -1.0); }
+                diagram_view(config, inf_canvas, inf_view, analysis, &mut tv.dispatch, graph);
+            }
+
+            if analysis.model().timetables.get(tv.timetable_idx).is_none() {
+                new_dispatch = Some(None);
+            }
+        },
     }
 
     new_dispatch
 }
 
+/// List of the dispatch's commands, with diagnostics for commands which do not work as intended.
+fn command_list(config :&Config, analysis :&Analysis, graph :&DispatchOutput) {
+    let with_issues = diagnostics::command_ids(&graph.diagnostics);
+    unsafe {
+        let label = if with_issues.len() > 0 {
+            CString::new(format!("\u{f071} {} commands with issues", with_issues.len())).unwrap()
+        } else {
+            CString::new(format!("\u{f0ca} {} commands", graph.dispatch.commands.len())).unwrap()
+        };
+        igPushItemWidth(220.0);
+        let comboflag = ImGuiComboFlags__ImGuiComboFlags_HeightLarge;
+        if igBeginCombo(const_cstr!("##cmds").as_ptr(), label.as_ptr(), comboflag as _) {
+            for (cmd_id,(t,cmd)) in graph.dispatch.commands.iter() {
+                widgets::show_text(&format!("t={:.1} {}", t, command_text(analysis, cmd)));
+                igIndent(14.0);
+                for d in graph.diagnostics.iter().filter(|d| d.cmd_id == *cmd_id) {
+                    igPushStyleColorU32(ImGuiCol__ImGuiCol_Text as _, 
+                                        config.color_u32(RailUIColorName::CanvasSignalStop));
+                    widgets::show_text(&format!("\u{f071} {}", d.issue.message()));
+                    igPopStyleColor(1);
+                }
+                igUnindent(14.0);
+            }
+            if graph.dispatch.commands.len() == 0 { widgets::show_text("No commands."); }
+            igEndCombo();
+        }
+        igPopItemWidth();
+    }
+}
+
+fn command_text(analysis :&Analysis, cmd :&model::Command) -> String {
+    use crate::document::history::train_name;
+    match cmd {
+        model::Command::Train(v,_) => {
+            let v = analysis.model().vehicles.get(*v).map(|v| v.name.as_str()).unwrap_or("Unknown vehicle");
+            format!("{} entering", v)
+        },
+        model::Command::Route(_) => format!("Route request"),
+        model::Command::Couple(front,rear) => format!("Couple {} onto {}", train_name(*rear), train_name(*front)),
+        model::Command::Split(train,length) => format!("Split {:.0} m off {}", length, train_name(*train)),
+    }
+}
+
+/// Show whether a dispatch copied from a plan still satisfies the plan.
+fn plan_check_icon(config :&Config, analysis :&Analysis, dispatch_idx :usize) {
+    let origin = match analysis.model().dispatches.get(dispatch_idx).and_then(|d| d.origin) {
+        Some(o) => o,
+        None => return,
+    };
+    let plan_name = analysis.model().plans.get(origin.plan).map(|p| p.name.clone())
+        .unwrap_or(format!("Plan ?"));
+    unsafe {
+        igSameLine(0.0,-1.0);
+        let tooltip = match analysis.data().plan_checks.vecmap_get(dispatch_idx) {
+            Some((generation, result)) if generation == analysis.generation() => {
+                let (color, icon) = if result.is_ok() {
+                    (RailUIColorName::CanvasSignalProceed, "\u{f00c}")
+                } else {
+                    (RailUIColorName::CanvasSignalStop, "\u{f00d}")
+                };
+                igPushStyleColorU32(ImGuiCol__ImGuiCol_Text as _, config.color_u32(color));
+                widgets::show_text(icon);
+                igPopStyleColor(1);
+                match result {
+                    Ok(()) => format!("Satisfies {} (copied from dispatch {}).", plan_name, origin.candidate),
+                    Err(e) => format!("{}: {} (copied from dispatch {}).", plan_name, e, origin.candidate),
+                }
+            },
+            _ => {
+                igPushStyleColorU32(ImGuiCol__ImGuiCol_Text as _, 
+                                    config.color_u32(RailUIColorName::CanvasTrackDrawing));
+                widgets::show_text("\u{f110}");
+                igPopStyleColor(1);
+                format!("Checking {}.", plan_name)
+            },
+        };
+        if igIsItemHovered(0) {
+            igBeginTooltip();
+            widgets::show_text(&tooltip);
+            igEndTooltip();
+        }
+    }
+}
+
 pub enum Action {
     DispatchName(usize,String),
     PlanName(usize, String),
     DeleteDispatch(usize),
     DeletePlan(usize),
+    TimetableName(usize, String),
+    DeleteTimetable(usize),
 }
 
 /// Select a new dispatch view from manual or auto dispatches already existing in model
@@ -86,6 +202,7 @@ pub fn dispatch_select_bar(config :&Config, dispatch_view :&Option<DispatchView>
                            analysis :&mut Analysis) -> Option<Option<DispatchView>> {
     unsafe {
         let mut new_dispatch_auto = None;
+        let mut new_timetable = false;
         let mut retval = None;
         let mut action = None;
 
@@ -105,6 +222,13 @@ pub fn dispatch_select_bar(config :&Config, dispatch_view :&Option<DispatchView>
                     CString::new(format!("\u{f0d0} Plan ?")).unwrap()
                 }
             }
+            Some(DispatchView::Timetable(TimetableView { timetable_idx, .. })) =>  {
+                if let Some(t) = analysis.model().timetables.get(*timetable_idx) {
+                    CString::new(format!("\u{f017} {}",&t.name)).unwrap()
+                } else {
+                    CString::new(format!("\u{f017} Timetable ?")).unwrap()
+                }
+            }
         };
 
         igPushItemWidth(250.0);
@@ -133,6 +257,7 @@ pub fn dispatch_select_bar(config :&Config, dispatch_view :&Option<DispatchView>
 
                 igAlignTextToFramePadding();
                 widgets::show_text("\u{f4fd}");
+                plan_check_icon(config, analysis, *id);
 
                 igSameLine(0.0,-1.0); 
                 if let Some(new_name) = widgets::edit_text(const_cstr!("##dnm").as_ptr(), 
@@ -207,6 +332,46 @@ pub fn dispatch_select_bar(config :&Config, dispatch_view :&Option<DispatchView>
             }
             igSpacing();
 
+            widgets::sep();
+            igPushIDInt(3);
+            let mut any = false;
+            for (id,t) in analysis.model().timetables.iter() {
+                any = true;
+                igPushIDInt(*id as _);
+
+                igAlignTextToFramePadding();
+                widgets::show_text("\u{f017}");
+                igSameLine(0.0,-1.0); 
+                timetable::timetable_icon(config, analysis, *id);
+
+                igSameLine(0.0,-1.0); 
+                if let Some(new_name) = widgets::edit_text(const_cstr!("##tnm").as_ptr(), 
+                                                           t.name.clone()) {
+                    action = Some(Action::TimetableName(*id, new_name));
+                }
+                igSameLine(0.0,-1.0);
+                if igButton(const_cstr!("\u{f2ed}").as_ptr(), ImVec2::zero()) {
+                    action = Some(Action::DeleteTimetable(*id));
+                }
+                igSameLine(0.0,-1.0); 
+                if igButton(const_cstr!("\u{f07c}").as_ptr(), ImVec2::zero()) {
+                    retval = Some(Some(DispatchView::Timetable(TimetableView {
+                        timetable_idx: *id,
+                        dispatch: ManualDispatchView::new(*id),
+                    })));
+                }
+
+                igPopID();
+            }
+            if !any { widgets::show_text("No timetables."); }
+            igPopID();
+
+            igSpacing();
+            if igButton(const_cstr!("\u{f0fe}\u{f017} Timetable").as_ptr(), ImVec2::zero()) {
+                new_timetable = true;
+            }
+            igSpacing();
+
             igEndCombo();
         }
 
@@ -214,7 +379,7 @@ pub fn dispatch_select_bar(config :&Config, dispatch_view :&Option<DispatchView>
 
         if igIsItemHovered(0) {
             igBeginTooltip();
-            widgets::show_text("Add automatic or manual dispatching.");
+            widgets::show_text("Add automatic, manual or timetable dispatching.");
             igEndTooltip();
         }
 
@@ -243,6 +408,18 @@ pub fn dispatch_select_bar(config :&Config, dispatch_view :&Option<DispatchView>
                     None
                 });
             }
+            Some(Action::TimetableName(id,name)) => {
+                analysis.edit_model(|m| {
+                    if let Some(t) = m.timetables.get_mut(id) { t.name = name; }
+                    Some(model::EditClass::TimetableName(id))
+                });
+            },
+            Some(Action::DeleteTimetable(id)) => {
+                analysis.edit_model(|m| {
+                    m.timetables.remove(id);
+                    None
+                });
+            }
             _ => {},
         }
 
@@ -270,6 +447,19 @@ pub fn dispatch_select_bar(config :&Config, dispatch_view :&Option<DispatchView>
             })));
         }
 
+        if new_timetable {
+            // Create new timetable and set it to current
+            let mut model = analysis.model().clone();
+            let name = format!("Timetable {}", model.timetables.next_id()+1);
+            let id = model.timetables.insert(model::Timetable::new_empty(name));
+            analysis.set_model(model, None);
+
+            retval = Some(Some(DispatchView::Timetable(TimetableView {
+                timetable_idx: id,
+                dispatch: ManualDispatchView::new(id),
+            })));
+        }
+
         retval
     }
 }
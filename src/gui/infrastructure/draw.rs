@@ -184,6 +184,11 @@ pub fn base(config :&Config, analysis :&Analysis, inf_view :&InfView,
             let state = object_states.get(pta).unwrap_or(&empty);
             obj.draw(draw.pos, &inf_view.view, draw.draw_list, col, state, config);
         }
+
+        let color_preview = config.color_u32(RailUIColorName::CanvasRoutePath);
+        for obj in &inf_view.preview_objects {
+            obj.draw(draw.pos, &inf_view.view, draw.draw_list, color_preview, &[], config);
+        }
     }
 }
 
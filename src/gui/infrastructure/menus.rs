@@ -12,6 +12,8 @@ use crate::document::objects::*;
 use crate::document::infview::*;
 use crate::document::view::*;
 use crate::document::interlocking::*;
+use crate::document::mileage;
+use crate::util::order_ivec;
 use crate::gui::widgets;
 use crate::gui::plan;
 use crate::config::RailUIColorName;
@@ -92,13 +94,143 @@ pub fn object_menu(analysis :&mut Analysis, pta :PtA) -> Option<()> {
             None
         });
     }
+
+    // Role in signalling design
+    let constraint = analysis.model().synthesis_constraints.get(&pta).cloned();
+    widgets::show_text("Signal designer:");
+    let mut new_constraint = widgets::radio_select(&[
+        (const_cstr!("Free").as_ptr(), constraint.is_none(), None),
+        (const_cstr!("Fixed").as_ptr(), constraint == Some(SynthesisConstraint::Fixed), 
+            Some(SynthesisConstraint::Fixed)),
+        (const_cstr!("Forbidden zone").as_ptr(), matches!(constraint, Some(SynthesisConstraint::ForbiddenZone(_))),
+            Some(SynthesisConstraint::ForbiddenZone(50.0))),
+    ]).map(|x| *x);
+    if let Some(SynthesisConstraint::ForbiddenZone(d)) = constraint {
+        let mut d = d;
+        unsafe {
+            igInputDouble(const_cstr!("Zone distance (m)").as_ptr(), &mut d, 1.0, 10.0,
+                          const_cstr!("%.1f").as_ptr(), 0 as _);
+            if igIsItemEdited() { new_constraint = Some(Some(SynthesisConstraint::ForbiddenZone(d.max(0.0)))); }
+        }
+    }
+    if let Some(c) = new_constraint {
+        analysis.edit_model(|new| {
+            match c {
+                Some(c) => { new.synthesis_constraints.insert(pta, c); },
+                None => { new.synthesis_constraints.remove(&pta); },
+            }
+            None
+        });
+    }
+    Some(())
+}
+
+/// Show the lengths of a line segment and of its track, and let the user
+/// override them independently of the drawing.
+pub fn lineseg_editor(analysis :&mut Analysis, seg :(Pt,Pt)) -> Option<()> {
+    let seg = order_ivec(seg.0, seg.1);
+    let topo = analysis.data().topology.as_ref()?.1.clone();
+    let (track_idx, mut seg_length) = topo.segment_track(seg)?;
+    let segments = topo.track_segments(track_idx);
+    let mut track_length = topo.tracks[track_idx].0;
+    let mut fixed_track = segments.iter().any(|s| analysis.model().track_lengths.contains_key(s));
+    let mut fixed_seg = analysis.model().segment_lengths.contains_key(&seg);
+
+    let mut set_track = None;
+    let mut set_seg = None;
+    unsafe {
+        igCheckbox(const_cstr!("Fixed track length").as_ptr(), &mut fixed_track);
+        if igIsItemEdited() { set_track = Some(if fixed_track { Some(track_length) } else { None }); }
+        igInputDouble(const_cstr!("Track length (m)").as_ptr(), &mut track_length, 10.0, 100.0,
+                      const_cstr!("%.1f").as_ptr(), 0 as _);
+        if igIsItemEdited() { set_track = Some(Some(track_length.max(1.0))); }
+
+        igCheckbox(const_cstr!("Fixed segment length").as_ptr(), &mut fixed_seg);
+        if igIsItemEdited() { set_seg = Some(if fixed_seg { Some(seg_length) } else { None }); }
+        igInputDouble(const_cstr!("Segment length (m)").as_ptr(), &mut seg_length, 1.0, 10.0,
+                      const_cstr!("%.1f").as_ptr(), 0 as _);
+        if igIsItemEdited() { set_seg = Some(Some(seg_length.max(0.1))); }
+    }
+
+    if set_track.is_some() || set_seg.is_some() {
+        analysis.edit_model(|m| {
+            if let Some(l) = set_track {
+                for s in segments.iter() { m.track_lengths.remove(s); }
+                if let Some(l) = l { m.track_lengths.insert(seg, l); }
+            }
+            if let Some(l) = set_seg {
+                match l {
+                    Some(l) => { m.segment_lengths.insert(seg, l); },
+                    None => { m.segment_lengths.remove(&seg); },
+                }
+            }
+            Some(EditClass::Length(seg.0, seg.1))
+        });
+    }
+    Some(())
+}
+
+/// Show the mileage at a node or object, and let the user fix it, or mark
+/// a mileage reversal or a km jump there.
+pub fn mileage_editor(analysis :&mut Analysis, thing :Ref, allow_discontinuity :bool) -> Option<()> {
+    let computed = analysis.data().dgraph.as_ref().and_then(|(_,dg)| dg.ref_mileage(&thing));
+    let spec = analysis.model().mileages.get(&thing).cloned();
+    let km = computed.unwrap_or(0.0);
+    match computed {
+        Some(m) => widgets::show_text(&format!("Mileage: {}", mileage::format_mileage(m))),
+        None => widgets::show_text("Mileage: unknown"),
+    }
+
+    let mut choices = vec![
+        (const_cstr!("Computed mileage").as_ptr(), spec.is_none(), None),
+        (const_cstr!("Fixed mileage").as_ptr(), matches!(spec, Some(MileageSpec::Fixed(_))), 
+            Some(MileageSpec::Fixed(km))),
+    ];
+    if allow_discontinuity {
+        choices.push((const_cstr!("Mileage reversal").as_ptr(), matches!(spec, Some(MileageSpec::Reversal)),
+            Some(MileageSpec::Reversal)));
+        choices.push((const_cstr!("Km jump").as_ptr(), matches!(spec, Some(MileageSpec::Jump(_,_))),
+            Some(MileageSpec::Jump(km,km))));
+    }
+    let mut new_spec = widgets::radio_select(&choices).map(|x| *x);
+
+    unsafe {
+        match spec {
+            Some(MileageSpec::Fixed(x)) => {
+                let mut x = x / 1000.0;
+                igInputDouble(const_cstr!("km").as_ptr(), &mut x, 0.1, 1.0, 
+                              const_cstr!("%.3f").as_ptr(), 0 as _);
+                if igIsItemEdited() { new_spec = Some(Some(MileageSpec::Fixed(1000.0*x))); }
+            },
+            Some(MileageSpec::Jump(from,to)) => {
+                let (mut from, mut to) = (from / 1000.0, to / 1000.0);
+                igInputDouble(const_cstr!("From km").as_ptr(), &mut from, 0.1, 1.0, 
+                              const_cstr!("%.3f").as_ptr(), 0 as _);
+                if igIsItemEdited() { new_spec = Some(Some(MileageSpec::Jump(1000.0*from, 1000.0*to))); }
+                igInputDouble(const_cstr!("To km").as_ptr(), &mut to, 0.1, 1.0, 
+                              const_cstr!("%.3f").as_ptr(), 0 as _);
+                if igIsItemEdited() { new_spec = Some(Some(MileageSpec::Jump(1000.0*from, 1000.0*to))); }
+            },
+            _ => {},
+        }
+    }
+
+    if let Some(new_spec) = new_spec {
+        analysis.edit_model(|m| {
+            match new_spec {
+                Some(s) => { m.mileages.insert(thing, s); },
+                None => { m.mileages.remove(&thing); },
+            }
+            Some(EditClass::Mileage(thing))
+        });
+    }
     Some(())
 }
 
 pub fn route_selector(analysis :&mut Analysis, dispatch_view :&Option<DispatchView>, 
                       thing :Ref, preview :&mut Option<usize>) -> Option<Command> {
 
-    let have_auto = matches!(&dispatch_view, Some(DispatchView::Auto(_)));
+    let have_auto = matches!(&dispatch_view, Some(DispatchView::Auto(_)) | Some(DispatchView::Timetable(_)));
     if have_auto { return None; }
 
     let il = &analysis.data().interlocking.as_ref()?.1;
@@ -153,15 +285,15 @@ pub fn route_selector(analysis :&mut Analysis, dispatch_view :&Option<DispatchVi
 
 // TODO: return dispatch_view instead of &mut?
 pub fn add_plan_visit(analysis :&mut Analysis, 
-                      dispatch_view :&mut Option<DispatchView>, thing :Ref) {
+                      dispatch_view :&mut Option<DispatchView>, loc :PlanLoc) {
     let mut action = None;
 
-    let have_manual = matches!(&dispatch_view, Some(DispatchView::Manual(_)));
+    let have_manual = matches!(&dispatch_view, Some(DispatchView::Manual(_)) | Some(DispatchView::Timetable(_)));
     if have_manual { return; }
 
     unsafe {
         if let Some(DispatchView::Auto(AutoDispatchView { plan_idx, .. })) = &dispatch_view {
-            widgets::show_text("Add visit to plan");
+            widgets::show_text(if loc.is_ok() { "Add visit to plan" } else { "Add visit at clicked position" });
 
             if let Some(plan) = analysis.model().plans.get(*plan_idx) {
                 igIndent(14.0);
@@ -176,7 +308,7 @@ pub fn add_plan_visit(analysis :&mut Analysis,
                 igUnindent(14.0);
             }
 
-        } else {
+        } else if loc.is_ok() {
             if igSelectable(const_cstr!("Create new plan").as_ptr(), false, 0 as _, ImVec2::zero()) {
                 action = Some(None);
             }
@@ -186,7 +318,7 @@ pub fn add_plan_visit(analysis :&mut Analysis,
     if let Some(opt_train) = action {
         let mut set_plan = None;
         analysis.edit_model(|m| {
-            let visit = Visit { locs: vec![Ok(thing)], dwell: None, };
+            let visit = Visit { locs: vec![loc], dwell: None, };
             let visits = if let Some((plan_idx,  train_id)) = opt_train {
                 let (_,visits) = m.plans.get_mut(plan_idx).unwrap()
                                  .trains.get_mut(train_id).unwrap();
@@ -214,3 +346,55 @@ pub fn add_plan_visit(analysis :&mut Analysis,
 
 
 
+
+pub fn add_timetable_location(analysis :&mut Analysis, 
+                              dispatch_view :&Option<DispatchView>, thing :Ref) {
+    let timetable_idx = match dispatch_view {
+        Some(DispatchView::Timetable(TimetableView { timetable_idx, .. })) => *timetable_idx,
+        _ => return,
+    };
+    let timetable = match analysis.model().timetables.get(timetable_idx) {
+        Some(t) => t,
+        None => return,
+    };
+    let is_entry = match (thing, &analysis.data().interlocking) {
+        (Ref::Node(pt), Some((_,il))) => il.boundary_routes.contains_key(&pt),
+        _ => false,
+    };
+
+    let mut action = None;
+    unsafe {
+        widgets::show_text("Add to timetable");
+        igIndent(14.0);
+        for (train_id, train) in timetable.trains.iter() {
+            if train.path.is_empty() && !is_entry { continue; }
+            igPushIDInt(*train_id as _);
+            if igSelectable(const_cstr!("##ttr").as_ptr(), false, 0 as _, ImVec2::zero()) {
+                action = Some(Some(*train_id));
+            }
+            igSameLine(0.0,-1.0); widgets::show_text(&format!("Train {}", train_id));
+            igPopID();
+        }
+        if is_entry {
+            if igSelectable(const_cstr!("New train entering here").as_ptr(), false, 0 as _, ImVec2::zero()) {
+                action = Some(None);
+            }
+        }
+        igUnindent(14.0);
+    }
+
+    if let Some(opt_train) = action {
+        analysis.edit_model(|m| {
+            let vehicle = m.vehicles.iter().nth(0).map(|(id,_)| *id);
+            let timetable = m.timetables.get_mut(timetable_idx)?;
+            if let Some(train_id) = opt_train {
+                timetable.trains.get_mut(train_id)?.path.push(thing);
+            } else {
+                timetable.trains.insert(TimetableTrain {
+                    vehicle, entry_time: 0.0, priority: 0, path: vec![thing],
+                });
+            }
+            None
+        });
+    }
+}
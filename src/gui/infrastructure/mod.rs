@@ -19,7 +19,11 @@ use crate::document::dispatch::*;
 use crate::document::objects::*;
 use crate::gui::widgets;
 use crate::gui::widgets::Draw;
+use crate::export;
 use crate::config::RailUIColorName;
+use log::*;
+use std::collections::HashSet;
+use std::sync::mpsc;
 
 
 #[derive(Copy,Clone,Debug)]
@@ -40,6 +44,7 @@ pub fn inf_view(config :&Config,
                         config.color_u32(RailUIColorName::CanvasBackground),
                         const_cstr!("railwaycanvas").as_ptr());
         draw.begin_draw();
+        poll_auto_layout(analysis, inf_view);
         scroll(inf_view);
         let mut preview_route = None;
         context_menu(analysis, inf_view, dispatch_view, &draw, &mut preview_route);
@@ -50,7 +55,7 @@ pub fn inf_view(config :&Config,
         let pos_after = igGetCursorPos_nonUDT2().into();
         let framespace = igGetFrameHeightWithSpacing() - igGetFrameHeight();
         igSetCursorPos(pos_before + ImVec2 { x: 2.0*framespace, y: 2.0*framespace });
-        inf_toolbar(analysis, inf_view);
+        inf_toolbar(config, analysis, inf_view, dispatch_view);
         igSetCursorPos(pos_after);
         draw
     }
@@ -164,6 +169,9 @@ fn interact_normal(config :&Config, analysis :&mut Analysis,
                         inf_view.action = Action::Normal(NormalState::SelectWindow(a));
                     }
                 } else {
+                    if igIsItemHovered(0) && !igIsMouseDown(0) {
+                        mileage_tooltip(analysis, inf_view, draw);
+                    }
                     if igIsItemHovered(0) && igIsMouseReleased(0) {
                         if !(*io).KeyShift { inf_view.selection.clear(); }
                         if let Some((r,_)) = analysis.get_closest(
@@ -214,6 +222,28 @@ pub fn move_selected_objects(analysis :&mut Analysis, inf_view :&mut InfView, de
     analysis.override_edit_class(EditClass::MoveObjects(inf_view.selection.clone()));
 }
 
+fn mileage_tooltip(analysis :&Analysis, inf_view :&InfView, draw :&Draw) {
+    let thing = match analysis.get_closest(inf_view.view.screen_to_world_ptc(draw.mouse)) {
+        Some((r @ Ref::Node(_),_)) | Some((r @ Ref::Object(_),_)) => r,
+        _ => return,
+    };
+    let dgraph = match &analysis.data().dgraph { Some((_,dg)) => dg, None => return };
+    if let Some(km) = dgraph.ref_mileage(&thing) {
+        unsafe {
+            igBeginTooltip();
+            widgets::show_text(&crate::document::mileage::format_mileage(km));
+            match analysis.model().mileages.get(&thing) {
+                Some(MileageSpec::Fixed(_)) => widgets::show_text("Fixed mileage"),
+                Some(MileageSpec::Reversal) => widgets::show_text("Mileage reversal"),
+                Some(MileageSpec::Jump(from,to)) => widgets::show_text(&format!("Km jump from {} to {}",
+                    crate::document::mileage::format_mileage(*from), crate::document::mileage::format_mileage(*to))),
+                None => {},
+            }
+            igEndTooltip();
+        }
+    }
+}
+
 fn interact_drawing(config :&Config, analysis :&mut Analysis, inf_view :&mut InfView, 
                     draw :&Draw, from :Option<Pt>) {
     unsafe {
@@ -268,16 +298,14 @@ fn is_boundary_extension(analysis :&Analysis, p1 :Pt, p2 :Pt) -> Option<(Pt,Pt)>
 fn model_rename_node(model :&mut Model, a :Pt, b :Pt) {
     for (_,dispatch) in model.dispatches.iter_mut() {
         for (_,(_,command)) in dispatch.commands.iter_mut() {
-            match command {
-                Command::Train(_,r) | Command::Route(r) => {
-                    if r.from == Ref::Node(a) {
-                        r.from = Ref::Node(b);
-                    }
-                    if r.to == Ref::Node(a) {
-                        r.to = Ref::Node(b);
-                    }
+            if let Some(r) = command.route_mut() {
+                if r.from == Ref::Node(a) {
+                    r.from = Ref::Node(b);
+                }
+                if r.to == Ref::Node(a) {
+                    r.to = Ref::Node(b);
                 }
-            };
+            }
         }
     }
 
@@ -292,21 +320,37 @@ fn model_rename_node(model :&mut Model, a :Pt, b :Pt) {
             }
         }
     }
+
+    if let Some(spec) = model.mileages.remove(&Ref::Node(a)) {
+        model.mileages.insert(Ref::Node(b), spec);
+    }
+
+    // Length overrides are keyed by line segments. They follow the node unless
+    // the segment ending at the old point is still there.
+    let linesegs = &model.linesegs;
+    for lengths in vec![&mut model.segment_lengths, &mut model.track_lengths] {
+        let moved = lengths.keys().filter(|(p,q)| (*p == a || *q == a) && !linesegs.contains(&(*p,*q)))
+            .cloned().collect::<Vec<_>>();
+        for (p,q) in moved {
+            let rename = |x :Pt| if x == a { b } else { x };
+            if let Some(l) = lengths.remove(&(p,q)) {
+                lengths.insert(util::order_ivec(rename(p), rename(q)), l);
+            }
+        }
+    }
 }
 
-fn model_rename_object(model :&mut Model, a :PtA, b :PtA) {
+pub fn model_rename_object(model :&mut Model, a :PtA, b :PtA) {
     for (_,dispatch) in model.dispatches.iter_mut() {
         for (_,(_,command)) in dispatch.commands.iter_mut() {
-            match command {
-                Command::Train(_,r) | Command::Route(r) => {
-                    if r.from == Ref::Object(a) {
-                        r.from = Ref::Object(b);
-                    }
-                    if r.to == Ref::Object(a) {
-                        r.to = Ref::Object(b);
-                    }
+            if let Some(r) = command.route_mut() {
+                if r.from == Ref::Object(a) {
+                    r.from = Ref::Object(b);
+                }
+                if r.to == Ref::Object(a) {
+                    r.to = Ref::Object(b);
                 }
-            };
+            }
         }
     }
 
@@ -321,6 +365,13 @@ fn model_rename_object(model :&mut Model, a :PtA, b :PtA) {
             }
         }
     }
+
+    if let Some(spec) = model.mileages.remove(&Ref::Object(a)) {
+        model.mileages.insert(Ref::Object(b), spec);
+    }
+    if let Some(c) = model.synthesis_constraints.remove(&a) {
+        model.synthesis_constraints.insert(b, c);
+    }
 }
 
 
@@ -350,7 +401,8 @@ fn interact_insert(config :&Config, analysis :&mut Analysis,
     }
 }
 
-fn inf_toolbar(analysis :&mut Analysis, inf_view :&mut InfView) {
+fn inf_toolbar(config :&Config, analysis :&mut Analysis, inf_view :&mut InfView, 
+               dispatch_view :&Option<DispatchView>) {
     unsafe  {
     if toolbar_button(
         const_cstr!("\u{f245}").as_ptr(), 
@@ -406,6 +458,102 @@ fn inf_toolbar(analysis :&mut Analysis, inf_view :&mut InfView) {
         widgets::show_text("\u{f01e} redo (CTRL-Y)\nRedo the previously undone action.");
         igEndTooltip();
     }
+    igSameLine(0.0,-1.0);
+    if toolbar_button(const_cstr!("\u{f0d0}").as_ptr(), inf_view.layout_job.is_some(),
+                      inf_view.layout_job.is_none()) {
+        start_auto_layout(analysis, inf_view);
+    }
+    if igIsItemHovered(0) {
+        igBeginTooltip();
+        widgets::show_text("\u{f0d0} auto-layout\nArrange the tracks as a schematic drawing.\nIf tracks are selected, only these are moved.\nSee Edit > Layout options for goals and fixed nodes.");
+        igEndTooltip();
+    }
+    igSameLine(0.0,-1.0);
+    if toolbar_button(const_cstr!("\u{f56e}").as_ptr(), false, true) {
+        igOpenPopup(const_cstr!("infexport").as_ptr());
+    }
+    if igIsItemHovered(0) {
+        igBeginTooltip();
+        widgets::show_text("\u{f56e} export drawing\nSave the infrastructure as an SVG or PDF drawing.");
+        igEndTooltip();
+    }
+    if igBeginPopup(const_cstr!("infexport").as_ptr(), 0 as _) {
+        export_menu(config, analysis, inf_view, dispatch_view);
+        igEndPopup();
+    }
+    }
+}
+
+pub fn start_auto_layout(analysis :&Analysis, inf_view :&mut InfView) {
+    let d = analysis.data();
+    let topology = match &d.topology {
+        Some((g,t)) if g == analysis.generation() => t.clone(),
+        _ => { warn!("Auto-layout needs the track topology, which is not computed yet."); return; },
+    };
+    let dgraph = d.dgraph.as_ref().filter(|(g,_)| g == analysis.generation()).map(|(_,d)| d.clone());
+    let selection = if inf_view.selection.iter().any(|x| matches!(x, Ref::Node(_) | Ref::LineSeg(_,_))) {
+        Some(inf_view.selection.clone())
+    } else { None };
+    let model = analysis.model().clone();
+    let options = inf_view.layout_options.clone();
+
+    let (tx,rx) = mpsc::channel();
+    inf_view.layout_job = Some((*analysis.generation(), rx));
+    analysis.background_jobs().execute(move || {
+        let result = layout::auto_layout(&model, &topology, dgraph.as_ref().map(|d| &**d),
+                                         selection.as_ref(), &options);
+        let _ = tx.send(result);
+    });
+}
+
+fn poll_auto_layout(analysis :&mut Analysis, inf_view :&mut InfView) {
+    let result = match &inf_view.layout_job {
+        Some((_,rx)) => match rx.try_recv() {
+            Ok(r) => r,
+            Err(mpsc::TryRecvError::Empty) => return,
+            Err(mpsc::TryRecvError::Disconnected) => Err(format!("Auto-layout job failed.")),
+        },
+        None => return,
+    };
+    let (generation,_) = inf_view.layout_job.take().unwrap();
+    match result {
+        Ok(_) if generation != *analysis.generation() => {
+            warn!("The model was changed during auto-layout, the layout was discarded.");
+        },
+        Ok((model, moved)) => {
+            info!("Auto-layout finished.");
+            inf_view.layout_options.moved(&moved);
+            inf_view.selection = HashSet::new();
+            analysis.set_model(model, None);
+        },
+        Err(e) => { error!("Auto-layout failed: {}", e); },
+    }
+}
+
+fn export_menu(config :&Config, analysis :&Analysis, inf_view :&mut InfView, 
+               dispatch_view :&Option<DispatchView>) {
+    let cache = &inf_view.instant_cache;
+    let instant = dispatch_view_ref(dispatch_view).and_then(|r| cache.get(r));
+    let opts = &mut inf_view.export_options;
+    unsafe {
+        igCheckbox(const_cstr!("Labels").as_ptr(), &mut opts.labels);
+        igCheckbox(const_cstr!("Mileages").as_ptr(), &mut opts.mileages);
+        if instant.is_some() {
+            igCheckbox(const_cstr!("Dispatch state").as_ptr(), &mut opts.instant);
+        } else {
+            widgets::show_text("Open a dispatch to include its state.");
+        }
+        widgets::sep();
+        for (label, default) in &[(const_cstr!("SVG..."), "infrastructure.svg"), 
+                                  (const_cstr!("PDF..."), "infrastructure.pdf")] {
+            if igSelectable(label.as_ptr(), false, 0 as _, ImVec2::zero()) {
+                let d = analysis.data();
+                let canvas = export::infrastructure::schematic(config, analysis.model(),
+                    d.topology.as_ref().map(|(_,t)| &**t), d.dgraph.as_ref().map(|(_,d)| &**d),
+                    instant, opts);
+                export::save_interactive("Export infrastructure drawing", default, &canvas);
+            }
+        }
     }
 }
 
@@ -481,6 +629,7 @@ fn context_menu(analysis :&mut Analysis,
     }
 
     if igIsItemHovered(0) && igIsMouseClicked(1, false) {
+        inf_view.context_pos = Some(inf_view.view.screen_to_world_ptc(draw.mouse));
         if let Some((r,_)) = analysis.get_closest(inf_view.view.screen_to_world_ptc(draw.mouse)) {
             if !inf_view.selection.contains(&r) {
                 inf_view.selection = std::iter::once(r).collect();
@@ -536,25 +685,37 @@ fn context_menu_contents(analysis :&mut Analysis, inf_view :&mut InfView,
     widgets::sep();
     if inf_view.selection.len() == 1 {
         let thing = inf_view.selection.iter().nth(0).cloned().unwrap();
-        context_menu_single(analysis, dispatch_view ,thing,preview_route);
+        context_menu_single(analysis, dispatch_view ,thing, inf_view.context_pos, preview_route);
     }
     }
 }
 
 fn context_menu_single(analysis :&mut Analysis, 
                        dispatch_view :&mut Option<DispatchView>,
-                       thing :Ref, preview_route :&mut Option<usize>) {
+                       thing :Ref, pos :Option<PtC>, preview_route :&mut Option<usize>) {
 
     // Node editor
     if let Ref::Node(pt) = thing { 
         menus::node_editor(analysis, pt);
         widgets::sep();
+        let is_cont = analysis.data().topology.as_ref()
+            .and_then(|(_,t)| t.locations.get(&pt)).map(|(nd,_)| *nd == NDType::Cont).unwrap_or(false);
+        menus::mileage_editor(analysis, thing, is_cont);
+        widgets::sep();
+    }
+
+    // Line segment and track lengths
+    if let Ref::LineSeg(a,b) = thing {
+        menus::lineseg_editor(analysis, (a,b));
+        widgets::sep();
     }
 
     // Object editor
     if let Ref::Object(pta) = thing { 
         menus::object_menu(analysis, pta);
         widgets::sep();
+        menus::mileage_editor(analysis, thing, true);
+        widgets::sep();
     }
 
     // Manual dispatch from boundaries and signals
@@ -565,7 +726,15 @@ fn context_menu_single(analysis :&mut Analysis,
     widgets::sep();
 
     // Add visits to auto dispatch
-    menus::add_plan_visit(analysis, dispatch_view, thing);
+    menus::add_plan_visit(analysis, dispatch_view, Ok(thing));
+    if let (Ref::LineSeg(_,_), Some(pos)) = (thing, pos) {
+        unsafe { igPushIDInt(1); }
+        menus::add_plan_visit(analysis, dispatch_view, Err(pos));
+        unsafe { igPopID(); }
+    }
+
+    // Add locations to timetable trains
+    menus::add_timetable_location(analysis, dispatch_view, thing);
 }
 
 
@@ -582,7 +751,7 @@ fn start_route(analysis :&mut Analysis, dispatch_view :&mut Option<DispatchView>
 
     let (dispatch_idx,time) = match &dispatch_view {
         Some(DispatchView::Manual(m)) => (m.dispatch_idx, m.time),
-        None | Some(DispatchView::Auto(_)) => {
+        None | Some(DispatchView::Auto(_)) | Some(DispatchView::Timetable(_)) => {
             let name = format!("Dispatch {}", model.dispatches.next_id()+1);
             let dispatch_idx = model.dispatches.insert(Dispatch::new_empty(name));
             let time = 0.0;
@@ -602,11 +771,15 @@ fn start_route(analysis :&mut Analysis, dispatch_view :&mut Option<DispatchView>
 fn dispatch_view_ref(dispatch_view :&Option<DispatchView>) -> Option<DispatchRef> {
     match dispatch_view {
         Some(DispatchView::Manual(ManualDispatchView { dispatch_idx, time, .. })) => {
-           Some((Ok(*dispatch_idx),*time as _))
+           Some((DispatchSource::Manual(*dispatch_idx),*time as _))
         },
         Some(DispatchView::Auto(AutoDispatchView { plan_idx,
             dispatch: Some(ManualDispatchView { dispatch_idx, time, .. }), .. })) => {
-           Some((Err((*plan_idx, *dispatch_idx)), *time as _))
+           Some((DispatchSource::Plan(*plan_idx, *dispatch_idx), *time as _))
+        },
+        Some(DispatchView::Timetable(TimetableView { timetable_idx,
+            dispatch: ManualDispatchView { time, .. } })) => {
+           Some((DispatchSource::Timetable(*timetable_idx), *time as _))
         },
         _ => { return None; },
     }
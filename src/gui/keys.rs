@@ -56,6 +56,7 @@ pub fn keys(app :&mut App) {
             if igIsKeyPressed(' ' as _, false) {
                 if let Some(DispatchView::Manual(m)) 
                      | Some(DispatchView::Auto(AutoDispatchView { dispatch: Some(m), .. })) 
+                     | Some(DispatchView::Timetable(TimetableView { dispatch: m, .. }))
                          = &mut app.document.dispatch_view {
                     m.play = !m.play;
                 }
@@ -4,6 +4,7 @@ use log::*;
 
 use crate::app::*;
 use crate::document::Document;
+use crate::document::analysis::Analysis;
 use crate::gui;
 use crate::file;
 use crate::gui::widgets;
@@ -98,6 +99,10 @@ pub fn main_menu(app :&mut App) {
                                   std::ptr::null(), app.windows.vehicles, true) {
                     app.windows.vehicles = !app.windows.vehicles;
                 }
+                if igMenuItemBool(const_cstr!("Layout options").as_ptr(), 
+                                  std::ptr::null(), app.windows.layout, true) {
+                    app.windows.layout = !app.windows.layout;
+                }
                 if igMenuItemBool(const_cstr!("Signal designer").as_ptr(), 
                                   std::ptr::null(), app.windows.synthesis_window.is_some(), true) {
                     if app.windows.synthesis_window.is_none() {
@@ -108,6 +113,35 @@ pub fn main_menu(app :&mut App) {
 
                     }
                 }
+                if igMenuItemBool(const_cstr!("Signalling lint").as_ptr(), 
+                                  std::ptr::null(), app.windows.lint_window.is_some(), true) {
+                    if app.windows.lint_window.is_none() {
+                        let mut win = gui::windows::lint::LintWindow::new(app.background_jobs.clone());
+                        win.start(&app.document.analysis);
+                        app.windows.lint_window = Some(win);
+                    } else {
+                        app.windows.lint_window = None;
+                    }
+                }
+                if igMenuItemBool(const_cstr!("Delay simulation").as_ptr(), 
+                                  std::ptr::null(), app.windows.delays_window.is_some(), true) {
+                    if app.windows.delays_window.is_none() {
+                        let bg = app.background_jobs.clone();
+                        app.windows.delays_window = 
+                            Some(gui::windows::delays::DelaysWindow::new(bg));
+                    } else {
+                        app.windows.delays_window = None;
+                    }
+                }
+                if igMenuItemBool(const_cstr!("Capacity consumption").as_ptr(), 
+                                  std::ptr::null(), app.windows.capacity_window.is_some(), true) {
+                    if app.windows.capacity_window.is_none() {
+                        app.windows.capacity_window = 
+                            Some(gui::windows::capacity::CapacityWindow::new());
+                    } else {
+                        app.windows.capacity_window = None;
+                    }
+                }
                 if igMenuItemBool(const_cstr!("Delete all objects").as_ptr(), std::ptr::null(), false, true) {
                     app.document.analysis.edit_model(|m| {
                         m.objects.clear();
@@ -135,8 +169,23 @@ pub fn main_menu(app :&mut App) {
                 igEndMenu();
             }
 
+            analysis_status(&app.document.analysis);
+
             igEndMenuBar();
         }
     }
 }
 
+
+/// Show the progress of the background analysis in the menu bar.
+fn analysis_status(analysis :&Analysis) {
+    let progress = analysis.progress();
+    if progress.iter().all(|(_,done,total)| done == total) { return; }
+    let stages = progress.iter().filter(|(_,_,total)| *total > 0)
+        .map(|(name,done,total)| format!("{} {}/{}", name, done, total))
+        .collect::<Vec<_>>();
+    unsafe {
+        igSeparator();
+        widgets::show_text(&format!("\u{f110} {}", stages.join(", ")));
+    }
+}
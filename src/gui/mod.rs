@@ -5,6 +5,7 @@ pub mod windows;
 
 mod infrastructure;
 mod plan;
+mod timetable;
 mod diagram;
 mod dispatch;
 
@@ -26,10 +27,11 @@ pub fn main(app :&mut App) -> bool {
         // top menu bar
         mainmenu::main_menu(app);
 
-        // Three main window arrangements:
+        // Four main window arrangements:
         // 1. Infrastructure only (diagram_view = None)
         // 2. Manual dispatch view (diagram_view = Some(DispatchView::Manual(...)))
         // 3. Auto-dispatch view (diagram_view = Some(DispatchView::Auto(...)))
+        // 4. Timetable view (diagram_view = Some(DispatchView::Timetable(...)))
         let config = &app.config;
         let analysis = &mut app.document.analysis;
         let inf_view = &mut app.document.inf_view;
@@ -71,11 +73,20 @@ pub fn main(app :&mut App) -> bool {
     app.windows.debug = windows::debug::debug_window(app.windows.debug, &app, 
                                                      inf_canvas.as_ref(), &app.document.inf_view );
     windows::vehicles::edit_vehicles_window(&mut app.windows.vehicles, &mut app.document);
+    windows::layout::layout_options_window(&mut app.windows.layout, &mut app.document);
     windows::config::edit_config_window(&mut app.windows.config, &mut app.config);
 
     app.windows.import_window.draw(&mut app.document.analysis);
-    if let Some(win) = &mut app.windows.synthesis_window { if !win.draw(&mut app.document.analysis) {
+    if let Some(win) = &mut app.windows.synthesis_window { if !win.draw(&app.config, &mut app.document.analysis,
+                                                                                  &mut app.document.inf_view) {
         app.windows.synthesis_window = None; }}
+    if let Some(win) = &mut app.windows.delays_window { if !win.draw(&app.document.analysis) {
+        app.windows.delays_window = None; }}
+    if let Some(win) = &mut app.windows.capacity_window { if !win.draw(&app.config, &app.document.analysis) {
+        app.windows.capacity_window = None; }}
+    if let Some(win) = &mut app.windows.lint_window { if !win.draw(&mut app.document.analysis,
+                                                                   &mut app.document.inf_view) {
+        app.windows.lint_window = None; }}
 
     // Quit dialog
     let really_quit = if app.windows.quit {
@@ -13,6 +13,7 @@ use crate::config::*;
 use crate::document::infview::{InfView, unround_coord};
 use crate::gui::infrastructure::draw::box_around;
 use crate::document::dispatch::DispatchOutput;
+use crate::document::plan;
 
 enum Action { 
     VisitDelete { key :VisitKey },
@@ -20,6 +21,9 @@ enum Action {
     VisitMoveBefore { source: VisitKey, target :VisitKey },
     VisitMoveToEnd { source: VisitKey, target: usize }, // Train id
     OrderDeleteAt { key :VisitKey },
+    OrderDelete { idx :usize },
+    OrderMaxTime { idx :usize, max_time :Option<f64> },
+    Search(PlanSearch),
     TrainVehicle { train: usize, vehicle: usize },
     NewTrain,
     RemoveTrain { train: usize },
@@ -51,7 +55,22 @@ pub fn edit_plan(config :&Config,
         }
 
         igSameLine(0.0,-1.0);
-        plan_dispatches(config, analysis, auto_dispatch);
+        if igButton(const_cstr!("\u{f013} Search").as_ptr(), ImVec2::zero()) {
+            igOpenPopup(const_cstr!("plansearch").as_ptr());
+        }
+        if igBeginPopup(const_cstr!("plansearch").as_ptr(), 0 as _) {
+            if let Some(search) = analysis.model().plans.get(plan_idx).map(|p| p.search) {
+                if let Some(search) = search_settings(search) {
+                    action = Some(Action::Search(search));
+                }
+            }
+            igEndPopup();
+        }
+
+        igSameLine(0.0,-1.0);
+        if let Some(candidate) = plan_dispatches(config, analysis, auto_dispatch) {
+            new_dispatchview = copy_plan_dispatch(analysis, plan_idx, candidate);
+        }
 
         widgets::sep();
 
@@ -183,7 +202,8 @@ pub fn edit_plan(config :&Config,
 
             // Draw constraints
             let draw_list = igGetWindowDrawList();
-            for ((strain,svisit),(ttrain,tvisit),_) in plan.order.iter() {
+            let conflict = plan_explanation(analysis, plan_idx).and_then(|e| e.conflict.as_ref());
+            for (order_idx,((strain,svisit),(ttrain,tvisit),_)) in plan.order.iter().enumerate() {
                 let pos1 = visit_pos.get(&VisitKey { train: *strain, visit: *svisit, location: None });
                 let pos2 = visit_pos.get(&VisitKey { train: *ttrain, visit: *tvisit, location: None });
                 if let (Some(pos1),Some(pos2)) = (pos1.cloned(),pos2.cloned()) {
@@ -192,8 +212,12 @@ pub fn edit_plan(config :&Config,
                                        config.color_u32(RailUIColorName::GraphCommandRoute), 8);
                     ImDrawList_AddCircleFilled(draw_list, pos2, 8.0,
                                        config.color_u32(RailUIColorName::GraphCommandRoute), 8);
-                    ImDrawList_AddLine(draw_list, pos1, pos2, 
-                                       config.color_u32(RailUIColorName::GraphTrainFront), 4.0);
+                    let col = if conflict.map(|c| c.order.contains(&order_idx)).unwrap_or(false) {
+                        config.color_u32(RailUIColorName::CanvasSignalStop)
+                    } else {
+                        config.color_u32(RailUIColorName::GraphTrainFront)
+                    };
+                    ImDrawList_AddLine(draw_list, pos1, pos2, col, 4.0);
                     //ImDrawList_AddLine(draw_list, elbow, pos2, 
                                        //app.config.color_u32(RailUIColorName::GraphTrainFront), 4.0);
                 }
@@ -201,6 +225,11 @@ pub fn edit_plan(config :&Config,
 
             igSetCursorScreenPos(end_pos);
 
+            if plan.order.len() > 0 {
+                ordering_constraints(config, analysis, plan_idx, &mut action);
+            }
+            explain_failure(config, analysis, plan_idx, &mut hovered_visit);
+
             // Draw hovered visits/location in infrastructure view
             draw_hovered_inf(config, analysis.model(), plan_idx, &hovered_visit, inf_canvas, inf_view);
 
@@ -285,6 +314,26 @@ pub fn edit_plan(config :&Config,
                 None
             });
         },
+        Some(Action::Search(search)) => {
+            analysis.edit_model(|m| {
+                m.plans.get_mut(plan_idx)?.search = search;
+                Some(EditClass::PlanSearch(plan_idx))
+            });
+        },
+        Some(Action::OrderDelete { idx }) => {
+            analysis.edit_model(|m| {
+                let plan = m.plans.get_mut(plan_idx)?;
+                if idx < plan.order.len() { plan.order.remove(idx); }
+                None
+            });
+        },
+        Some(Action::OrderMaxTime { idx, max_time }) => {
+            analysis.edit_model(|m| {
+                let plan = m.plans.get_mut(plan_idx)?;
+                plan.order.get_mut(idx)?.2 = max_time;
+                Some(EditClass::PlanOrderTime(plan_idx, idx))
+            });
+        },
         Some(Action::VisitDelete { key }) => {
             analysis.edit_model(|m| {
                 let plan = m.plans.get_mut(plan_idx)?;
@@ -310,6 +359,175 @@ pub fn edit_plan(config :&Config,
     new_dispatchview
 }
 
+/// List of ordering constraints, with editable maximum time differences.
+fn ordering_constraints(config :&Config, analysis :&Analysis, plan_idx :usize, action :&mut Option<Action>) -> Option<()> {
+    let plan = analysis.model().plans.get(plan_idx)?;
+
+    // Only show bounds when planning has finished without finding any dispatches.
+    let failed = match analysis.data().plandispatches.get(plan_idx) {
+        Some(Some((g,ds))) => g == analysis.generation() && ds.is_empty(),
+        _ => false,
+    };
+    let bounds = match analysis.data().plan_bounds.get(plan_idx) {
+        Some(Some((g,b))) if failed && g == analysis.generation() => Some(b),
+        _ => None,
+    };
+    let conflict = plan_explanation(analysis, plan_idx).and_then(|e| e.conflict.as_ref());
+
+    unsafe {
+    widgets::sep();
+    widgets::show_text("Ordering constraints");
+    for (idx,(a,b,max_time)) in plan.order.iter().enumerate() {
+        igPushIDInt(idx as _);
+        if igButton(const_cstr!("\u{f55a}").as_ptr(), ImVec2::zero()) {
+            *action = Some(Action::OrderDelete { idx });
+        }
+        igSameLine(0.0,-1.0);
+        let in_conflict = conflict.map(|c| c.order.contains(&idx)).unwrap_or(false);
+        if in_conflict {
+            igPushStyleColorU32(ImGuiCol__ImGuiCol_Text as _, 
+                                config.color_u32(RailUIColorName::CanvasSignalStop));
+        }
+        widgets::show_text(&plan::order_name(plan, idx));
+        if in_conflict { igPopStyleColor(1); }
+
+        igSameLine(0.0,-1.0);
+        let mut bounded = max_time.is_some();
+        igCheckbox(const_cstr!("Max. time").as_ptr(), &mut bounded);
+        if igIsItemEdited() {
+            *action = Some(Action::OrderMaxTime { idx, max_time: if bounded { Some(60.0) } else { None } });
+        }
+        if let Some(dt) = max_time {
+            igSameLine(0.0,-1.0);
+            igPushItemWidth(100.0);
+            let mut dt = *dt;
+            igInputDouble(const_cstr!("s").as_ptr(), &mut dt, 1.0, 10.0, const_cstr!("%.1f").as_ptr(), 0 as _);
+            if igIsItemEdited() {
+                *action = Some(Action::OrderMaxTime { idx, max_time: Some(dt.max(0.0)) });
+            }
+            igPopItemWidth();
+        }
+
+        if let Some((_,tightest)) = bounds.and_then(|b| b.iter().find(|(i,_)| *i == idx)) {
+            igSameLine(0.0,-1.0);
+            igPushStyleColorU32(ImGuiCol__ImGuiCol_Text as _, 
+                                config.color_u32(RailUIColorName::CanvasSignalStop));
+            match tightest {
+                Some(t) => widgets::show_text(&format!("\u{f071} Tightest achievable time difference: {:.1} s", t)),
+                None => widgets::show_text("\u{f071} Not achievable even without time limit"),
+            }
+            igPopStyleColor(1);
+        }
+        igPopID();
+    }
+    }
+    Some(())
+}
+
+/// Planner search bounds and ranking objective. Returns the changed settings.
+fn search_settings(mut search :PlanSearch) -> Option<PlanSearch> {
+    let mut changed = false;
+    unsafe {
+    igPushItemWidth(120.0);
+    let mut n_before = search.n_before as i32;
+    igInputInt(const_cstr!("States before first result").as_ptr(), &mut n_before, 1, 1, 0 as _);
+    if igIsItemEdited() { search.n_before = n_before.max(1) as usize; changed = true; }
+    let mut n_after = search.n_after as i32;
+    igInputInt(const_cstr!("Extra states after first result").as_ptr(), &mut n_after, 1, 1, 0 as _);
+    if igIsItemEdited() { search.n_after = n_after.max(0) as usize; changed = true; }
+    let mut max_results = search.max_results as i32;
+    igInputInt(const_cstr!("Max. results").as_ptr(), &mut max_results, 1, 10, 0 as _);
+    if igIsItemEdited() { search.max_results = max_results.max(1) as usize; changed = true; }
+    igInputDouble(const_cstr!("Time budget (s)").as_ptr(), &mut search.time_budget, 
+                  1.0, 10.0, const_cstr!("%.1f").as_ptr(), 0 as _);
+    if igIsItemEdited() { search.time_budget = search.time_budget.max(0.0); changed = true; }
+
+    let objectives = [
+        (PlanObjective::TotalTime, const_cstr!("Total time")),
+        (PlanObjective::RunningTime, const_cstr!("Sum of running times")),
+        (PlanObjective::LastVisit, const_cstr!("Time at last visits")),
+    ];
+    let current = objectives.iter().find(|(o,_)| *o == search.objective).unwrap().1;
+    if igBeginCombo(const_cstr!("Rank by").as_ptr(), current.as_ptr(), 0) {
+        for (objective, name) in objectives.iter() {
+            if igSelectable(name.as_ptr(), *objective == search.objective, 0 as _, ImVec2::zero()) {
+                search.objective = *objective;
+                changed = true;
+            }
+        }
+        igEndCombo();
+    }
+    igPopItemWidth();
+    }
+    if changed { Some(search) } else { None }
+}
+
+/// Explanation of a failed planning, if planning for the current model has finished.
+fn plan_explanation(analysis :&Analysis, plan_idx :usize) -> Option<&plan::PlanExplanation> {
+    match analysis.data().plandispatches.get(plan_idx) {
+        Some(Some((g,ds))) if g == analysis.generation() && ds.is_empty() => {},
+        _ => return None,
+    };
+    match analysis.data().plan_explanations.get(plan_idx) {
+        Some(Some((g,e))) if g == analysis.generation() => Some(e),
+        _ => None,
+    }
+}
+
+fn explain_failure(config :&Config, analysis :&Analysis, plan_idx :usize, 
+                   hovered_visit :&mut Option<VisitKey>) -> Option<()> {
+    let plan = analysis.model().plans.get(plan_idx)?;
+    let explanation = plan_explanation(analysis, plan_idx)?;
+    unsafe {
+    widgets::sep();
+    igPushStyleColorU32(ImGuiCol__ImGuiCol_Text as _, 
+                        config.color_u32(RailUIColorName::CanvasSignalStop));
+    if let Some(e) = &explanation.error {
+        widgets::show_text(&format!("\u{f071} Planning failed: {}", e));
+        igPopStyleColor(1);
+        return Some(());
+    }
+    widgets::show_text("\u{f071} No dispatch satisfies the plan.");
+    igPopStyleColor(1);
+
+    if let Some(conflict) = &explanation.conflict {
+        widgets::show_text("These requirements cannot be planned together, relax one of them:");
+        igIndent(14.0);
+        for train in conflict.trains.iter() {
+            widgets::show_text(&format!("\u{f239} ({}) enters and leaves the infrastructure", train));
+        }
+        for visit in conflict.visits.iter() {
+            widgets::show_text(&plan::visit_name(plan, visit));
+            if igIsItemHovered(0) {
+                *hovered_visit = Some(VisitKey { train: visit.0, visit: visit.1, location: None });
+            }
+        }
+        for idx in conflict.order.iter() {
+            widgets::show_text(&format!("Ordering: {}", plan::order_name(plan, *idx)));
+        }
+        igUnindent(14.0);
+    } else if explanation.rejections.candidates > 0 {
+        widgets::show_text(&format!("All {} candidate dispatches were rejected in simulation:", 
+                                    explanation.rejections.candidates));
+        let mut causes = explanation.rejections.causes.iter().collect::<Vec<_>>();
+        causes.sort_by_key(|(_,n)| std::cmp::Reverse(**n));
+        igIndent(14.0);
+        for (cause, n) in causes {
+            widgets::show_text(&format!("{} ({}x)", cause.message(plan), n));
+            if let plan::TestPlanErr::MissingVisits(visit) = cause {
+                if igIsItemHovered(0) {
+                    *hovered_visit = Some(VisitKey { train: visit.0, visit: visit.1, location: None });
+                }
+            }
+        }
+        igUnindent(14.0);
+    } else {
+        widgets::show_text("The planner found no candidate dispatches.");
+    }
+    }
+    Some(())
+}
+
 pub fn planning_icon(config :&Config, analysis :&Analysis, generation :usize, dispatches :&Vec<DispatchOutput>) {
     unsafe {
     if generation == *analysis.generation() {
@@ -336,7 +554,9 @@ pub fn planning_icon(config :&Config, analysis :&Analysis, generation :usize, di
     }
 }
 
-fn plan_dispatches(config :&Config, analysis :&Analysis, adv :&mut AutoDispatchView)  {
+/// Select among the planner's dispatches. Returns a dispatch to be copied as a manual dispatch.
+fn plan_dispatches(config :&Config, analysis :&Analysis, adv :&mut AutoDispatchView) -> Option<usize> {
+    let mut copy = None;
     unsafe {
         if let Some(Some((generation,dispatches))) = analysis.data().plandispatches.get(adv.plan_idx) {
             planning_icon(config,analysis,*generation,dispatches);
@@ -345,7 +565,7 @@ fn plan_dispatches(config :&Config, analysis :&Analysis, adv :&mut AutoDispatchV
             let dispatch_idx = if let Some(ManualDispatchView { dispatch_idx, .. }) = &adv.dispatch {
                 Some(*dispatch_idx) } else { None };
             let dispatch_name = if let Some(dispatch_idx) = dispatch_idx {
-                CString::new(format!("Dispatch {}", dispatch_idx)).unwrap()
+                CString::new(format!("Dispatch {}", dispatch_idx+1)).unwrap()
             } else { CString::new(format!("None")).unwrap() };
 
             if igBeginCombo(const_cstr!("##chtr").as_ptr(), dispatch_name.as_ptr(), 0) {
@@ -363,14 +583,40 @@ fn plan_dispatches(config :&Config, analysis :&Analysis, adv :&mut AutoDispatchV
                     }
 
                     igSameLine(0.0,-1.0);
-                    widgets::show_text(&format!("Dispatch {}", di));
+                    widgets::show_text(&d.dispatch.name);
                     igPopID();
                 }
 
                 igEndCombo();
             }
+
+            if let Some(di) = dispatch_idx {
+                igSameLine(0.0,-1.0);
+                if igButton(const_cstr!("\u{f0c5} Copy as manual").as_ptr(), ImVec2::zero()) {
+                    copy = Some(di);
+                }
+                if igIsItemHovered(0) {
+                    igBeginTooltip();
+                    widgets::show_text("Copy this dispatch into an editable manual dispatch.");
+                    igEndTooltip();
+                }
+            }
         }
     }
+    copy
+}
+
+fn copy_plan_dispatch(analysis :&mut Analysis, plan_idx :usize, candidate :usize) -> Option<Option<DispatchView>> {
+    let (_,dispatches) = analysis.data().plandispatches.get(plan_idx)?.as_ref()?;
+    let mut dispatch = dispatches.get(candidate)?.dispatch.clone();
+    let plan_name = &analysis.model().plans.get(plan_idx)?.name;
+    dispatch.name = format!("{} ({})", plan_name, dispatch.name);
+    dispatch.origin = Some(DispatchOrigin { plan: plan_idx, candidate });
+
+    let mut model = analysis.model().clone();
+    let id = model.dispatches.insert(dispatch);
+    analysis.set_model(model, None);
+    Some(Some(DispatchView::Manual(ManualDispatchView::new(id))))
 }
 
 fn visit_move(m: &mut Model, plan :usize, source :VisitKey, t_train_idx: usize, idx :Option<usize>) -> Option<()> {
@@ -716,8 +962,9 @@ fn good_location_marker(config :&Config, vm :&Analysis, loc :&PlanLoc, first_vis
             name = const_cstr!("-O");
             col = config.color_u32(RailUIColorName::CanvasSignalStop);
         }
-        Err(ptc) =>  {
-            return Err(());
+        Err(_) =>  {
+            name = const_cstr!("\u{f3c5}");
+            col = config.color_u32(RailUIColorName::CanvasTrackDrawing);
         }
     };
     igPushStyleColorU32(ImGuiCol__ImGuiCol_Button as _, col);
@@ -742,7 +989,8 @@ fn draw_hovered_inf(config :&Config, model :&Model, plan_idx :usize, hovered_vis
             let pt = match loc {
                 Ok(Ref::Node(pt)) => glm::vec2(pt.x as f32, pt.y as f32),
                 Ok(Ref::Object(pta)) => unround_coord(*pta),
-                Ok(Ref::LineSeg(a,b)) => glm::vec2(a.x as f32, a.y as f32),
+                Ok(Ref::LineSeg(a,b)) => (glm::vec2(a.x as f32, a.y as f32) + 
+                                          glm::vec2(b.x as f32, b.y as f32)) * 0.5,
                 Err(p) => *p,
             };
             box_around(config, draw, inf_view, pt);
@@ -0,0 +1,185 @@
+use const_cstr::*;
+use backend_glfw::imgui::*;
+
+use crate::document::model::*;
+use crate::document::*;
+use crate::document::analysis::Analysis;
+use crate::document::infview::unround_coord;
+use crate::gui::widgets;
+use crate::gui::plan::select_train_combo;
+use crate::config::*;
+
+enum Action {
+    NewTrain,
+    RemoveTrain { train :usize },
+    RemoveLastLocation { train :usize },
+    TrainVehicle { train :usize, vehicle :usize },
+    TrainTime { train :usize, t :f64 },
+    TrainPriority { train :usize, priority :i32 },
+    LookAhead(f64),
+    Policy(ConflictPolicy),
+}
+
+pub fn edit_timetable(config :&Config, analysis :&mut Analysis, tv :&mut TimetableView) {
+    let timetable_idx = tv.timetable_idx;
+    let timetable = match analysis.model().timetables.get(timetable_idx) {
+        Some(t) => t,
+        None => return,
+    };
+    let mut action = None;
+
+    unsafe {
+        igSameLine(0.0,-1.0);
+        if igButton(const_cstr!("\u{f0fe} \u{f239} Train").as_ptr(), ImVec2::zero()) {
+            action = Some(Action::NewTrain);
+        }
+
+        igSameLine(0.0,-1.0);
+        igPushItemWidth(120.0);
+        let mut look_ahead = timetable.look_ahead;
+        igInputDouble(const_cstr!("Look-ahead (s)").as_ptr(), &mut look_ahead,
+                      1.0, 10.0, const_cstr!("%.1f").as_ptr(), 0 as _);
+        if igIsItemEdited() { action = Some(Action::LookAhead(look_ahead.max(0.0))); }
+
+        igSameLine(0.0,-1.0);
+        let policy_name = match timetable.policy {
+            ConflictPolicy::FirstComeFirstServed => const_cstr!("First come, first served"),
+            ConflictPolicy::Priority => const_cstr!("Priority"),
+        };
+        if igBeginCombo(const_cstr!("##policy").as_ptr(), policy_name.as_ptr(), 0) {
+            if igSelectable(const_cstr!("First come, first served").as_ptr(),
+                            timetable.policy == ConflictPolicy::FirstComeFirstServed, 0 as _, ImVec2::zero()) {
+                action = Some(Action::Policy(ConflictPolicy::FirstComeFirstServed));
+            }
+            if igSelectable(const_cstr!("Priority").as_ptr(),
+                            timetable.policy == ConflictPolicy::Priority, 0 as _, ImVec2::zero()) {
+                action = Some(Action::Policy(ConflictPolicy::Priority));
+            }
+            igEndCombo();
+        }
+        igPopItemWidth();
+
+        igSameLine(0.0,-1.0);
+        timetable_icon(config, analysis, timetable_idx);
+
+        widgets::sep();
+
+        for (train_id, train) in timetable.trains.iter() {
+            igPushIDInt(*train_id as _);
+            igAlignTextToFramePadding();
+            if igButton(const_cstr!("\u{f55a}").as_ptr(), ImVec2::zero()) {
+                action = Some(Action::RemoveTrain { train: *train_id });
+            }
+            igSameLine(0.0,-1.0);
+            widgets::show_text(&format!(" \u{f239} ({}) ", train_id));
+            igSameLine(0.0,-1.0);
+            igPushItemWidth(125.0);
+            if let Some(vehicle) = select_train_combo(analysis.model(), &train.vehicle) {
+                action = Some(Action::TrainVehicle { train: *train_id, vehicle });
+            }
+            igSameLine(0.0,-1.0);
+            let mut t = train.entry_time;
+            igInputDouble(const_cstr!("Entry").as_ptr(), &mut t,
+                          1.0, 10.0, const_cstr!("%.1f").as_ptr(), 0 as _);
+            if igIsItemEdited() { action = Some(Action::TrainTime { train: *train_id, t: t.max(0.0) }); }
+            igSameLine(0.0,-1.0);
+            let mut priority = train.priority;
+            igInputInt(const_cstr!("Priority").as_ptr(), &mut priority, 1, 10, 0 as _);
+            if igIsItemEdited() { action = Some(Action::TrainPriority { train: *train_id, priority }); }
+            igPopItemWidth();
+
+            igSameLine(0.0,-1.0);
+            if train.path.len() > 0 {
+                if igButton(const_cstr!("\u{f2ea}").as_ptr(), ImVec2::zero()) {
+                    action = Some(Action::RemoveLastLocation { train: *train_id });
+                }
+                if igIsItemHovered(0) {
+                    igBeginTooltip();
+                    widgets::show_text("Remove last location");
+                    igEndTooltip();
+                }
+                igSameLine(0.0,-1.0);
+                let path = train.path.iter().map(location_name).collect::<Vec<_>>();
+                widgets::show_text(&path.join(" \u{f061} "));
+            } else {
+                widgets::show_text("Right click on a boundary to set the train's entry.");
+            }
+            igPopID();
+        }
+
+        if let Some(Some((_,Err(e)))) = analysis.data().timetables.get(timetable_idx) {
+            widgets::show_text(&format!("\u{f071} {}", e));
+        }
+    }
+
+    if let Some(action) = action {
+        analysis.edit_model(|m| {
+            let mut class = None;
+            let timetable = m.timetables.get_mut(timetable_idx)?;
+            match action {
+                Action::NewTrain => {
+                    let vehicle = m.vehicles.iter().next().map(|(id,_)| *id);
+                    timetable.trains.insert(TimetableTrain {
+                        vehicle, entry_time: 0.0, priority: 0, path: Vec::new(),
+                    });
+                },
+                Action::RemoveTrain { train } => { timetable.trains.remove(train); },
+                Action::RemoveLastLocation { train } => { timetable.trains.get_mut(train)?.path.pop(); },
+                Action::TrainVehicle { train, vehicle } => {
+                    timetable.trains.get_mut(train)?.vehicle = Some(vehicle);
+                },
+                Action::TrainTime { train, t } => {
+                    timetable.trains.get_mut(train)?.entry_time = t;
+                    class = Some(EditClass::TimetableTrainTime(timetable_idx, train));
+                },
+                Action::TrainPriority { train, priority } => {
+                    timetable.trains.get_mut(train)?.priority = priority;
+                    class = Some(EditClass::TimetableTrainPriority(timetable_idx, train));
+                },
+                Action::LookAhead(t) => {
+                    timetable.look_ahead = t;
+                    class = Some(EditClass::TimetableLookAhead(timetable_idx));
+                },
+                Action::Policy(p) => { timetable.policy = p; },
+            }
+            class
+        });
+    }
+}
+
+pub fn timetable_icon(config :&Config, analysis :&Analysis, timetable_idx :usize) {
+    unsafe {
+    match analysis.data().timetables.get(timetable_idx) {
+        Some(Some((generation, result))) if generation == analysis.generation() => {
+            if result.is_ok() {
+                igPushStyleColorU32(ImGuiCol__ImGuiCol_Text as _,
+                                    config.color_u32(RailUIColorName::CanvasSignalProceed));
+                widgets::show_text("\u{f00c}");
+            } else {
+                igPushStyleColorU32(ImGuiCol__ImGuiCol_Text as _,
+                                    config.color_u32(RailUIColorName::CanvasSignalStop));
+                widgets::show_text("\u{f00d}");
+            }
+            igPopStyleColor(1);
+        },
+        _ => {
+            // Simulation still running
+            igPushStyleColorU32(ImGuiCol__ImGuiCol_Text as _,
+                                config.color_u32(RailUIColorName::CanvasTrackDrawing));
+            widgets::show_text("\u{f110}");
+            igPopStyleColor(1);
+        },
+    }
+    }
+}
+
+fn location_name(r :&Ref) -> String {
+    match r {
+        Ref::Node(pt) => format!("({},{})", pt.x, pt.y),
+        Ref::Object(pta) => {
+            let p = unround_coord(*pta);
+            format!("\u{f637} ({:.1},{:.1})", p.x, p.y)
+        },
+        Ref::LineSeg(a,b) => format!("({},{})-({},{})", a.x, a.y, b.x, b.y),
+    }
+}
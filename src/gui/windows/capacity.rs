@@ -0,0 +1,144 @@
+use const_cstr::*;
+use std::ffi::CString;
+use backend_glfw::imgui::*;
+
+use crate::config::*;
+use crate::gui::widgets;
+use crate::gui::widgets::Draw;
+use crate::document::analysis::*;
+use crate::document::capacity::*;
+use crate::util::VecMap;
+
+pub struct CapacityWindow {
+    dispatch_idx :Option<usize>,
+    params :CapacityParams,
+}
+
+impl CapacityWindow {
+    pub fn new() -> CapacityWindow {
+        CapacityWindow {
+            dispatch_idx: None,
+            params: Default::default(),
+        }
+    }
+
+    pub fn draw(&mut self, config :&Config, analysis :&Analysis) -> bool {
+        let mut keep_open = true;
+        unsafe {
+            widgets::next_window_center_when_appearing();
+            igBegin(const_cstr!("Capacity consumption").as_ptr(), &mut keep_open as _, 0 as _);
+
+            let current_name = self.dispatch_idx.and_then(|i| analysis.model().dispatches.get(i))
+                .map(|d| d.name.clone()).unwrap_or(format!("Select dispatch"));
+            let current_name = CString::new(current_name).unwrap();
+            if igBeginCombo(const_cstr!("Dispatch").as_ptr(), current_name.as_ptr(), 0) {
+                for (id,d) in analysis.model().dispatches.iter() {
+                    let name = CString::new(d.name.clone()).unwrap();
+                    if igSelectable(name.as_ptr(), self.dispatch_idx == Some(*id), 0 as _, ImVec2::zero()) {
+                        self.dispatch_idx = Some(*id);
+                    }
+                }
+                igEndCombo();
+            }
+
+            let output = self.dispatch_idx.and_then(|i| analysis.data().dispatch.vecmap_get(i))
+                .filter(|(g,_)| g == analysis.generation()).map(|(_,d)| d);
+            let output = match output {
+                Some(d) => d,
+                None => {
+                    widgets::show_text("No simulated dispatch selected.");
+                    igEnd();
+                    return keep_open;
+                }
+            };
+
+            let format = const_cstr!("%.1f");
+            let p = &mut self.params;
+            igInputDouble(const_cstr!("Setup time (s)").as_ptr(), &mut p.setup_time,
+                          1.0, 10.0, format.as_ptr(), 0 as _);
+            igInputDouble(const_cstr!("Release time (s)").as_ptr(), &mut p.release_time,
+                          1.0, 10.0, format.as_ptr(), 0 as _);
+            p.setup_time = p.setup_time.max(0.0);
+            p.release_time = p.release_time.max(0.0);
+
+            if !p.section.0.is_finite() || !p.section.1.is_finite() {
+                p.section = (output.pos_interval.0 as f64, output.pos_interval.1 as f64);
+            }
+            igInputDouble(const_cstr!("Section from (km)").as_ptr(), &mut p.section.0,
+                          10.0, 100.0, const_cstr!("%.0f").as_ptr(), 0 as _);
+            igInputDouble(const_cstr!("Section to (km)").as_ptr(), &mut p.section.1,
+                          10.0, 100.0, const_cstr!("%.0f").as_ptr(), 0 as _);
+            igSameLine(0.0,-1.0);
+            if igButton(const_cstr!("Whole line").as_ptr(), ImVec2::zero()) {
+                p.section = (output.pos_interval.0 as f64, output.pos_interval.1 as f64);
+            }
+
+            let mut use_window = p.window.is_some();
+            if igCheckbox(const_cstr!("Evaluation window").as_ptr(), &mut use_window) {
+                p.window = if use_window { Some((0.0, 3600.0)) } else { None };
+            }
+            if let Some(w) = p.window.as_mut() {
+                igInputDouble(const_cstr!("Window from (s)").as_ptr(), &mut w.0,
+                              60.0, 600.0, format.as_ptr(), 0 as _);
+                igInputDouble(const_cstr!("Window to (s)").as_ptr(), &mut w.1,
+                              60.0, 600.0, format.as_ptr(), 0 as _);
+                w.1 = w.1.max(w.0 + 1.0);
+            }
+
+            widgets::sep();
+            match capacity_consumption(&output.diagram, &self.params) {
+                Some(result) => {
+                    widgets::show_text(&format!("Period: {:.1} s", result.period.1 - result.period.0));
+                    widgets::show_text(&format!("Occupation time: {:.1} s", result.occupation_time));
+                    widgets::show_text(&format!("Capacity consumption: {:.1} %", result.consumption));
+                    stairway_diagrams(config, &result, self.params.section);
+                },
+                None => { widgets::show_text("No trains in the selected line section and time window."); },
+            }
+
+            igEnd();
+        }
+        keep_open
+    }
+}
+
+/// Original and compressed blocking-time stairways side by side.
+fn stairway_diagrams(config :&Config, result :&CapacityConsumption, section :(f64,f64)) {
+    let t0 = result.period.0;
+    let time = (t0, t0 + (result.period.1 - t0).max(result.occupation_time));
+    unsafe {
+        let avail :ImVec2 = igGetContentRegionAvail_nonUDT2().into();
+        let size = ImVec2 { x: 0.5*avail.x - 4.0, y: avail.y.max(200.0) };
+        for (i,(title, stairways)) in [("Timetable", &result.stairways),
+                                       ("Compressed", &result.compressed)].iter().enumerate() {
+            if i > 0 { igSameLine(0.0,-1.0); }
+            igBeginGroup();
+            widgets::show_text(title);
+            let id = if i == 0 { const_cstr!("stairs") } else { const_cstr!("stairscomp") };
+            let draw = widgets::canvas(size, config.color_u32(RailUIColorName::GraphBackground), id.as_ptr());
+            draw.begin_draw();
+            draw_stairways(config, &draw, stairways, section, time);
+            draw.end_draw();
+            igEndGroup();
+        }
+    }
+}
+
+fn draw_stairways(config :&Config, draw :&Draw, stairways :&[Stairway], section :(f64,f64), time :(f64,f64)) {
+    let col_fill = config.color_u32(RailUIColorName::GraphBlockReserved);
+    let col_border = config.color_u32(RailUIColorName::GraphBlockBorder);
+    let to_screen = |t :f64, x :f64| ImVec2 {
+        x: draw.pos.x + draw.size.x*(((x - section.0)/(section.1 - section.0)) as f32),
+        y: draw.pos.y + draw.size.y*(((t - time.0)/(time.1 - time.0)) as f32),
+    };
+    for s in stairways {
+        for b in s.blocks.iter() {
+            unsafe {
+                ImDrawList_AddRectFilled(draw.draw_list, to_screen(b.time.0, b.pos.0),
+                                         to_screen(b.time.1, b.pos.1), col_fill, 0.0, 0);
+                ImDrawList_AddRect(draw.draw_list, to_screen(b.time.0, b.pos.0),
+                                   to_screen(b.time.1, b.pos.1), col_border, 0.0, 0, 1.0);
+            }
+        }
+    }
+}
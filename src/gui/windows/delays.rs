@@ -0,0 +1,209 @@
+use const_cstr::*;
+use std::ffi::CString;
+use std::sync::mpsc;
+use log::*;
+use backend_glfw::imgui::*;
+
+use crate::gui::widgets;
+use crate::document::analysis::*;
+use crate::document::delays::*;
+use crate::util::VecMap;
+use crate::app::*;
+
+pub struct DelaysWindow {
+    dispatch_idx :Option<usize>,
+    distributions :DelayDistributions,
+    runs :i32,
+    seed :i32,
+
+    nominal :Option<TrainVisits>,
+    results :Vec<RunResult>,
+    errors :usize,
+    stats :Option<Vec<TrainDelays>>,
+
+    thread :Option<mpsc::Receiver<Result<RunResult,String>>>,
+    thread_pool :BackgroundJobs,
+}
+
+impl DelaysWindow {
+    pub fn new(bg :BackgroundJobs) -> DelaysWindow {
+        DelaysWindow {
+            dispatch_idx: None,
+            distributions: Default::default(),
+            runs: 100,
+            seed: 1,
+            nominal: None,
+            results: Vec::new(),
+            errors: 0,
+            stats: None,
+            thread: None,
+            thread_pool: bg,
+        }
+    }
+
+    pub fn draw(&mut self, analysis :&Analysis) -> bool {
+        let mut keep_open = true;
+        unsafe {
+            widgets::next_window_center_when_appearing();
+            igBegin(const_cstr!("Delay simulation").as_ptr(), &mut keep_open as _, 0 as _);
+
+            let current_name = self.dispatch_idx.and_then(|i| analysis.model().dispatches.get(i))
+                .map(|d| d.name.clone()).unwrap_or(format!("Select dispatch"));
+            let current_name = CString::new(current_name).unwrap();
+            if igBeginCombo(const_cstr!("Dispatch").as_ptr(), current_name.as_ptr(), 0) {
+                for (id,d) in analysis.model().dispatches.iter() {
+                    let name = CString::new(d.name.clone()).unwrap();
+                    if igSelectable(name.as_ptr(), self.dispatch_idx == Some(*id), 0 as _, ImVec2::zero()) {
+                        self.dispatch_idx = Some(*id);
+                    }
+                }
+                igEndCombo();
+            }
+
+            let format = const_cstr!("%.1f");
+            let d = &mut self.distributions;
+            igInputDouble(const_cstr!("Mean entry delay (s)").as_ptr(), &mut d.entry_delay,
+                          1.0, 10.0, format.as_ptr(), 0 as _);
+            igInputDouble(const_cstr!("Max. dwell variation (s)").as_ptr(), &mut d.dwell_variation,
+                          1.0, 10.0, format.as_ptr(), 0 as _);
+            igInputDouble(const_cstr!("Min. performance factor").as_ptr(), &mut d.min_performance,
+                          0.01, 0.1, const_cstr!("%.2f").as_ptr(), 0 as _);
+            d.entry_delay = d.entry_delay.max(0.0);
+            d.dwell_variation = d.dwell_variation.max(0.0);
+            d.min_performance = d.min_performance.max(0.1).min(1.0);
+            igInputInt(const_cstr!("Runs").as_ptr(), &mut self.runs, 10, 100, 0 as _);
+            self.runs = self.runs.max(1);
+            igInputInt(const_cstr!("Seed").as_ptr(), &mut self.seed, 1, 100, 0 as _);
+
+            if self.thread.is_some() {
+                let done = self.results.len() + self.errors;
+                igProgressBar(done as f32 / self.runs as f32, ImVec2 { x: -1.0, y: 0.0 }, std::ptr::null());
+            } else {
+                if igButton(const_cstr!("\u{f04b} Run").as_ptr(), ImVec2::zero()) {
+                    self.start(analysis);
+                }
+                if let Some(stats) = &self.stats {
+                    igSameLine(0.0,-1.0);
+                    if igButton(const_cstr!("\u{f56e} Export CSV...").as_ptr(), ImVec2::zero()) {
+                        export_csv(stats);
+                    }
+                }
+            }
+
+            if self.errors > 0 {
+                widgets::show_text(&format!("\u{f071} {} runs failed.", self.errors));
+            }
+
+            widgets::sep();
+            if let Some(stats) = &self.stats {
+                delay_table(stats);
+            }
+
+            igEnd();
+        }
+        keep_open
+    }
+
+    pub fn start(&mut self, analysis :&Analysis) {
+        let dispatch_idx = match self.dispatch_idx { Some(i) => i, None => return };
+        let (dgraph, il) = match (&analysis.data().dgraph, &analysis.data().interlocking) {
+            (Some((_,dgraph)), Some((_,il))) => (dgraph.clone(), il.clone()),
+            _ => return,
+        };
+        let nominal = match analysis.data().dispatch.vecmap_get(dispatch_idx) {
+            Some((_,output)) => train_visits(&dgraph, &output.history),
+            _ => return,
+        };
+        let commands = match analysis.model().dispatches.get(dispatch_idx) {
+            Some(d) => d.commands.clone(),
+            _ => return,
+        };
+        let vehicles = analysis.model().vehicles.data().to_vec();
+
+        self.nominal = Some(nominal);
+        self.results = Vec::new();
+        self.errors = 0;
+        self.stats = None;
+
+        let (tx,rx) = mpsc::channel();
+        self.thread = Some(rx);
+        for run in 0..self.runs {
+            let (tx, dgraph, il) = (tx.clone(), dgraph.clone(), il.clone());
+            let (commands, vehicles) = (commands.clone(), vehicles.clone());
+            let distributions = self.distributions;
+            let seed = (self.seed as u64) << 32 | run as u64;
+            self.thread_pool.execute(move || {
+                let result = simulate_run(&dgraph, &il, &vehicles, &commands, &distributions, seed);
+                let _ = tx.send(result);
+            });
+        }
+    }
+}
+
+impl BackgroundUpdates for DelaysWindow {
+    fn check(&mut self) {
+        if let Some(rx) = &mut self.thread {
+            loop {
+                match rx.try_recv() {
+                    Ok(Ok(result)) => { self.results.push(result); },
+                    Ok(Err(e)) => {
+                        error!("Delay simulation run failed: {}", e);
+                        self.errors += 1;
+                    },
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        self.thread = None;
+                        if let Some(nominal) = &self.nominal {
+                            self.stats = Some(delay_statistics(nominal, &self.results));
+                        }
+                        break;
+                    },
+                    Err(mpsc::TryRecvError::Empty) => { break; }
+                }
+            }
+        }
+    }
+}
+
+fn delay_table(stats :&[TrainDelays]) {
+    unsafe {
+        let n_cols = 5 + PERCENTILES.len();
+        igColumns(n_cols as _, const_cstr!("delaycols").as_ptr(), true);
+        let mut header = vec![format!("Train"), format!("Location"), format!("Mean")];
+        header.extend(PERCENTILES.iter().map(|p| format!("P{}", p)));
+        header.push(format!("Knock-on"));
+        header.push(format!("Missing"));
+        for h in header {
+            widgets::show_text(&h);
+            igNextColumn();
+        }
+        igSeparator();
+
+        let row = |train :&str, loc :&str, d :&DelayStats| {
+            widgets::show_text(train); igNextColumn();
+            widgets::show_text(loc); igNextColumn();
+            widgets::show_text(&format!("{:.1}", d.mean)); igNextColumn();
+            for p in d.percentiles.iter() {
+                widgets::show_text(&format!("{:.1}", p)); igNextColumn();
+            }
+            widgets::show_text(&format!("{:.1}", d.knock_on)); igNextColumn();
+            widgets::show_text(&format!("{}", d.missing)); igNextColumn();
+        };
+
+        for train in stats {
+            row(&train.name, "Total", &train.total);
+            for (pt,_t,d) in train.visits.iter() {
+                row("", &format!("({},{})", pt.x, pt.y), d);
+            }
+        }
+        igColumns(1, std::ptr::null(), false);
+    }
+}
+
+fn export_csv(stats :&[TrainDelays]) {
+    if let Some(filename) = tinyfiledialogs::save_file_dialog("Export delay statistics", "delays.csv") {
+        match std::fs::write(&filename, to_csv(stats)) {
+            Ok(()) => info!("Delay statistics written to {:?}", filename),
+            Err(e) => error!("Could not write delay statistics: {}", e),
+        }
+    }
+}
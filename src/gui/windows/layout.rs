@@ -0,0 +1,92 @@
+use const_cstr::*;
+use std::ffi::CString;
+use backend_glfw::imgui::*;
+
+use crate::document::Document;
+use crate::document::model::*;
+use crate::document::layout;
+use crate::gui::widgets;
+use crate::gui::infrastructure::start_auto_layout;
+
+pub fn layout_options_window(popen :&mut bool, doc :&mut Document) {
+    if !*popen { return; }
+    unsafe {
+    widgets::next_window_center_when_appearing();
+    igBegin(const_cstr!("Layout options").as_ptr(), popen as *mut bool, 0 as _);
+
+    widgets::show_text("Goals, in order of priority:");
+    let goals = &mut doc.inf_view.layout_options.goals;
+    let mut swap = None;
+    for i in 0..goals.len() {
+        igPushIDInt(i as _);
+        if igButton(const_cstr!("\u{f062}").as_ptr(), ImVec2::zero()) && i > 0 {
+            swap = Some((i-1,i));
+        }
+        igSameLine(0.0,-1.0);
+        if igButton(const_cstr!("\u{f063}").as_ptr(), ImVec2::zero()) && i+1 < goals.len() {
+            swap = Some((i,i+1));
+        }
+        igSameLine(0.0,-1.0);
+        let name = CString::new(layout::goal_name(goals[i].0)).unwrap();
+        igCheckbox(name.as_ptr(), &mut goals[i].1);
+        igPopID();
+    }
+    if let Some((a,b)) = swap { goals.swap(a,b); }
+
+    widgets::sep();
+    let selected_nodes = doc.inf_view.selection.iter()
+        .filter_map(|x| if let Ref::Node(pt) = x { Some(*pt) } else { None }).collect::<Vec<_>>();
+    let options = &mut doc.inf_view.layout_options;
+    widgets::show_text(&format!("{} fixed nodes.", options.pinned.len()));
+    for (label, pin) in &[(const_cstr!("Fix x"), (true,false)),
+                          (const_cstr!("Fix y"), (false,true)),
+                          (const_cstr!("Fix x and y"), (true,true))] {
+        if igButton(label.as_ptr(), ImVec2::zero()) {
+            for pt in selected_nodes.iter() {
+                let p = options.pinned.entry(*pt).or_insert((false,false));
+                *p = (p.0 || pin.0, p.1 || pin.1);
+            }
+        }
+        igSameLine(0.0,-1.0);
+    }
+    if igButton(const_cstr!("Release").as_ptr(), ImVec2::zero()) {
+        for pt in selected_nodes.iter() { options.pinned.remove(pt); }
+    }
+    if igIsItemHovered(0) {
+        igBeginTooltip();
+        widgets::show_text("Fixed coordinates apply to the selected nodes.");
+        igEndTooltip();
+    }
+
+    widgets::sep();
+    widgets::show_text(&format!("{} main tracks.", options.main_tracks.len()));
+    let topology = doc.analysis.data().topology.as_ref()
+        .filter(|(g,_)| g == doc.analysis.generation()).map(|(_,t)| t.clone());
+    if igButton(const_cstr!("Add selected tracks").as_ptr(), ImVec2::zero()) {
+        if let Some(topo) = &topology {
+            options.main_tracks.extend(layout::selected_tracks(topo, &doc.inf_view.selection));
+        }
+    }
+    igSameLine(0.0,-1.0);
+    if igButton(const_cstr!("Remove selected tracks").as_ptr(), ImVec2::zero()) {
+        if let Some(topo) = &topology {
+            for t in layout::selected_tracks(topo, &doc.inf_view.selection) {
+                options.main_tracks.remove(&t);
+            }
+        }
+    }
+    if !options.main_tracks.is_empty() &&
+        !options.goals.iter().any(|(g,used)| *used && *g == railplotlib::solvers::Goal::MainTrackHeight) {
+        widgets::show_text("Enable the main track height goal to keep main tracks together.");
+    }
+
+    widgets::sep();
+    if doc.inf_view.layout_job.is_some() {
+        widgets::show_text("Running auto-layout...");
+    } else if igButton(const_cstr!("\u{f0d0} Auto-layout").as_ptr(), ImVec2::zero()) {
+        start_auto_layout(&doc.analysis, &mut doc.inf_view);
+    }
+
+    igEnd();
+    }
+}
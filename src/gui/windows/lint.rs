@@ -0,0 +1,183 @@
+use matches::matches;
+use const_cstr::*;
+use std::sync::mpsc;
+use log::*;
+use backend_glfw::imgui::*;
+
+use crate::gui::widgets;
+use crate::gui::infrastructure::model_rename_object;
+use crate::gui::windows::synthesis::{loc_on_track, design_objects};
+use crate::document::model::*;
+use crate::document::objects::*;
+use crate::document::analysis::*;
+use crate::document::infview::{InfView, round_coord};
+use crate::synthesis::lint::*;
+use crate::app::*;
+
+pub struct LintWindow {
+    issues :Vec<LintIssue>,
+    error :Option<String>,
+    /// Model generation which the issues were computed from.
+    generation :Option<Generation>,
+
+    thread :Option<mpsc::Receiver<(Generation, Result<Vec<LintIssue>,String>)>>,
+    thread_pool :BackgroundJobs,
+}
+
+impl LintWindow {
+    pub fn new(bg :BackgroundJobs) -> LintWindow {
+        LintWindow {
+            issues: Vec::new(),
+            error: None,
+            generation: None,
+            thread: None,
+            thread_pool: bg,
+        }
+    }
+
+    pub fn start(&mut self, analysis :&Analysis) {
+        let (tx,rx) = mpsc::channel();
+        self.thread = Some(rx);
+        let model = analysis.model().clone();
+        let generation = *analysis.generation();
+        self.thread_pool.execute(move || {
+            let _ = tx.send((generation, lint(&model)));
+        });
+    }
+
+    pub fn draw(&mut self, analysis :&mut Analysis, inf_view :&mut InfView) -> bool {
+        let mut keep_open = true;
+        unsafe {
+            widgets::next_window_center_when_appearing();
+            igBegin(const_cstr!("Signalling lint").as_ptr(), &mut keep_open as _, 0 as _);
+
+            if self.thread.is_some() {
+                widgets::show_text("\u{f110} Checking signalling against all plans.");
+            } else {
+                if igButton(const_cstr!("\u{f021} Check again").as_ptr(), ImVec2::zero()) {
+                    self.start(analysis);
+                }
+                if self.generation.is_some() && self.generation.as_ref() != Some(analysis.generation()) {
+                    igSameLine(0.0,-1.0);
+                    widgets::show_text("\u{f071} The model has changed, issues may be outdated.");
+                }
+            }
+
+            if let Some(e) = &self.error {
+                widgets::show_text(&format!("\u{f00d} {}", e));
+            } else if self.thread.is_none() && self.issues.len() == 0 {
+                widgets::show_text("\u{f00c} No issues found.");
+            }
+
+            // Fixes are only offered for issues computed from the current model,
+            // since an earlier fix may have changed or resolved the others.
+            let can_fix = self.thread.is_none() && self.generation.as_ref() == Some(analysis.generation());
+            let mut fixed = None;
+            for (i,issue) in self.issues.iter().enumerate() {
+                igPushIDInt(i as _);
+                if can_fix {
+                    if igButton(const_cstr!("Apply fix").as_ptr(), ImVec2::zero()) {
+                        fixed = Some(i);
+                    }
+                    igSameLine(0.0,-1.0);
+                }
+                if igSelectable(const_cstr!("##issue").as_ptr(), false, 0 as _, ImVec2::zero()) {
+                    inf_view.selection = issue.objects().into_iter().map(Ref::Object).collect();
+                    inf_view.preview_objects = match issue {
+                        LintIssue::FasterNewSignal(s) => design_objects(analysis.model(),
+                                                          &vec![(s.track, s.pos, s.function, s.dir)]),
+                        _ => Vec::new(),
+                    };
+                }
+                igSameLine(0.0,-1.0);
+                widgets::show_text(&issue.message());
+                igPopID();
+            }
+
+            if let Some(i) = fixed {
+                let issue = self.issues.remove(i);
+                analysis.edit_model(|m| { apply_fix(m, &issue); None });
+                inf_view.preview_objects.clear();
+                self.start(analysis);
+            }
+
+            igEnd();
+        }
+        if !keep_open { inf_view.preview_objects.clear(); }
+        keep_open
+    }
+}
+
+fn apply_fix(model :&mut Model, issue :&LintIssue) {
+    match issue {
+        LintIssue::RedundantSignal(id, true) => {
+            if let Some(obj) = model.objects.get_mut(id) {
+                obj.functions.retain(|f| !matches!(f, Function::MainSignal { .. }));
+                if !obj.functions.contains(&Function::Detector) {
+                    obj.functions.push(Function::Detector);
+                }
+            }
+        },
+        LintIssue::RedundantSignal(id, false) => {
+            remove_function(model, *id, |f| matches!(f, Function::MainSignal { .. }));
+        },
+        LintIssue::RedundantDetector(id) => {
+            remove_function(model, *id, |f| *f == Function::Detector);
+        },
+        LintIssue::FasterSignalPosition(m) | LintIssue::FasterDetectorPosition(m) => {
+            use crate::document::topology;
+            let topo = match topology::convert(model, 50.0) {
+                Ok(t) => t,
+                Err(_) => { warn!("Lint fix: could not convert topology"); return; }
+            };
+            let (from,_) = loc_on_track(&topo.interval_lines, m.track, m.from);
+            let (to,_) = loc_on_track(&topo.interval_lines, m.track, m.to);
+            for id in m.objects.iter() {
+                if let Some(mut obj) = model.objects.remove(id) {
+                    let target = obj.loc + (to - from);
+                    obj.move_to(model, target);
+                    let new_id = round_coord(obj.loc);
+                    model.objects.insert(new_id, obj);
+                    model_rename_object(model, *id, new_id);
+                }
+            }
+        },
+        LintIssue::FasterNewSignal(s) => {
+            for obj in design_objects(model, &vec![(s.track, s.pos, s.function, s.dir)]) {
+                model.objects.insert(round_coord(obj.loc), obj);
+            }
+        },
+    }
+}
+
+/// Remove the matching functions from the object, and the object itself if no functions remain.
+fn remove_function(model :&mut Model, id :PtA, f :impl Fn(&Function) -> bool) {
+    if let Some(obj) = model.objects.get_mut(&id) {
+        obj.functions.retain(|x| !f(x));
+        if obj.functions.len() == 0 {
+            model.delete(Ref::Object(id));
+        }
+    }
+}
+
+impl BackgroundUpdates for LintWindow {
+    fn check(&mut self) {
+        if let Some(rx) = &mut self.thread {
+            match rx.try_recv() {
+                Ok((generation, result)) => {
+                    self.generation = Some(generation);
+                    match result {
+                        Ok(issues) => { self.issues = issues; self.error = None; },
+                        Err(e) => { self.issues = Vec::new(); self.error = Some(e); },
+                    }
+                    self.thread = None;
+                },
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.thread = None;
+                    self.error = Some(format!("Lint pass failed."));
+                },
+                Err(mpsc::TryRecvError::Empty) => {},
+            }
+        }
+    }
+}
@@ -4,4 +4,8 @@ pub mod config;
 pub mod quit;
 pub mod logview;
 pub mod synthesis;
+pub mod delays;
+pub mod capacity;
+pub mod lint;
 
+pub mod layout;
@@ -7,33 +7,48 @@ use std::sync::mpsc;
 use std::sync::Arc;
 use log::*;
 
+use crate::config::*;
 use crate::gui::widgets;
 use crate::document::model::*;
 use crate::document::analysis::*;
 use crate::document::objects;
 use crate::document::objects::*;
-use crate::document::infview::round_coord;
+use crate::document::infview::{InfView, round_coord};
 use crate::synthesis::*;
 use crate::app::*;
 
 pub struct SynthesisWindow {
     model :Arc<Model>,
 
-    result_models :Vec<(String, f64, Design)>,
+    result_models :Vec<(String, Option<f64>, Design)>,
     results_ranking :Vec<usize>,
     results_log :Vec<String>,
 
     enabled_planspecs :HashMap<usize,bool>,
+    plan_weights :HashMap<usize,f64>,
+    weights_changed :bool,
+    cost_model :CostModel,
+    selected :Option<usize>,
+
+    /// Hash of the inputs of the current run.
+    key :u64,
+    /// Progress of the current run at the last completed signal set.
+    checkpoint :Option<SynthesisCheckpoint>,
+    paused :bool,
 
     thread: Option<mpsc::Receiver<FullSynMsg>>,
+    cancel: CancelToken,
     thread_pool: BackgroundJobs,
 }
 
-fn add_objects(analysis :&mut Analysis, objs :&Design) {
+/// Model objects for the movable objects of a design, including the detector
+/// which is built together with each main signal.
+pub fn design_objects(model :&Model, objs :&Design) -> Vec<objects::Object> {
     use crate::document::topology;
-    let mut model = analysis.model().clone();
-    let topo = topology::convert(&model, 50.0).unwrap();
-    for (track_idx, pos, func, dir) in objs.iter() {
+    let topo = topology::convert(model, 50.0).unwrap();
+    let (fixed,_) = constraints(model, &topo);
+    let mut output = Vec::new();
+    for (track_idx, pos, func, dir) in objs.iter().filter(|o| !fixed.contains(o)) {
         let sideways = match dir {
             None => 0.0,
             Some(AB::A) => 0.01,
@@ -42,31 +57,33 @@ fn add_objects(analysis :&mut Analysis, objs :&Design) {
 
         let (pt,tangent) = loc_on_track(&topo.interval_lines, *track_idx, *pos);
         let normal = glm::vec2(tangent.y, -tangent.x);
-        let mut obj = objects::Object {
-            loc: pt, 
-            tangent: glm::vec2(tangent.x.round() as _, tangent.y.round() as _),
-            functions: vec![*func],
-        };
-        obj.move_to(&model, pt + sideways*glm::vec2(normal.x as f32, normal.y as f32));
-        //println!("ADding object {:?}", obj);
-        model.objects.insert(round_coord(obj.loc), obj);
-
+        let mut functions = vec![*func];
         if matches!(func, Function::MainSignal { .. } ) {
+            functions.push(Function::Detector);
+        }
+        for f in functions {
             let mut obj = objects::Object {
                 loc: pt, 
                 tangent: glm::vec2(tangent.x.round() as _, tangent.y.round() as _),
-                functions: vec![Function::Detector],
+                functions: vec![f],
             };
-            obj.move_to(&model, pt + sideways*glm::vec2(normal.x as f32, normal.y as f32));
-            //println!("ADding object {:?}", obj);
-            model.objects.insert(round_coord(obj.loc), obj);
+            obj.move_to(model, pt + sideways*glm::vec2(normal.x as f32, normal.y as f32));
+            output.push(obj);
         }
     }
+    output
+}
 
+/// Add a design, found for the synthesis input `design_model`, to the current model.
+fn add_objects(analysis :&mut Analysis, design_model :&Model, objs :&Design) {
+    let mut model = analysis.model().clone();
+    for obj in design_objects(design_model, objs) {
+        model.objects.insert(round_coord(obj.loc), obj);
+    }
     analysis.set_model(model, None);
 }
 
-fn loc_on_track(interval_lines :&Vec<Vec<(OrderedFloat<f64>, PtC)>>, track_idx :usize, l :f64) -> (PtC, PtC) {
+pub fn loc_on_track(interval_lines :&Vec<Vec<(OrderedFloat<f64>, PtC)>>, track_idx :usize, l :f64) -> (PtC, PtC) {
     let lines = &interval_lines[track_idx];
     for ((OrderedFloat(l_a),p_a),(OrderedFloat(l_b),p_b)) in lines.iter().zip(lines.iter().skip(1)) {
         if *l_a <= l && l <= *l_b {
@@ -87,14 +104,22 @@ impl SynthesisWindow {
             results_log :Vec::new(),
 
             enabled_planspecs :HashMap::new(),
+            plan_weights :HashMap::new(),
+            weights_changed :false,
+            cost_model :Default::default(),
+            selected :None,
+            key: 0,
+            checkpoint: None,
+            paused: false,
             thread: None,
+            cancel: CancelToken::new(),
             thread_pool: bg,
         };
         win.start();
         win
     }
 
-    pub fn draw(&mut self, current_doc :&mut Analysis) -> bool {
+    pub fn draw(&mut self, config :&Config, current_doc :&mut Analysis, inf_view :&mut InfView) -> bool {
         let mut keep_open = true;
         use backend_glfw::imgui::*;
         unsafe {
@@ -115,15 +140,55 @@ impl SynthesisWindow {
                     self.start();
                 }
                 igSameLine(0.0,-1.0);
+                let mut weight = self.plan_weights.get(plan_id).cloned().unwrap_or(1.0);
+                igPushItemWidth(80.0);
+                igInputDouble(const_cstr!("##weight").as_ptr(), &mut weight, 0.0, 0.0, 
+                              const_cstr!("%.2f").as_ptr(), 0 as _);
+                igPopItemWidth();
+                if igIsItemEdited() {
+                    self.plan_weights.insert(*plan_id, weight.max(0.0));
+                    self.weights_changed = true;
+                }
+                if igIsItemHovered(0) {
+                    igBeginTooltip();
+                    widgets::show_text("Running time weight");
+                    igEndTooltip();
+                }
+                igSameLine(0.0,-1.0);
                 widgets::show_text(&plan.name);
                 igPopID();
             }
 
+            if self.weights_changed {
+                if igButton(const_cstr!("\u{f021} Restart with new weights").as_ptr(), ImVec2::zero()) {
+                    self.start();
+                }
+            }
+
+            igSeparator();
+            widgets::show_text("\u{f155} Prices:");
+            let format = const_cstr!("%.2f");
+            let c = &mut self.cost_model;
+            igInputDouble(const_cstr!("Signal").as_ptr(), &mut c.signal_price, 0.1, 1.0, format.as_ptr(), 0 as _);
+            igInputDouble(const_cstr!("Detector").as_ptr(), &mut c.detector_price, 0.1, 1.0, format.as_ptr(), 0 as _);
+            igInputDouble(const_cstr!("Distant signal").as_ptr(), &mut c.distant_price, 0.1, 1.0, format.as_ptr(), 0 as _);
+
             igEndChild();
             igSameLine(0.0,-1.0);
             igBeginChild(const_cstr!("sdr").as_ptr(), ImVec2 { x: 0.0, y: -150.0 }, true, 0 as _);
             if self.thread.is_some() {
                 widgets::show_text("\u{f110} Running.");
+                igSameLine(0.0,-1.0);
+                if igButton(const_cstr!("\u{f04c} Pause").as_ptr(), ImVec2::zero()) {
+                    self.pause(current_doc);
+                }
+            } else if self.paused {
+                widgets::show_text("\u{f04c} Paused.");
+                igSameLine(0.0,-1.0);
+                if igButton(const_cstr!("\u{f04b} Resume").as_ptr(), ImVec2::zero()) {
+                    let checkpoint = self.checkpoint.clone();
+                    self.run(checkpoint);
+                }
             } else {
                 if self.result_models.len() > 0 {
                     widgets::show_text("\u{f00c} Designs available.");
@@ -132,11 +197,13 @@ impl SynthesisWindow {
                 }
             }
 
+            self.pareto_plot(config, current_doc, inf_view);
+
             for i in self.results_ranking.iter() {
                 igPushIDInt(*i as _);
                 let (n,score,objs) = &self.result_models[*i];
                 if igSelectable(const_cstr!("##msg").as_ptr(), false, 0 as _, ImVec2::zero()) {
-                    add_objects(current_doc, objs);
+                    add_objects(current_doc, &self.model, objs);
                 }
 
                 if igIsItemHovered(0) {
@@ -147,8 +214,9 @@ impl SynthesisWindow {
                     igEndTooltip();
                 }
 
-                igSameLine(0.0,-1.0); widgets::show_text(&format!("Design {} @ {:.2} with {} objs.",
-                                                                  n, score, objs.len()));
+                let score = score.map(|s| format!("{:.2}", s)).unwrap_or(format!("(not measured)"));
+                igSameLine(0.0,-1.0); widgets::show_text(&format!("Design {} @ {} with {} objs, price {:.2}.",
+                                                                  n, score, objs.len(), self.cost_model.price(objs)));
                 igPopID();
             }
 
@@ -164,12 +232,115 @@ impl SynthesisWindow {
             igEnd();
         }
 
+        if !keep_open { inf_view.preview_objects.clear(); }
         keep_open
     }
 
+    /// Scatter plot of price versus weighted running time, with the Pareto front
+    /// highlighted. Clicking a design previews it on the infrastructure canvas.
+    fn pareto_plot(&mut self, config :&Config, analysis :&mut Analysis, inf_view :&mut InfView) {
+        use backend_glfw::imgui::*;
+        // The maximal design has no score and is left out of the plot.
+        let points = self.result_models.iter()
+            .map(|(_,score,design)| (self.cost_model.price(design), score.unwrap_or(std::f64::INFINITY)))
+            .collect::<Vec<_>>();
+        let front = pareto_front(&points);
+        if self.selected.is_none() { inf_view.preview_objects.clear(); }
+        let measured = points.iter().enumerate()
+            .filter(|(_,(x,y))| x.is_finite() && y.is_finite()).collect::<Vec<_>>();
+        if measured.len() == 0 { return; }
+
+        let lo = |f :fn(&(f64,f64)) -> f64| measured.iter().map(|(_,p)| f(p)).fold(std::f64::INFINITY, f64::min);
+        let hi = |f :fn(&(f64,f64)) -> f64| measured.iter().map(|(_,p)| f(p)).fold(std::f64::NEG_INFINITY, f64::max);
+        let (x_lo,x_hi) = (lo(|p| p.0), hi(|p| p.0));
+        let (y_lo,y_hi) = (lo(|p| p.1), hi(|p| p.1));
+
+        unsafe {
+            widgets::show_text("Price versus weighted running time:");
+            let size = ImVec2 { x: igGetContentRegionAvail_nonUDT2().x, y: 200.0 };
+            let draw = widgets::canvas(size, config.color_u32(RailUIColorName::GraphBackground),
+                                       const_cstr!("pareto").as_ptr());
+            draw.begin_draw();
+
+            let margin = 20.0;
+            let scale = |v :f64, lo :f64, hi :f64| if hi > lo { ((v - lo)/(hi - lo)) as f32 } else { 0.5 };
+            let to_screen = |(x,y) :(f64,f64)| draw.pos + ImVec2 {
+                x: margin + (draw.size.x - 2.0*margin) * scale(x, x_lo, x_hi),
+                y: draw.size.y - margin - (draw.size.y - 2.0*margin) * scale(y, y_lo, y_hi) };
+
+            let color_text = config.color_u32(RailUIColorName::GraphTimeSliderText);
+            let color_front = config.color_u32(RailUIColorName::GraphTrainFront);
+            let color_selected = config.color_u32(RailUIColorName::GraphCommandBorder);
+            for (a,b) in front.iter().zip(front.iter().skip(1)) {
+                ImDrawList_AddLine(draw.draw_list, to_screen(points[*a]), to_screen(points[*b]), color_front, 1.5);
+            }
+
+            let mut hovered = None;
+            let mut hovered_dist = 8.0;
+            for (i,p) in measured.iter() {
+                let pos = to_screen(**p);
+                let on_front = front.contains(i);
+                let col = if on_front { color_front } else { color_text };
+                ImDrawList_AddCircleFilled(draw.draw_list, pos, if on_front { 4.0 } else { 3.0 }, col, 8);
+                if self.selected == Some(*i) {
+                    ImDrawList_AddCircle(draw.draw_list, pos, 7.0, color_selected, 12, 1.5);
+                }
+                let d = draw.pos + draw.mouse - pos;
+                let dist = (d.x*d.x + d.y*d.y).sqrt();
+                if dist < hovered_dist {
+                    hovered = Some(*i);
+                    hovered_dist = dist;
+                }
+            }
+
+            for (text,pos) in &[(format!("price {:.2}", x_lo), ImVec2 { x: 2.0, y: draw.size.y - 16.0 }),
+                                (format!("{:.2}", x_hi), ImVec2 { x: draw.size.x - 50.0, y: draw.size.y - 16.0 }),
+                                (format!("time {:.1}", y_hi), ImVec2 { x: 2.0, y: 2.0 })] {
+                ImDrawList_AddText(draw.draw_list, draw.pos + *pos, color_text,
+                                   text.as_ptr() as _, text.as_ptr().offset(text.len() as isize) as _);
+            }
+            draw.end_draw();
+
+            if igIsItemHovered(0) {
+                if let Some(i) = hovered {
+                    let (name,_,design) = &self.result_models[i];
+                    igBeginTooltip();
+                    widgets::show_text(&format!("Design {}: price {:.2}, time {:.2}, {} objs.{}",
+                                                name, points[i].0, points[i].1, design.len(),
+                                                if front.contains(&i) { " (Pareto optimal)" } else { "" }));
+                    igEndTooltip();
+                }
+                if igIsMouseClicked(0, false) {
+                    self.selected = hovered;
+                    inf_view.preview_objects = match hovered {
+                        Some(i) => design_objects(&self.model, &self.result_models[i].2),
+                        None => Vec::new(),
+                    };
+                }
+            }
+
+            if let Some(i) = self.selected {
+                if igButton(const_cstr!("\u{f067} Add selected design").as_ptr(), ImVec2::zero()) {
+                    add_objects(analysis, &self.model, &self.result_models[i].2);
+                    inf_view.preview_objects.clear();
+                    self.selected = None;
+                }
+            }
+        }
+    }
+
+    /// Start a new run, or resume the run saved with the model if it has the same inputs.
     pub fn start(&mut self) {
-        self.result_models = Vec::new();
-        self.results_ranking = Vec::new();
+        let saved = self.model.synthesis_checkpoint.as_ref().map(|c| (**c).clone());
+        self.run(saved);
+    }
+
+    fn run(&mut self, resume :Option<SynthesisCheckpoint>) {
+        self.selected = None;
+        self.weights_changed = false;
+        self.paused = false;
+        self.cancel.cancel();
+        self.cancel = CancelToken::new();
         let (tx,rx) = mpsc::channel();
         self.thread = Some(rx);
         let model = self.model.clone();
@@ -178,24 +349,72 @@ impl SynthesisWindow {
             .filter_map(|(id,p)| if self.enabled_planspecs.get(id).cloned().unwrap_or(true) {
                 Some(p) } else { None })
             .cloned().collect::<Vec<_>>();
+        let mut cost_model = self.cost_model.clone();
+        cost_model.plan_weights = model.plans.iter()
+            .filter(|(id,_)| self.enabled_planspecs.get(*id).cloned().unwrap_or(true))
+            .map(|(id,_)| self.plan_weights.get(id).cloned().unwrap_or(1.0))
+            .collect();
+
+        self.key = crate::document::cache::synthesis_key(&model, &plans, &cost_model.plan_weights);
+        self.checkpoint = resume.filter(|c| c.key == self.key);
+        self.result_models = self.checkpoint.as_ref().map(|c| c.designs.clone()).unwrap_or_default();
+        self.update_ranking();
 
-        self.thread_pool.execute(move || {
+        let resume = self.checkpoint.clone();
+        let jobs = self.thread_pool.clone();
+        let cancel = self.cancel.clone();
+        // The run waits for the candidate jobs it queues on the pool, so it gets its own thread.
+        std::thread::spawn(move || {
             use crate::document::topology;
             let topo = topology::convert(&model, 50.0).unwrap();
             let vehicles = model.vehicles.iter().cloned().collect::<Vec<_>>();
+            let (fixed, forbidden) = constraints(&model, &topo);
 
-            let result = full_synthesis(&SynthesisBackground { topology: &topo, plans: &plans, vehicles: &vehicles }, 
-                           |msg| tx.send(msg).is_ok());
+            let input = Arc::new(SynthesisInput { topology: topo, plans, vehicles, fixed, forbidden,
+                                                  cost_model, models: Default::default(), cancel });
+            let result = full_synthesis(input, jobs, resume.as_ref(), |msg| tx.send(msg).is_ok());
 
             if let Err(e) = result {
                 error!("full_synthesis: {:?}", e);
+                if let SynErr::Plan(e) = e {
+                    let _ = tx.send(FullSynMsg::S(format!("Synthesis failed: {}", e)));
+                }
             }
 
         });
     }
+
+    /// Stop the current run and save its progress with the model.
+    fn pause(&mut self, analysis :&mut Analysis) {
+        self.cancel.cancel();
+        self.thread = None;
+        self.paused = true;
+        if let Some(checkpoint) = &self.checkpoint {
+            analysis.edit_model(|m| {
+                m.synthesis_checkpoint = Some(Arc::new(checkpoint.clone()));
+                Some(EditClass::SynthesisCheckpoint)
+            });
+            self.results_log.push(format!("Paused after {} signal sets, progress saved with the model.",
+                                          checkpoint.explored.len()));
+        } else {
+            self.results_log.push(format!("Paused before completing any signal sets."));
+        }
+    }
+
+    fn update_ranking(&mut self) {
+        self.results_ranking = (0..(self.result_models.len())).collect();
+        let m = &self.result_models;
+        self.results_ranking.sort_by_key(|i| OrderedFloat(m[*i].1.unwrap_or(std::f64::INFINITY)));
+    }
 }
 
 
+impl Drop for SynthesisWindow {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
 impl BackgroundUpdates for SynthesisWindow {
     fn check(&mut self) {
         if let Some(rx) = &mut self.thread {
@@ -206,9 +425,16 @@ impl BackgroundUpdates for SynthesisWindow {
                     },
                     Ok(FullSynMsg::ModelAvailable(a,b,c)) => { 
                         self.result_models.push((a,b,c)); 
-                        self.results_ranking = (0..(self.result_models.len())).collect();
-                        let m = &mut self.result_models;
-                        self.results_ranking.sort_by_key(|i| OrderedFloat(m[*i].1));
+                        self.update_ranking();
+                    }
+                    Ok(FullSynMsg::SignalSetDone(signals)) => {
+                        let mut explored = self.checkpoint.take().map(|c| c.explored).unwrap_or_default();
+                        explored.push(signals);
+                        self.checkpoint = Some(SynthesisCheckpoint {
+                            key: self.key,
+                            explored,
+                            designs: self.result_models.clone(),
+                        });
                     }
                     Ok(_) => {},
                     Err(mpsc::TryRecvError::Disconnected) => { 
@@ -5,6 +5,7 @@ use const_cstr::const_cstr;
 use crate::document::model::*;
 use crate::document::model;
 use crate::document::analysis::*;
+use crate::document::layout::LayoutOptions;
 use crate::file;
 use crate::app::*;
 use crate::gui::widgets;
@@ -40,7 +41,8 @@ pub enum ImportState {
     SourceFileError(String),
     PlotError(String),
     WaitForDrawing,
-    Available(Model),
+    /// Imported model, with warnings about inconsistent input.
+    Available(Model, Vec<String>),
 }
 
 impl ImportWindow {
@@ -71,7 +73,8 @@ impl ImportWindow {
                 }
             },
 
-            ImportState::Available(model) => {
+            ImportState::Available(model, warnings) => {
+                for w in warnings.iter() { widgets::show_text(&format!("\u{f071} {}", w)); }
                 if igButton(const_cstr!("Import").as_ptr(), ImVec2 { x: 80.0, y: 0.0 }) {
                     *doc = Analysis::from_model( model.clone(), self.thread_pool.clone());  
                     //doc.fileinfo.set_unsaved();
@@ -140,7 +143,7 @@ pub fn load_railml_file(filename :String, tx :mpsc::Sender<ImportState>)  {
     if tx.send(ImportState::Ping).is_err() { return; }
     info!("Converted to topomodel");
 
-    let plotmodel = match convert_railplot(topomodel) {
+    let (plotmodel, warnings) = match convert_railplot(topomodel) {
         Ok(m) => m,
         Err(e) => {
             let _ = tx.send(e);
@@ -151,13 +154,7 @@ pub fn load_railml_file(filename :String, tx :mpsc::Sender<ImportState>)  {
     info!("Converted to plotmodel");
 
     let solver = railplotlib::solvers::LevelsSatSolver {
-        criteria: vec![
-            railplotlib::solvers::Goal::Bends,
-            railplotlib::solvers::Goal::Height,
-            railplotlib::solvers::Goal::Width,
-            railplotlib::solvers::Goal::LocalX,
-            railplotlib::solvers::Goal::LocalY,
-        ],
+        criteria: LayoutOptions::default().criteria(),
         nodes_distinct: false,
     };
     use railplotlib::solvers::SchematicSolver;
@@ -184,12 +181,13 @@ pub fn load_railml_file(filename :String, tx :mpsc::Sender<ImportState>)  {
     };
 
     info!("Model available");
-    let _ = tx.send(ImportState::Available(model));
+    for w in warnings.iter() { warn!("railML import: {}", w); }
+    let _ = tx.send(ImportState::Available(model, warnings));
 }
 
 
 pub fn convert_railplot(topo :railmlio::topo::Topological) 
-    -> Result<railplotlib::model::SchematicGraph<()>, ImportState> {
+    -> Result<(railplotlib::model::SchematicGraph<()>, Vec<String>), ImportState> {
 
     use railmlio::topo;
     use railplotlib::model as plot;
@@ -280,12 +278,14 @@ pub fn convert_railplot(topo :railmlio::topo::Topological)
 
             // now we have roughly estimated mileages and have switch orientations
             // (incoming/outgoing = increasing/decreasing milage)
-            // TODO add lsqr calculations with track lengths and unknown kms.
+            let mut warnings = Vec::new();
+            estimate_mileages(&topo, &track_connections, &mut km0, &mut warnings);
 
             let mut model = plot::SchematicGraph {
                 nodes: Vec::new(),
                 edges: Vec::new(),
                 main_tracks_edges: Vec::new(),
+                fixed_nodes: Vec::new(),
             };
 
             fn to_dir(dir :isize) -> plot::Dir { 
@@ -382,11 +382,134 @@ pub fn convert_railplot(topo :railmlio::topo::Topological)
                 }
             }
 
-            Ok(model)
+            Ok((model, warnings))
         }
     }
 }
 
+/// Least-squares estimate of node mileages from the track lengths and the
+/// mileages given in the railML file. The rough estimate `km0` gives the
+/// direction of each track, and is replaced by the estimate. Given mileages
+/// are kept, and inconsistencies are reported as warnings.
+fn estimate_mileages(topo :&railmlio::topo::Topological,
+                     track_connections :&HashMap<(usize,railmlio::topo::AB),(usize,railmlio::topo::Port)>,
+                     km0 :&mut HashMap<usize, (isize, f64)>,
+                     warnings :&mut Vec<String>) {
+    use railmlio::topo::AB;
+    let tol = |length :f64| (0.01*length).max(1.0);
+    let ends = |t :usize| (track_connections[&(t,AB::A)].0, track_connections[&(t,AB::B)].0);
+
+    let mut known :HashMap<usize,f64> = HashMap::new();
+    for (t,track) in topo.tracks.iter().enumerate() {
+        let (na,nb) = ends(t);
+        for (n,km) in &[(na,track.mileages.0), (nb,track.mileages.1)] {
+            let km = match km { Some(km) => *km, None => continue };
+            match known.get(n) {
+                Some(k) if (k - km).abs() > tol(0.0) => {
+                    warnings.push(format!("Conflicting mileages {:.1} and {:.1} at the same location.", k, km));
+                },
+                Some(_) => {},
+                None => { known.insert(*n, km); },
+            }
+        }
+    }
+
+    // Mileages in the file may increase in the opposite direction of the estimate.
+    if known.len() >= 2 {
+        let n = known.len() as f64;
+        let mean_km = known.values().sum::<f64>() / n;
+        let mean_est = known.keys().map(|k| km0[k].1).sum::<f64>() / n;
+        let cov = known.iter().map(|(k,km)| (km - mean_km)*(km0[k].1 - mean_est)).sum::<f64>();
+        if cov < 0.0 {
+            for (dir,pos) in km0.values_mut() { *dir = -*dir; *pos = -*pos; }
+        }
+    }
+
+    // Align the estimate with the given mileages, or fix the lowest estimate
+    // if no mileages are given.
+    if known.is_empty() {
+        if let Some((n,(_,pos))) = km0.iter().min_by_key(|(_,(_,pos))| ordered_float::OrderedFloat(*pos)) {
+            known.insert(*n,*pos);
+        }
+    }
+    let offset = known.iter().map(|(n,km)| km - km0[n].1).sum::<f64>() / (known.len().max(1) as f64);
+
+    let mut vars :HashMap<usize,usize> = HashMap::new();
+    let mut nodes = km0.keys().cloned().collect::<Vec<_>>();
+    nodes.sort();
+    for n in nodes.iter() {
+        if !known.contains_key(n) { let v = vars.len(); vars.insert(*n, v); }
+    }
+
+    // Rows: one per track with an unknown end, and a weak prior for each unknown.
+    let prior = 1e-3;
+    let mut rows = Vec::new();
+    let mut rhs = Vec::new();
+    for (t,track) in topo.tracks.iter().enumerate() {
+        let (na,nb) = ends(t);
+        let sign = if km0[&nb].1 >= km0[&na].1 { 1.0 } else { -1.0 };
+        let d = sign*track.length;
+        match (known.get(&na), known.get(&nb)) {
+            (Some(ka), Some(kb)) => {
+                if (kb - ka - d).abs() > tol(track.length) {
+                    warnings.push(format!("Track length {:.1} disagrees with the mileages {:.1} and {:.1} at its ends.",
+                                          track.length, ka, kb));
+                }
+            },
+            (ka, kb) => {
+                rows.push((t, vars.get(&na).cloned(), vars.get(&nb).cloned()));
+                rhs.push(d + ka.cloned().unwrap_or(0.0) - kb.cloned().unwrap_or(0.0));
+            },
+        }
+    }
+    let n_tracks = rows.len();
+    for n in nodes.iter() {
+        if vars.contains_key(n) { rhs.push(prior*(km0[n].1 + offset)); }
+    }
+
+    let mut sol = Vec::new();
+    if !vars.is_empty() {
+        let params = lsqr::Params {
+            damp: 0.0,
+            rel_mat_err: 1e-8,
+            rel_rhs_err: 1e-8,
+            condlim: 0.0,
+            iterlim: 10*(rows.len() + vars.len()),
+        };
+        let prior_vars = nodes.iter().filter_map(|n| vars.get(n).cloned()).collect::<Vec<_>>();
+        sol = lsqr::lsqr(|_| {}, n_tracks + prior_vars.len(), vars.len(), params, |prod| {
+            match prod {
+                lsqr::Product::YAddAx { x, y } => {
+                    for (i,(_,a,b)) in rows.iter().enumerate() {
+                        if let Some(a) = a { y[i] -= x[*a]; }
+                        if let Some(b) = b { y[i] += x[*b]; }
+                    }
+                    for (i,v) in prior_vars.iter().enumerate() { y[n_tracks + i] += prior*x[*v]; }
+                },
+                lsqr::Product::XAddATy { x, y } => {
+                    for (i,(_,a,b)) in rows.iter().enumerate() {
+                        if let Some(a) = a { x[*a] -= y[i]; }
+                        if let Some(b) = b { x[*b] += y[i]; }
+                    }
+                    for (i,v) in prior_vars.iter().enumerate() { x[*v] += prior*y[n_tracks + i]; }
+                },
+            }
+        }, &mut rhs).0;
+    }
+
+    let km = |n :usize| known.get(&n).cloned().unwrap_or_else(|| sol[vars[&n]]);
+    for (t,_,_) in rows.iter() {
+        let (na,nb) = ends(*t);
+        let length = topo.tracks[*t].length;
+        let diff = (km(nb) - km(na)).abs();
+        if (diff - length).abs() > tol(length) {
+            warnings.push(format!("Track length {:.1} disagrees with the estimated mileages {:.1} and {:.1} at its ends.",
+                                  length, km(na), km(nb)));
+        }
+    }
+    for (n,(_,pos)) in km0.iter_mut() { *pos = km(*n); }
+}
+
 
 pub fn round_pt_tol((x,y) :(f64,f64)) -> Result<Pt,()> {
     use nalgebra_glm as glm;
@@ -5,6 +5,7 @@ mod file;
 mod gui;
 mod util;
 mod import;
+mod export;
 
 mod synthesis;
 
@@ -54,6 +55,8 @@ fn main() {
         app.document.check();
         app.windows.import_window.update();
         if let Some(win) = &mut app.windows.synthesis_window { win.check(); }
+        if let Some(win) = &mut app.windows.delays_window { win.check(); }
+        if let Some(win) = &mut app.windows.lint_window { win.check(); }
 
         // Advance time in animations
 		let dt = unsafe { (*backend_glfw::imgui::igGetIO()).DeltaTime } as f64;
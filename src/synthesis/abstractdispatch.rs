@@ -13,7 +13,7 @@ pub type AbstractDispatch = Vec<AbstractCommand>;
 pub struct AbstractCommand {
     pub from :Result<Pt,PtA>,
     pub to :Result<Pt,PtA>,
-    pub switches :HashSet<(Pt, rolling_inf::SwitchPosition)>,
+    pub switches :HashSet<((Pt,Port), rolling_inf::SwitchPosition)>,
     pub train :usize,
 }
 
@@ -55,7 +55,7 @@ pub fn abstract_dispatches(
                 entries.remove(&ignore_trigger(route.exit));
                 exits.remove(&ignore_trigger(route.entry));
                 for (sw,side) in route.resources.switch_positions.iter() {
-                    switches.insert((*dgraph.switch_ids.get_by_left(sw).unwrap(), *side));
+                    switches.insert((*dgraph.switch_ports.get(sw).unwrap(), *side));
                 }
             }
             assert_eq!(entries.len(), 1); assert_eq!(exits.len(), 1); 
@@ -89,7 +89,7 @@ pub fn concrete_dispatch(dgraph :&DGraph, il :&Interlocking, ad :&AbstractComman
         'rs: for route_idx in route_idxs {
             let route = &il.routes[*route_idx].route;
             let switches = route.resources.switch_positions.iter()
-                .map(|(sw,side)| (*dgraph.switch_ids.get_by_left(sw).unwrap(), *side))
+                .map(|(sw,side)| (*dgraph.switch_ports.get(sw).unwrap(), *side))
                 .collect::<HashSet<(_,_)>>();
 
             let sw_ok = switches.difference(&ad.switches).nth(0).is_none();
@@ -1,5 +1,38 @@
+use ordered_float::OrderedFloat;
 use crate::synthesis::*;
 
+/// Designs with one more signal, in each direction, in the middle of the longest
+/// free interval of each track. Positions inside forbidden zones are not used.
 pub fn add_signal(bg :&SynthesisBackground, design :Design) -> impl Iterator<Item = Design> {
-    std::iter::empty()
+    let min_dist = 21.9;
+    let mut candidates = Vec::new();
+    for (tr,(length,_,_)) in bg.topology.tracks.iter().enumerate() {
+        let mut pts = design.iter().filter(|o| o.0 == tr).map(|o| o.1).collect::<Vec<_>>();
+        pts.push(0.0);
+        pts.push(*length);
+        pts.sort_by_key(|p| OrderedFloat(*p));
+        let gap = pts.iter().zip(pts.iter().skip(1)).max_by_key(|(a,b)| OrderedFloat(*b - *a));
+        if let Some((a,b)) = gap {
+            if let Some(pos) = free_position(bg, tr, a + min_dist, b - min_dist) {
+                candidates.push((tr, pos, AB::A));
+                candidates.push((tr, pos, AB::B));
+            }
+        }
+    }
+
+    candidates.into_iter().map(move |(tr,pos,dir)| {
+        let mut design = design.clone();
+        design.push((tr, pos, Function::MainSignal { has_distant: true }, Some(dir)));
+        design
+    })
+}
+
+/// The position closest to the middle of the interval which is not inside a forbidden zone.
+fn free_position(bg :&SynthesisBackground, tr :usize, lo :f64, hi :f64) -> Option<f64> {
+    if !(lo <= hi) { return None; }
+    let mid = 0.5*(lo + hi);
+    let zone_ends = bg.forbidden.iter().filter(|(t,_,_)| *t == tr).flat_map(|(_,a,b)| vec![*a,*b]);
+    std::iter::once(mid).chain(zone_ends)
+        .filter(|p| lo <= *p && *p <= hi && !bg.is_forbidden(tr, *p))
+        .min_by_key(|p| OrderedFloat((p - mid).abs()))
 }
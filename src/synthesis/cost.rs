@@ -9,6 +9,60 @@ use crate::document::dispatch::max_time;
 use crate::document::plan::eval_plan;
 use crate::document::history::History;
 
+/// Prices of signalling equipment and weights of the running time of each plan,
+/// used to rank designs by cost versus performance.
+#[derive(Debug, Clone)]
+pub struct CostModel {
+    pub signal_price :f64,
+    pub detector_price :f64,
+    pub distant_price :f64,
+    /// Running time weight for each plan, indexed like `SynthesisBackground::plans`.
+    pub plan_weights :Vec<f64>,
+}
+
+impl Default for CostModel {
+    fn default() -> CostModel {
+        CostModel {
+            signal_price: 1.0,
+            detector_price: 0.3,
+            distant_price: 0.5,
+            plan_weights: Vec::new(),
+        }
+    }
+}
+
+impl CostModel {
+    pub fn plan_weight(&self, planspec_id :usize) -> f64 {
+        self.plan_weights.get(planspec_id).cloned().unwrap_or(1.0)
+    }
+
+    /// Equipment price of a design. Main signals are built with a detector, see `create_model`.
+    pub fn price(&self, design :&Design) -> f64 {
+        design.iter().map(|(_,_,func,_)| match func {
+            Function::MainSignal { has_distant } => self.signal_price + self.detector_price
+                + if *has_distant { self.distant_price } else { 0.0 },
+            Function::Detector => self.detector_price,
+        }).sum()
+    }
+}
+
+/// Indices of the points which are not dominated by any other point, minimizing
+/// both coordinates. The result is sorted by the first coordinate.
+pub fn pareto_front(points :&[(f64,f64)]) -> Vec<usize> {
+    let mut idxs = (0..points.len())
+        .filter(|i| points[*i].0.is_finite() && points[*i].1.is_finite())
+        .collect::<Vec<_>>();
+    idxs.sort_by(|a,b| points[*a].partial_cmp(&points[*b]).unwrap());
+    let mut front = Vec::new();
+    let mut best = std::f64::INFINITY;
+    for i in idxs {
+        if points[i].1 < best {
+            best = points[i].1;
+            front.push(i);
+        }
+    }
+    front
+}
 
 pub fn measure_dispatch(bg :&SynthesisBackground, dgraph :&DGraph, il :&Interlocking,
     planspec_id :usize, dispatch :&AbstractDispatch) -> Result<f64,()> {
@@ -28,7 +82,8 @@ pub fn measure_dispatch(bg :&SynthesisBackground, dgraph :&DGraph, il :&Interloc
 
 pub fn measure(bg :&SynthesisBackground, allplans :&MultiPlan, design :&Design) -> f64 {
     //println!("cost::measure");
-    let (topo,dgraph,il) = create_model(bg,design);
+    let model = bg.models.get(bg, design);
+    let (_,dgraph,il) = &*model;
     let mut total_cost = 0.0;
     //println!("Testing design {:?}", design);
     //println!("Testing design on plans {:?}", allplans);
@@ -41,7 +96,7 @@ pub fn measure(bg :&SynthesisBackground, allplans :&MultiPlan, design :&Design)
                 //println!("measure on dispatch {:?}", d);
                     measure_dispatch(bg, &dgraph, &il, planspec_id, d)
                     .unwrap_or(std::f64::INFINITY)}).sum::<f64>();
-            total_cost += planspec_cost / dispatches.len() as f64;
+            total_cost += bg.cost_model.plan_weight(planspec_id) * planspec_cost / dispatches.len() as f64;
         }
     }
 
@@ -71,3 +126,25 @@ fn mk_commands(bg :&SynthesisBackground, dgraph :&DGraph, il:&Interlocking,
     Ok(output)
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pareto_front_dominance() {
+        let inf = std::f64::INFINITY;
+        let points = vec![
+            (3.0, 10.0), // front
+            (1.0, 20.0), // front
+            (2.0, 25.0), // dominated by 1
+            (5.0, 10.0), // dominated by 0, same time
+            (4.0,  5.0), // front
+            (3.0, 12.0), // dominated by 0, same price
+            (6.0,  5.0), // dominated by 4
+            (0.5,  inf), // not measured
+        ];
+        assert_eq!(pareto_front(&points), vec![1, 0, 4]);
+        assert_eq!(pareto_front(&[]), Vec::<usize>::new());
+    }
+}
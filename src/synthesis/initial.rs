@@ -1,11 +1,14 @@
 use crate::document::topology::*;
 use crate::document::model::*;
 use crate::document::objects::*;
+use matches::matches;
 use crate::synthesis::*;
 
 /// An initial guess for signal and detector placement.
-/// Places a signal at 0 and 150 m from every switch.
-pub fn initial_design(topo :&Topology) -> Design {
+/// Places a signal at 0 and 150 m from every switch, except inside 
+/// forbidden zones, and adds the fixed objects.
+pub fn initial_design(bg :&SynthesisBackground) -> Design {
+    let topo = bg.topology;
 
     let stock_length = 23.0;
     let fouling_length = 51.0;
@@ -18,13 +21,15 @@ pub fn initial_design(topo :&Topology) -> Design {
             match port {
                 Port::Trunk => { // set a detector at the stock
                     for c in cur_move(topo, Cursor { tr: track_idx, pos: *pos, dir: *dir }, stock_length) {
+                        if bg.is_forbidden(c.tr, c.pos) { continue; }
                         objects.push((c.tr, c.pos, Function::Detector, None));
                     }
                 }
-                Port::Left | Port::Right => { // set a signal and detector at each overlap length
+                Port::Left | Port::Right | Port::Cross(_,_) => { // set a signal and detector at each overlap length
                     for overlap_length in &overlap_lengths {
                         let l = fouling_length + overlap_length;
                         for c in cur_move(topo, Cursor { tr: track_idx, pos: *pos, dir: *dir}, l) {
+                            if bg.is_forbidden(c.tr, c.pos) { continue; }
                             // signals now implies a co-located detector
                             //objects.push((c.tr,c.pos,Function::Detector,None));
                             objects.push((c.tr,c.pos,Function::MainSignal { has_distant: true },Some(c.dir.other())));
@@ -32,11 +37,22 @@ pub fn initial_design(topo :&Topology) -> Design {
                     }
                 },
 
-                _ => {}, // TODO crossings
+                _ => {},
+            }
+
+            // Slip crossings also have switch blades, so detect the stock as for a trunk.
+            if let Port::Cross(_,_) = port {
+                if is_slip_blade(topo, &(track_idx, *dir), **port) {
+                    for c in cur_move(topo, Cursor { tr: track_idx, pos: *pos, dir: *dir }, stock_length) {
+                        if bg.is_forbidden(c.tr, c.pos) { continue; }
+                        objects.push((c.tr, c.pos, Function::Detector, None));
+                    }
+                }
             }
         }
     }
 
+    objects.extend(bg.fixed.iter().cloned());
     objects
 }
 
@@ -68,17 +84,54 @@ fn cur_move(topo :&Topology, c :Cursor, length: f64) -> Vec<Cursor> {
     }
 }
 
+/// Ports which a train can pass to from the given port of a node.
+fn connected_ports(nd :NDType, port :Port) -> Vec<Port> {
+    match (nd, port) {
+        (NDType::Crossing(type_), Port::Cross(ab,n)) => {
+            let mut out = vec![Port::Cross(ab.other(), n)];
+            // Slips connect Cross(A,0) with Cross(B,1) (left) and Cross(B,0) with Cross(A,1) (right),
+            // as in the dgraph conversion.
+            let left  = matches!(type_, CrossingType::DoubleSlip | CrossingType::SingleSlip(Side::Left));
+            let right = matches!(type_, CrossingType::DoubleSlip | CrossingType::SingleSlip(Side::Right));
+            for (dir,drivable) in &[(AB::A, left), (AB::B, right)] {
+                if !*drivable { continue; }
+                if (ab,n) == (*dir,0) { out.push(Port::Cross(dir.other(), 1)); }
+                if (ab,n) == (dir.other(),1) { out.push(Port::Cross(*dir, 0)); }
+            }
+            out
+        },
+        (_, Port::Trunk) => vec![Port::Left, Port::Right],
+        (_, Port::Left) | (_, Port::Right) => vec![Port::Trunk],
+        (_, Port::ContA) => vec![Port::ContB],
+        (_, Port::ContB) => vec![Port::ContA],
+        _ => vec![],
+    }
+}
+
+/// The track end is at a slip crossing port where the train may diverge.
+fn is_slip_blade(topo :&Topology, (tr,dir) :&(usize,AB), port :Port) -> bool {
+    let pt = match dir { AB::A => (topo.tracks[*tr].1).0, AB::B => (topo.tracks[*tr].2).0 };
+    match topo.locations.get(&pt) {
+        Some((nd,_)) => connected_ports(*nd, port).len() > 1,
+        None => false,
+    }
+}
+
 fn other_cursors(topo :&Topology, tr :usize, dir :AB) -> Vec<Cursor> {
     let mut output = Vec::new();
     let (pt,port) = match dir {
         AB::A => &topo.tracks[tr].2,
         AB::B => &topo.tracks[tr].1,
     };
+    let targets = match topo.locations.get(pt) {
+        Some((nd,_)) => connected_ports(*nd, *port),
+        None => vec![],
+    };
     for (i,(l,(pt_a,port_a),(pt_b,port_b))) in topo.tracks.iter().enumerate() {
-        if pt_a == pt && port.is_opposite(port_a) {
+        if pt_a == pt && targets.contains(port_a) {
             output.push(Cursor { tr: i, pos: 0.0, dir: AB::A });
         }
-        if pt_b == pt && port.is_opposite(port_b) {
+        if pt_b == pt && targets.contains(port_b) {
             output.push(Cursor { tr: i, pos: *l, dir: AB::B });
         }
     }
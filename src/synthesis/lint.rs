@@ -0,0 +1,177 @@
+use matches::matches;
+use crate::synthesis::*;
+use crate::document::topology;
+
+/// Objects closer than this (m) to a main signal are its train detector.
+const COLOCATED :f64 = 1.0;
+/// Smallest reported change in object position (m).
+const MIN_MOVE :f64 = 5.0;
+/// Smallest reported running time improvement (s).
+const MIN_SAVING :f64 = 0.5;
+
+#[derive(Debug, Clone)]
+pub enum LintIssue {
+    /// The main signal can be removed. If the bool is set, a train detector is
+    /// still needed at its location.
+    RedundantSignal(PtA, bool),
+    RedundantDetector(PtA),
+    FasterSignalPosition(ObjectMove),
+    FasterDetectorPosition(ObjectMove),
+    FasterNewSignal(NewSignal),
+}
+
+/// Moving the given model objects along a track shortens the weighted running time.
+#[derive(Debug, Clone)]
+pub struct ObjectMove {
+    pub objects :Vec<PtA>,
+    pub track :usize,
+    pub from :f64,
+    pub to :f64,
+    pub saving :f64,
+}
+
+/// Adding a main signal (with its detector) shortens the weighted running time.
+#[derive(Debug, Clone)]
+pub struct NewSignal {
+    pub track :usize,
+    pub pos :f64,
+    pub function :Function,
+    pub dir :Option<AB>,
+    pub saving :f64,
+}
+
+impl LintIssue {
+    pub fn objects(&self) -> Vec<PtA> {
+        match self {
+            LintIssue::RedundantSignal(id,_) | LintIssue::RedundantDetector(id) => vec![*id],
+            LintIssue::FasterSignalPosition(m) | LintIssue::FasterDetectorPosition(m) => m.objects.clone(),
+            LintIssue::FasterNewSignal(_) => Vec::new(),
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            LintIssue::RedundantSignal(_,true) =>
+                format!("Signal is not needed by any plan, but its detector is."),
+            LintIssue::RedundantSignal(_,false) => format!("Signal is not needed by any plan."),
+            LintIssue::RedundantDetector(_) => format!("Detector is not needed by any plan."),
+            LintIssue::FasterSignalPosition(m) =>
+                format!("Moving signal {:.0} m saves {:.1} s.", m.to - m.from, m.saving),
+            LintIssue::FasterDetectorPosition(m) =>
+                format!("Moving detector {:.0} m saves {:.1} s.", m.to - m.from, m.saving),
+            LintIssue::FasterNewSignal(s) =>
+                format!("New signal at {:.0} m on track {} saves {:.1} s.", s.pos, s.track, s.saving),
+        }
+    }
+}
+
+/// The model's objects as a design, with the model objects represented by each
+/// design object. Detectors at a main signal are part of the signal, see `create_model`.
+pub fn current_design(topo :&Topology) -> (Design, Vec<Vec<PtA>>) {
+    let mut design :Design = Vec::new();
+    let mut ids :Vec<Vec<PtA>> = Vec::new();
+    for (track_idx, objs) in topo.trackobjects.iter().enumerate() {
+        for (pos,id,func,dir) in objs.iter().filter(|o| matches!(o.2, Function::MainSignal { .. })) {
+            design.push((track_idx, *pos, *func, *dir));
+            ids.push(vec![*id]);
+        }
+        for (pos,id,_,_) in objs.iter().filter(|o| o.2 == Function::Detector) {
+            let signal = design.iter().position(|o| o.0 == track_idx &&
+                                                matches!(o.2, Function::MainSignal { .. }) &&
+                                                (o.1 - pos).abs() < COLOCATED);
+            match signal {
+                Some(i) => { if !ids[i].contains(id) { ids[i].push(*id); } },
+                None => {
+                    design.push((track_idx, *pos, Function::Detector, None));
+                    ids.push(vec![*id]);
+                },
+            }
+        }
+    }
+    (design, ids)
+}
+
+/// Check the model's signalling against all of its plans. Reports signals and
+/// detectors which are not needed by any plan, and object positions and new
+/// signals which shorten the running time.
+pub fn lint(model :&Model) -> Result<Vec<LintIssue>, String> {
+    let topo = topology::convert(model, 50.0).map_err(|_| format!("Could not convert topology."))?;
+    let (design, ids) = current_design(&topo);
+    let (fixed, forbidden) = constraints(model, &topo);
+    let plans = model.plans.iter().map(|(_,p)| p.clone()).collect::<Vec<_>>();
+    if plans.len() == 0 { return Ok(Vec::new()); }
+    let vehicles = model.vehicles.iter().cloned().collect::<Vec<_>>();
+    let mut input = SynthesisInput { topology: topo, plans, vehicles, fixed: fixed.clone(), forbidden,
+                                     cost_model: Default::default(), models: Default::default(),
+                                     cancel: Default::default() };
+    let mut issues = Vec::new();
+
+    // The cheapest signal set using only the current objects.
+    let (reduced,_) = reduce::reduced_signal_sets(&input.background(), design.clone(), &[])?.next()
+        .ok_or(format!("The plans cannot be satisfied with the current signalling."))?;
+    for (obj,ids) in design.iter().zip(ids.iter()) {
+        if reduced.contains(obj) { continue; }
+        match obj.2 {
+            Function::MainSignal { .. } => {
+                let detector = reduced.iter().any(|o| o.0 == obj.0 && o.1 == obj.1 && o.2 == Function::Detector);
+                issues.push(LintIssue::RedundantSignal(ids[0], detector && ids.len() == 1));
+                if !detector {
+                    issues.extend(ids[1..].iter().map(|id| LintIssue::RedundantDetector(*id)));
+                }
+            },
+            Function::Detector => issues.push(LintIssue::RedundantDetector(ids[0])),
+        }
+    }
+
+    // Dispatches using all the current objects.
+    input.fixed = design.clone();
+    let (current, adispatch) = reduce::reduced_signal_sets(&input.background(), design.clone(), &[])?.next()
+        .ok_or(format!("The plans cannot be satisfied with the current signalling."))?;
+    input.fixed = fixed;
+    let bg = input.background();
+    let base = cost::measure(&bg, &adispatch, &current);
+    if !base.is_finite() { return Ok(issues); }
+
+    // The optimized design is sorted by track and position.
+    let (_,optimized) = optimize::optimize_locations(&bg, &adispatch, &current);
+    let mut order = (0..current.len()).collect::<Vec<_>>();
+    order.sort_by(|a,b| (current[*a].0, current[*a].1).partial_cmp(&(current[*b].0, current[*b].1)).unwrap());
+
+    for (i,new) in order.into_iter().zip(optimized.iter()) {
+        let (track,pos,func,_) = current[i];
+        if (new.1 - pos).abs() < MIN_MOVE { continue; }
+        let objects = match design.iter().position(|o| o.0 == track && o.1 == pos && o.2 == func) {
+            Some(j) => ids[j].clone(),
+            None => continue, // detector which is part of a signal
+        };
+
+        let mut moved = current.clone();
+        moved[i].1 = new.1;
+        let saving = base - cost::measure(&bg, &adispatch, &moved);
+        if saving >= MIN_SAVING {
+            let m = ObjectMove { objects, track, from: pos, to: new.1, saving };
+            issues.push(match func {
+                Function::MainSignal { .. } => LintIssue::FasterSignalPosition(m),
+                Function::Detector => LintIssue::FasterDetectorPosition(m),
+            });
+        }
+    }
+
+    // New signals from the designer's candidates, each moved to its best position
+    // while the current objects stay in place.
+    input.fixed = current.clone();
+    let bg = input.background();
+    let mut new_signals = Vec::new();
+    for candidate in add::add_signal(&bg, current.clone()) {
+        let (score, optimized) = optimize::optimize_locations(&bg, &adispatch, &candidate);
+        let saving = base - score;
+        if !(saving >= MIN_SAVING) { continue; }
+        if let Some((track,pos,function,dir)) = optimized.into_iter().find(|o| !current.contains(o)) {
+            new_signals.push(NewSignal { track, pos, function, dir, saving });
+        }
+    }
+    new_signals.sort_by(|a,b| b.saving.partial_cmp(&a.saving).unwrap());
+    issues.extend(new_signals.into_iter().map(LintIssue::FasterNewSignal));
+
+    Ok(issues)
+}
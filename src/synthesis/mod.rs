@@ -1,5 +1,6 @@
 use numerical_optimization::powell::*;
 use std::collections::{HashMap,HashSet, BTreeSet};
+use std::sync::{Arc, Mutex, mpsc};
 use boolinator::Boolinator;
 use matches::matches;
 use nalgebra_glm as glm;
@@ -11,6 +12,7 @@ use crate::document::objects::*;
 use crate::document::topology::*;
 use crate::document::dgraph;
 use crate::document::interlocking;
+use crate::app::{BackgroundJobs, CancelToken};
 
 mod abstractdispatch;
 mod initial;
@@ -18,44 +20,176 @@ mod optimize;
 mod reduce;
 mod add;
 mod cost;
+pub mod lint;
 
 #[derive(Debug)]
 pub enum FullSynMsg {
     S(String),
     TryingSignalSet(),
-    ModelAvailable(String, f64, Design),
+    /// A design with its name and score. The maximal design is not measured and has no score.
+    ModelAvailable(String, Option<f64>, Design),
+    /// All designs from the reduced signal set, given by its main signals, have been reported.
+    SignalSetDone(Vec<Object>),
 }
 
 pub struct SynthesisBackground<'a> {
     pub topology :&'a Topology,
     pub plans :&'a [PlanSpec],
     pub vehicles :&'a [(usize,Vehicle)],
+    /// Objects which are kept, unmoved, in every design.
+    pub fixed :&'a [Object],
+    /// Intervals `(track_idx, start, end)` where no objects may be placed.
+    pub forbidden :&'a [(usize,f64,f64)],
+    pub cost_model :&'a CostModel,
+    pub models :&'a ModelCache,
+    pub cancel :&'a CancelToken,
+}
+
+/// Owned inputs of a synthesis run, shared with the parallel evaluation jobs.
+pub struct SynthesisInput {
+    pub topology :Topology,
+    pub plans :Vec<PlanSpec>,
+    pub vehicles :Vec<(usize,Vehicle)>,
+    pub fixed :Vec<Object>,
+    pub forbidden :Vec<(usize,f64,f64)>,
+    pub cost_model :CostModel,
+    pub models :ModelCache,
+    /// Stops the run, including queued evaluation jobs, when cancelled.
+    pub cancel :CancelToken,
+}
+
+impl SynthesisInput {
+    pub fn background(&self) -> SynthesisBackground {
+        SynthesisBackground {
+            topology: &self.topology,
+            plans: &self.plans,
+            vehicles: &self.vehicles,
+            fixed: &self.fixed,
+            forbidden: &self.forbidden,
+            cost_model: &self.cost_model,
+            models: &self.models,
+            cancel: &self.cancel,
+        }
+    }
+}
+
+pub type SynthesisModel = (Topology,dgraph::DGraph,interlocking::Interlocking);
+
+const MODEL_CACHE_SIZE :usize = 200;
+
+/// Memoized `create_model` results, keyed by design. The location optimization
+/// evaluates the same design many times, also across candidates.
+/// When full, the least recently used model is evicted.
+#[derive(Default)]
+pub struct ModelCache(Mutex<ModelCacheEntries>);
+
+#[derive(Default)]
+struct ModelCacheEntries {
+    clock :u64,
+    models :HashMap<Vec<(usize,u64,Function,Option<AB>)>, (u64, Arc<SynthesisModel>)>,
+}
+
+impl ModelCache {
+    pub fn get(&self, bg :&SynthesisBackground, design :&Design) -> Arc<SynthesisModel> {
+        let key = design.iter().map(|(tr,pos,func,dir)| (*tr, pos.to_bits(), *func, *dir)).collect::<Vec<_>>();
+        {
+            let mut cache = self.0.lock().unwrap();
+            let cache = &mut *cache;
+            cache.clock += 1;
+            if let Some((last_use, model)) = cache.models.get_mut(&key) {
+                *last_use = cache.clock;
+                return model.clone();
+            }
+        }
+        let model = Arc::new(create_model(bg, design));
+        let mut cache = self.0.lock().unwrap();
+        if cache.models.len() >= MODEL_CACHE_SIZE {
+            let oldest = cache.models.iter().min_by_key(|(_,(last_use,_))| *last_use).map(|(k,_)| k.clone());
+            if let Some(k) = oldest { cache.models.remove(&k); }
+        }
+        cache.clock += 1;
+        let clock = cache.clock;
+        cache.models.insert(key, (clock, model.clone()));
+        model
+    }
+}
+
+impl<'a> SynthesisBackground<'a> {
+    pub fn is_fixed(&self, obj :&Object) -> bool {
+        self.fixed.contains(obj)
+    }
+
+    pub fn is_forbidden(&self, track_idx :usize, pos :f64) -> bool {
+        self.forbidden.iter().any(|(tr,a,b)| *tr == track_idx && *a < pos && pos < *b)
+    }
+}
+
+/// Fixed objects and forbidden intervals from the model's synthesis constraints.
+pub fn constraints(model :&Model, topo :&Topology) -> (Vec<Object>, Vec<(usize,f64,f64)>) {
+    let mut fixed = Vec::new();
+    let mut forbidden = Vec::new();
+    for (track_idx, objs) in topo.trackobjects.iter().enumerate() {
+        for (pos, id, func, dir) in objs.iter() {
+            match model.synthesis_constraints.get(id) {
+                Some(SynthesisConstraint::Fixed) => { fixed.push((track_idx, *pos, *func, *dir)); },
+                Some(SynthesisConstraint::ForbiddenZone(d)) => {
+                    let length = topo.tracks[track_idx].0;
+                    let zone = (track_idx, (pos - d).max(0.0), (pos + d).min(length));
+                    if !forbidden.contains(&zone) { forbidden.push(zone); }
+                },
+                None => {},
+            }
+        }
+    }
+    (fixed, forbidden)
 }
 
 #[derive(Debug)]
-pub enum SynErr { Aborted }
+pub enum SynErr { Aborted, Plan(String) }
 
 pub type Design = Vec<Object>;
 pub type Object = (usize,f64,Function,Option<AB>);
 
 pub use abstractdispatch::*;
-
-
-pub fn full_synthesis( bg :&SynthesisBackground,
-                       mut output :impl FnMut(FullSynMsg) -> bool) -> Result<(),SynErr> {
-    output(FullSynMsg::S(format!("Starting full synthesis procedure."))).ok_or(SynErr::Aborted)?;
-    let maximal_objects = initial::initial_design(&bg.topology);
-    output(FullSynMsg::ModelAvailable(format!("Maximal model"), 0.0, 
-                                      maximal_objects.clone())).ok_or(SynErr::Aborted)?;
-
-    let mut signal_set_iterator = reduce::reduced_signal_sets(bg, maximal_objects);
+pub use cost::{CostModel, pareto_front};
+
+
+/// Run the synthesis procedure, reporting designs through `output` until it returns false.
+/// Candidate designs are optimized in parallel on the `jobs` pool. This function
+/// waits for those jobs, so it must not itself run on the `jobs` pool.
+/// When resuming from a checkpoint, the signal sets it has explored are excluded
+/// from the search and its designs are not reported again.
+pub fn full_synthesis(input :Arc<SynthesisInput>, mut jobs :BackgroundJobs,
+                      resume :Option<&SynthesisCheckpoint>,
+                      mut output :impl FnMut(FullSynMsg) -> bool) -> Result<(),SynErr> {
+    let cancel = input.cancel.clone();
+    let mut output = |msg :FullSynMsg| if output(msg) { Ok(()) } else { cancel.cancel(); Err(SynErr::Aborted) };
+    let bg = input.background();
+
+    output(FullSynMsg::S(format!("Starting full synthesis procedure.")))?;
+    let maximal_objects = initial::initial_design(&bg);
+    let (mut n, explored) = match resume {
+        Some(checkpoint) => {
+            output(FullSynMsg::S(format!("Resuming after {} signal sets.", checkpoint.explored.len())))?;
+            (checkpoint.designs.len(), &checkpoint.explored[..])
+        },
+        None => {
+            output(FullSynMsg::ModelAvailable(format!("Maximal model"), None, 
+                                              maximal_objects.clone()))?;
+            (1, &[][..])
+        },
+    };
+
+    let mut signal_set_iterator = reduce::reduced_signal_sets(&bg, maximal_objects, explored)
+        .map_err(SynErr::Plan)?;
 
     // Try all minimal signal sets
     // TODO reorg to breadth first?
-    let mut n = 1;
-    while let Some((mut design, adispatch)) = signal_set_iterator.next() {
-        println!("reduced design");
-        println!("{:?}", design);
+    while let Some((design, adispatch)) = signal_set_iterator.next() {
+        if cancel.is_cancelled() { return Err(SynErr::Aborted); }
+        let signals = design.iter().filter(|o| matches!(o.2, Function::MainSignal { .. }))
+            .cloned().collect::<Vec<_>>();
+
         // the adispatch contains references to fixed infrastructure and
         // relative refernces to the Design, i.e. the objects whose positions can
         // be moved.
@@ -70,21 +204,30 @@ pub fn full_synthesis( bg :&SynthesisBackground,
                 }
             }
         }
-        output(FullSynMsg::TryingSignalSet()).ok_or(SynErr::Aborted)?;
-        let (score,design) = optimize::optimize_locations(bg, &adispatch, &design);
-        output(FullSynMsg::ModelAvailable(format!("reduced {}",n), score, design.clone())).ok_or(SynErr::Aborted)?;
+        output(FullSynMsg::TryingSignalSet())?;
+        let (score,design) = optimize::optimize_locations(&bg, &adispatch, &design);
+        output(FullSynMsg::ModelAvailable(format!("reduced {}",n), Some(score), design.clone()))?;
         n += 1;
 
-        let mut add_signal_iterator = add::add_signal(bg, design);
-        while let Some(mut design) = add_signal_iterator.next() {
-            output(FullSynMsg::TryingSignalSet()).ok_or(SynErr::Aborted)?;
-            let (score,design) = optimize::optimize_locations(bg, &adispatch, &design);
-            output(FullSynMsg::ModelAvailable(format!("added {}", n), score, design)).ok_or(SynErr::Aborted)?;
+        let adispatch = Arc::new(adispatch);
+        let (tx,rx) = mpsc::channel();
+        for design in add::add_signal(&bg, design) {
+            let (input, adispatch, tx, cancel) = (input.clone(), adispatch.clone(), tx.clone(), cancel.clone());
+            jobs.execute(move || {
+                if cancel.is_cancelled() { return; }
+                let _ = tx.send(optimize::optimize_locations(&input.background(), &adispatch, &design));
+            });
+        }
+        drop(tx);
+
+        for (score,design) in rx.iter() {
+            output(FullSynMsg::TryingSignalSet())?;
+            output(FullSynMsg::ModelAvailable(format!("added {}", n), Some(score), design))?;
             n += 1;
         }
+        output(FullSynMsg::SignalSetDone(signals))?;
     }
 
-    //output(FullSynMsg::S(format!("Done"))).ok_or(SynErr::Aborted)?;
     Ok(())
 }
 
@@ -2,60 +2,87 @@ use log::*;
 use ordered_float::OrderedFloat;
 use nalgebra::DVector;
 use crate::synthesis::*;
-use itertools::*;
-use std::mem::replace;
 use permutation::Permutation;
 
+/// Powell's method evaluates its line searches one design at a time, so the
+/// measurements within one optimization are serial. Parallelism comes from
+/// optimizing several candidate designs at once, see `full_synthesis`.
 pub fn optimize_locations(bg :&SynthesisBackground, adispatch :&MultiPlan, design :&Design) -> (f64,Design) {
     info!("optimize_locations: starting");
     let order = permutation::sort_by_key(&design[..], 
                  |(tr,pos,_,_)| (*tr, OrderedFloat(*pos)));
-    let baseline_value = cost::measure(bg, adispatch, design);
+    if design.iter().all(|o| bg.is_fixed(o)) {
+        // Nothing to move.
+        return (cost::measure(bg, adispatch, design), design.clone());
+    }
     let mut n = 0;
     let start_pt = design_encode(bg, design, &order);
     info!("Encoding first design {:?}\n  {:?}", design, start_pt);
     let (cost, best_pt) = powell_optimize_unit(start_pt, |new_pt| {
         n += 1;
-        cost::measure(bg, adispatch, &design_decode(bg, new_pt, design, &order))
+        if bg.cancel.is_cancelled() { return std::f64::INFINITY; }
+        let new_design = design_decode(bg, new_pt, design, &order);
+        if new_design.iter().any(|o| !bg.is_fixed(o) && bg.is_forbidden(o.0, o.1)) {
+            return std::f64::INFINITY;
+        }
+        cost::measure(bg, adispatch, &new_design)
     }).unwrap();
     info!("optimize_locations: {} iterations", n);
     (cost, design_decode(bg, &best_pt, design, &order))
 }
 
+const MIN_DIST :f64 = 21.9;
+
+/// Objects sorted by track and position.
+fn sorted<'a>(design :&'a Design, order :&Permutation) -> Vec<&'a Object> {
+    (0..(design.len())).map(|i| &design[order.apply_inv_idx(i)]).collect()
+}
+
+/// Position of the next fixed object on the track, or the track's end.
+fn next_fixed(bg :&SynthesisBackground, rest :&[&Object], tr :usize) -> f64 {
+    rest.iter().take_while(|o| o.0 == tr).find(|o| bg.is_fixed(o)).map(|o| o.1)
+        .unwrap_or(bg.topology.tracks[tr].0)
+}
+
+/// Each movable object is encoded as a value in [0,1] between the previous
+/// object on the track and the next fixed object (or the track's end).
+/// Fixed objects are not part of the parameter vector.
 fn design_encode(bg :&SynthesisBackground, design :&Design, order :&Permutation) 
     -> DVector<f64> {
 
-    let min_dist = 21.9;
-
-    DVector::from_iterator(design.len(), 
-       (0..(design.len())).map(|i| &design[order.apply_inv_idx(i)])
-        .group_by(|(tr,_,_,_)| tr).into_iter().flat_map(|(tr,group)| {
-           let track_length = bg.topology.tracks[*tr].0;
-           group.scan(0.0, move |prev, (_,pos,_,_)| {
-               Some(glm::clamp_scalar(
-                     linearstep(replace(prev, *pos) + min_dist, track_length - min_dist, *pos),
-                   0.0, 1.0))
-           })
-       })
-    )
+    let objs = sorted(design, order);
+    let mut params = Vec::new();
+    let mut prev :Option<(usize,f64)> = None;
+    for (i,(tr,pos,func,dir)) in objs.iter().enumerate() {
+        let prev_pos = prev.filter(|(t,_)| t == tr).map(|(_,p)| p).unwrap_or(0.0);
+        if !bg.is_fixed(&(*tr,*pos,*func,*dir)) {
+            let hi = next_fixed(bg, &objs[i+1..], *tr);
+            params.push(glm::clamp_scalar(linearstep(prev_pos + MIN_DIST, hi - MIN_DIST, *pos), 0.0, 1.0));
+        }
+        prev = Some((*tr,*pos));
+    }
+    DVector::from_vec(params)
 }
 
 fn design_decode(bg :&SynthesisBackground, pt: &DVector<f64>, design :&Design, order :&Permutation) -> Design {
 
-    let min_dist = 21.9;
-
-    let out = pt.iter().enumerate().map(|(i,ipos)| (&design[order.apply_inv_idx(i)], ipos))
-        .group_by(|((tr,_,_,_),_)| tr).into_iter().flat_map(|(tr,group)| {
-            let track_length = bg.topology.tracks[*tr].0;
-            // we have [0,1], and we want to map it to [last_pos,track_length]
-            group.scan(0.0, move |prev, ((_tr,_oldpos,func,dir),ipos)| {
-                let pos = glm::lerp_scalar(*prev + min_dist, track_length - min_dist, *ipos);
-                *prev = pos; Some((*tr,pos,*func,*dir))
-            })
-        }).collect::<Vec<_>>();
+    let objs = sorted(design, order);
+    let mut params = pt.iter();
+    let mut out = Vec::new();
+    let mut prev :Option<(usize,f64)> = None;
+    for (i,(tr,oldpos,func,dir)) in objs.iter().enumerate() {
+        let prev_pos = prev.filter(|(t,_)| t == tr).map(|(_,p)| p).unwrap_or(0.0);
+        let pos = if bg.is_fixed(&(*tr,*oldpos,*func,*dir)) { *oldpos } else {
+            // we have [0,1], and we want to map it to [last_pos,next_fixed]
+            let hi = next_fixed(bg, &objs[i+1..], *tr);
+            glm::lerp_scalar(prev_pos + MIN_DIST, hi - MIN_DIST, *params.next().unwrap())
+        };
+        prev = Some((*tr,pos));
+        out.push((*tr,pos,*func,*dir));
+    }
     out
 }
 
 fn linearstep(lo :f64, hi :f64, val :f64) -> f64 {
-    (val - lo)/(hi - lo)
+    if hi > lo { (val - lo)/(hi - lo) } else { 0.0 }
 }
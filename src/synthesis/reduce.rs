@@ -1,5 +1,5 @@
 use matches::matches;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::collections::BTreeSet;
 use rolling::input::staticinfrastructure as rolling_inf;
 use nalgebra_glm as glm;
@@ -11,20 +11,39 @@ use crate::document::dgraph;
 use crate::document::interlocking;
 use crate::document::plan;
 
-pub fn reduced_signal_sets<'a>(bg :&'a SynthesisBackground, design :Design) 
-    -> impl Iterator<Item = (Design, MultiPlan)> + 'a {
+/// Signal sets of the given maximal design, excluding the `explored` sets, each
+/// given by its main signal objects.
+pub fn reduced_signal_sets<'a>(bg :&'a SynthesisBackground, design :Design, explored :&[Vec<Object>]) 
+    -> Result<impl Iterator<Item = (Design, MultiPlan)> + 'a, String> {
         
     let (topo,dgraph,il) = create_model(bg, &design);
     let inf = plan::convert_inf(&il.routes.iter()
                                 .map(|i| i.route.clone()).enumerate().collect());
-    let plans = bg.plans.iter().map(|p| plan::convert_plan(&il, bg.vehicles, p))
-        .collect::<Result<Vec<_>,_>>().unwrap();
+    let plans = bg.plans.iter().map(|p| plan::convert_plan(&dgraph, &il, bg.vehicles, p))
+        .collect::<Result<Vec<_>,_>>().map_err(|e| e.message())?;
 
     //println!("create optmizer");
     let mut optimizer = planner::optimize::SignalOptimizer::new(inf, plans.into());
     //println!("create optmizer ok");
 
-    Iter { bg, topo, dgraph, il, optimizer }
+    // Fixed signals are kept in every signal set.
+    for (obj_idx, obj) in design.iter().enumerate() {
+        if bg.is_fixed(obj) && matches!(obj.2, Function::MainSignal { .. }) {
+            if let Some(id) = dgraph.object_ids.get_by_right(&glm::vec2(obj_idx as i32, 0)) {
+                optimizer.fix_signal(planner::input::SignalId::Signal(*id));
+            }
+        }
+    }
+
+    for signals in explored {
+        let ids = signals.iter().filter_map(|obj| design.iter().position(|o| o == obj))
+            .filter_map(|obj_idx| dgraph.object_ids.get_by_right(&glm::vec2(obj_idx as i32, 0)))
+            .map(|id| planner::input::SignalId::Signal(*id))
+            .collect::<HashSet<_>>();
+        optimizer.exclude_signal_set(&ids);
+    }
+
+    Ok(Iter { bg, topo, dgraph, il, optimizer })
 }
 
 pub struct Iter<'a> {
@@ -42,13 +61,14 @@ impl<'a> Iterator for Iter<'a> {
         let topo = &self.topo;
         let dgraph = &self.dgraph;
         let il = &self.il;
+        let bg = self.bg;
         opt.next_signal_set().map(|mut s| {
             let dispatches = s.get_dispatches();
             let detectors = s.reduce_detectors(&dispatches);
             let signals = s.get_signals();
             println!("SIGNALS");
             println!("{:?}", signals);
-            let (design, id_map) = convert_signals(topo, dgraph, &signals, &detectors);
+            let (design, id_map) = convert_signals(bg, topo, dgraph, &signals, &detectors);
             let dispatches = dispatches.into_iter().enumerate()
                 .map(|(planspec_idx,routeplans)| routeplans.into_iter()
                      .map(|routeplan| abstract_dispatches(dgraph, il, &id_map, &routeplan))
@@ -59,7 +79,7 @@ impl<'a> Iterator for Iter<'a> {
 }
 
 
-fn convert_signals(topo :&Topology, dgraph :&dgraph::DGraph, 
+fn convert_signals(bg :&SynthesisBackground, topo :&Topology, dgraph :&dgraph::DGraph, 
                    signals :&HashSet<planner::input::SignalId>, 
                    detectors :&HashSet<planner::input::SignalId>) 
     -> (Design,HashMap<PtA,PtA>) {
@@ -85,7 +105,8 @@ fn convert_signals(topo :&Topology, dgraph :&dgraph::DGraph,
             //println!("convert {:?}", (pos,id,func,dir));
             match func {
                 Function::Detector => {
-                    if det_id.get(id).map(|d| detectors.contains(&planner::input::SignalId::Detector(*d)) ||
+                    if bg.is_fixed(&(track_idx, *pos, *func, *dir)) ||
+                        det_id.get(id).map(|d| detectors.contains(&planner::input::SignalId::Detector(*d)) ||
                                               detectors.contains(&planner::input::SignalId::Detector(*d + 1)))
                         .unwrap_or(false) {
 
@@ -72,6 +72,7 @@ pub trait VecMap<V> {
     fn vecmap_insert(&mut self, key :usize, value :V);
     fn vecmap_remove(&mut self, key :usize) -> bool;
     fn vecmap_get(&self, key :usize) -> Option<&V>;
+    fn vecmap_get_mut(&mut self, key :usize) -> Option<&mut V>;
 }
 
 impl<V> VecMap<V> for Vec<Option<V>> {
@@ -98,6 +99,13 @@ impl<V> VecMap<V> for Vec<Option<V>> {
         }
         None
     }
+
+    fn vecmap_get_mut(&mut self, key :usize) -> Option<&mut V> {
+        if let Some(Some(e)) = self.get_mut(key) {
+            return Some(e);
+        }
+        None
+    }
 }
 
 